@@ -0,0 +1,332 @@
+//! # Benchmarks
+//!
+//! Measures the parts of the search loop separately: generating keys, shuffling and
+//! fingerprinting them, rendering digests as hex and matching them. Every measurement runs on a
+//! single thread first and then on all of them, which shows how well each part scales.
+
+use anyhow::Error;
+use log::{info, warn};
+use rayon::ThreadPool;
+
+use std::hint::black_box;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use vanity_gpg::matcher::{Candidate, Matcher, PatternSet};
+use vanity_gpg::pgp_backends::{Digest, FingerprintBatch, HexImplementation, KeyVersion, PGPError};
+use vanity_gpg::{Backend, CipherSuite};
+
+use crate::stats::json_string;
+
+/// Cipher suites benchmarked by default, as named on the command line
+pub const SUITES: [&str; 7] = [
+    "Ed25519", "RSA2048", "RSA3072", "RSA4096", "NISTP256", "NISTP384", "NISTP521",
+];
+
+/// Digests per batch, the same as in the search loop
+const BATCH_SIZE: usize = 64;
+
+/// Operations between two looks at the clock
+const CHUNK: usize = 1024;
+
+/// Number of digests the matchers are benchmarked with
+const MATCHER_DIGESTS: u32 = 4096;
+
+/// Result of one measurement
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    /// What was measured, e.g. the backend and cipher suite
+    component: String,
+    /// Name of the measurement
+    name: String,
+    /// Unit of the rates
+    unit: &'static str,
+    /// Rate on a single thread
+    single: f64,
+    /// Combined rate of all threads
+    scaled: f64,
+}
+
+/// Runs and collects measurements
+pub struct Bench<'a> {
+    pool: &'a ThreadPool,
+    jobs: usize,
+    duration: Duration,
+    measurements: Vec<Measurement>,
+}
+
+/// Move on to a new key once the creation times of `backend` are used up, like the search does
+///
+/// The time it takes is added to `excluded`.
+fn replace_key<B, G>(backend: &mut B, generate: &G, excluded: &mut Duration) -> Result<(), Error>
+where
+    B: Backend,
+    G: Fn() -> Result<B, Error>,
+{
+    let start = Instant::now();
+    if !backend.renew().unwrap_or(false) {
+        *backend = generate()?;
+    }
+    *excluded += start.elapsed();
+    Ok(())
+}
+
+impl<'a> Bench<'a> {
+    /// Measure for `duration` each on one and on `jobs` threads of `pool`
+    pub fn new(pool: &'a ThreadPool, jobs: usize, duration: Duration) -> Self {
+        Self {
+            pool,
+            jobs,
+            duration,
+            measurements: Vec::new(),
+        }
+    }
+
+    /// Run `work` over and over on a state created by `init`, on one and on all threads
+    ///
+    /// `work` returns the number of operations it did, and adds the time it spent on anything
+    /// that shouldn't count towards the rate, like replacing a used up key, to its second argument.
+    fn measure<S, I, W>(
+        &mut self,
+        component: &str,
+        name: &str,
+        unit: &'static str,
+        init: I,
+        work: W,
+    ) -> Result<(), Error>
+    where
+        I: Fn() -> Result<S, Error> + Sync,
+        W: Fn(&mut S, &mut Duration) -> Result<usize, Error> + Sync,
+    {
+        info!("Benchmarking {} {}", component, name);
+        let single = self.rate(1, &init, &work)?;
+        let scaled = match self.jobs {
+            1 => single,
+            jobs => self.rate(jobs, &init, &work)?,
+        };
+        self.measurements.push(Measurement {
+            component: component.to_string(),
+            name: name.to_string(),
+            unit,
+            single,
+            scaled,
+        });
+        Ok(())
+    }
+
+    /// Combined rate of `threads` threads running `work`
+    fn rate<S, I, W>(&self, threads: usize, init: &I, work: &W) -> Result<f64, Error>
+    where
+        I: Fn() -> Result<S, Error> + Sync,
+        W: Fn(&mut S, &mut Duration) -> Result<usize, Error> + Sync,
+    {
+        let rates = Mutex::new(Vec::with_capacity(threads));
+        self.pool.scope(|scope| {
+            for _ in 0..threads {
+                scope.spawn(|_| {
+                    let rate = init().and_then(|mut state| {
+                        let start = Instant::now();
+                        let mut excluded = Duration::ZERO;
+                        let mut count: usize = 0;
+                        while start.elapsed().saturating_sub(excluded) < self.duration {
+                            count += work(&mut state, &mut excluded)?;
+                        }
+                        let elapsed = start.elapsed().saturating_sub(excluded);
+                        Ok(count as f64 / elapsed.as_secs_f64())
+                    });
+                    rates.lock().unwrap().push(rate);
+                });
+            }
+        });
+        rates.into_inner().unwrap().into_iter().sum()
+    }
+
+    /// Measure key generation and fingerprinting of a backend for every cipher suite
+    ///
+    /// Cipher suites the backend doesn't support are skipped.
+    pub fn backend<B, F>(&mut self, backend: &str, suites: &[String], new: F) -> Result<(), Error>
+    where
+        B: Backend,
+        F: Fn(CipherSuite) -> Result<B, PGPError> + Sync,
+    {
+        for suite in suites {
+            let cipher_suite = CipherSuite::from_str(suite)?;
+            if let Err(error) = new(cipher_suite.clone()) {
+                warn!("Skipping {} with {}: {}", suite, backend, error);
+                continue;
+            }
+            let component = format!("{} {}", backend, suite);
+            let generate = || Ok(new(cipher_suite.clone())?);
+            self.measure(
+                &component,
+                "keygen",
+                "key/s",
+                || Ok(()),
+                |_, _| {
+                    black_box(generate()?);
+                    Ok(1)
+                },
+            )?;
+            self.measure(
+                &component,
+                "shuffle",
+                "hash/s",
+                generate,
+                |backend, excluded| {
+                    for _ in 0..CHUNK {
+                        if backend.shuffle().is_err() {
+                            replace_key(backend, &generate, excluded)?;
+                        }
+                        black_box(backend.fingerprint_digest());
+                    }
+                    Ok(CHUNK)
+                },
+            )?;
+            self.measure(
+                &component,
+                "batch",
+                "hash/s",
+                || Ok((generate()?, [Digest::default(); BATCH_SIZE])),
+                |(backend, digests), excluded| {
+                    let mut count = 0;
+                    while count < CHUNK {
+                        let written = backend.fingerprints(digests);
+                        black_box(&digests);
+                        count += written;
+                        if written == 0 || backend.advance(written).is_err() {
+                            replace_key(backend, &generate, excluded)?;
+                        }
+                    }
+                    Ok(count)
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Measure every hex conversion supported by the CPU on fingerprints of `version`
+    pub fn hex(&mut self, version: KeyVersion) -> Result<(), Error> {
+        let component = match version {
+            KeyVersion::V4 => "sha1_to_hex",
+            KeyVersion::V5 | KeyVersion::V6 => "sha256_to_hex",
+        };
+        for implementation in HexImplementation::available() {
+            self.measure(
+                component,
+                implementation.name(),
+                "hex/s",
+                || Ok(vec![0u8; version.digest_bytes()]),
+                |digest, _| {
+                    for index in 0..CHUNK {
+                        digest[0] = index as u8;
+                        black_box(implementation.to_hex(black_box(digest)));
+                    }
+                    Ok(CHUNK)
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Measure every pattern and the whole set on pseudo-random fingerprints of `version`
+    pub fn matchers(&mut self, patterns: &PatternSet, version: KeyVersion) -> Result<(), Error> {
+        let values: Vec<u32> = (0..MATCHER_DIGESTS).collect();
+        let mut digests = vec![Digest::default(); values.len()];
+        FingerprintBatch::new(version, &[0u8; 16], 12).digests(&values, &mut digests);
+        let digests = &digests;
+        let mut run = |name: &str, matcher: &(dyn Matcher + Sync)| {
+            self.measure(
+                "matcher",
+                name,
+                "hash/s",
+                || Ok(0),
+                |position: &mut usize, _| {
+                    for _ in 0..CHUNK {
+                        black_box(matcher.accepts(&Candidate::new(&digests[*position])));
+                        *position = (*position + 1) % digests.len();
+                    }
+                    Ok(CHUNK)
+                },
+            )
+        };
+        for pattern in patterns.patterns() {
+            run(pattern.name(), pattern.matcher())?;
+        }
+        if patterns.len() > 1 {
+            run("(all)", patterns)?;
+        }
+        Ok(())
+    }
+
+    /// Render the measurements as a table
+    pub fn to_table(&self) -> String {
+        let mut lines = vec![format!(
+            "{:<20} {:<12} {:>14} {:>14} {:>14} {:>8}  {}",
+            "component",
+            "measurement",
+            "1 thread",
+            format!("{} threads", self.jobs),
+            "per thread",
+            "scaling",
+            "unit"
+        )];
+        for measurement in &self.measurements {
+            lines.push(format!(
+                "{:<20} {:<12} {:>14.2} {:>14.2} {:>14.2} {:>7.0}%  {}",
+                measurement.component,
+                measurement.name,
+                measurement.single,
+                measurement.scaled,
+                measurement.per_thread(self.jobs),
+                measurement.scaling(self.jobs) * 100.0,
+                measurement.unit
+            ));
+        }
+        lines.join("\n")
+    }
+
+    /// Render the measurements as a JSON object
+    pub fn to_json(&self) -> String {
+        let measurements = self
+            .measurements
+            .iter()
+            .map(|measurement| {
+                format!(
+                    "{{\"component\": {}, \"name\": {}, \"unit\": {}, \"single\": {:.2}, \
+                     \"scaled\": {:.2}, \"per_thread\": {:.2}, \"scaling\": {:.3}}}",
+                    json_string(&measurement.component),
+                    json_string(&measurement.name),
+                    json_string(measurement.unit),
+                    measurement.single,
+                    measurement.scaled,
+                    measurement.per_thread(self.jobs),
+                    measurement.scaling(self.jobs)
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(", ");
+        format!(
+            "{{\"threads\": {}, \"seconds\": {:.3}, \"measurements\": [{}]}}",
+            self.jobs,
+            self.duration.as_secs_f64(),
+            measurements
+        )
+    }
+}
+
+impl Measurement {
+    /// Rate of each of the `jobs` threads when all of them run
+    fn per_thread(&self, jobs: usize) -> f64 {
+        self.scaled / jobs as f64
+    }
+
+    /// How close `jobs` threads come to `jobs` times the single thread rate
+    fn scaling(&self, jobs: usize) -> f64 {
+        if self.single > 0.0 {
+            self.per_thread(jobs) / self.single
+        } else {
+            0.0
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Background key generation
+//!
+//! Generating an RSA key takes up to seconds, and a search thread tests nothing meanwhile. A
+//! `KeyPool` keeps a bounded queue of fresh keys filled by background threads, and search threads
+//! only take keys from it (`KeyPool::generator` plugs into `Search::new`). Every time a search
+//! thread finds the queue empty and has to wait, the pool counts it as starved.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::pgp_backends::PGPError;
+
+/// Counters of a `KeyPool`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStatistics {
+    /// Keys ready in the queue
+    pub queued: usize,
+    /// Keys generated by the background threads, including failed attempts
+    pub generated: u64,
+    /// Keys taken from the queue
+    pub taken: u64,
+    /// Number of times the queue was empty when a key was taken
+    pub starved: u64,
+    /// Total time spent waiting for the queue
+    pub waited: Duration,
+}
+
+/// Queue and counters, behind the lock
+#[derive(Debug)]
+struct State<B> {
+    keys: VecDeque<Result<B, PGPError>>,
+    /// Keys being generated right now
+    pending: usize,
+    stopped: bool,
+    statistics: PoolStatistics,
+}
+
+/// Everything shared with the background threads
+#[derive(Debug)]
+struct Shared<B> {
+    state: Mutex<State<B>>,
+    /// Signaled when a key was queued
+    filled: Condvar,
+    /// Signaled when a key was taken or the pool stopped
+    drained: Condvar,
+    capacity: usize,
+}
+
+/// Bounded queue of keys generated in the background
+///
+/// The background threads stop once the pool is dropped.
+#[derive(Debug)]
+pub struct KeyPool<B> {
+    shared: Arc<Shared<B>>,
+}
+
+impl<B> Shared<B> {
+    fn lock(&self) -> MutexGuard<'_, State<B>> {
+        self.state.lock().unwrap()
+    }
+
+    /// Keep the queue full until the pool stops
+    fn fill<G: Fn() -> Result<B, PGPError>>(&self, generate: &G) {
+        loop {
+            {
+                let mut state = self
+                    .drained
+                    .wait_while(self.lock(), |state| {
+                        !state.stopped && state.keys.len() + state.pending >= self.capacity
+                    })
+                    .unwrap();
+                if state.stopped {
+                    return;
+                }
+                state.pending += 1;
+            }
+            let key = generate();
+            let mut state = self.lock();
+            state.pending -= 1;
+            if state.stopped {
+                return;
+            }
+            state.statistics.generated += 1;
+            state.keys.push_back(key);
+            self.filled.notify_one();
+        }
+    }
+}
+
+impl<B: Send + 'static> KeyPool<B> {
+    /// Start `threads` background threads that keep up to `capacity` keys ready
+    pub fn new<G>(generate: G, threads: usize, capacity: usize) -> Self
+    where
+        G: Fn() -> Result<B, PGPError> + Send + Sync + 'static,
+    {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State {
+                keys: VecDeque::with_capacity(capacity),
+                pending: 0,
+                stopped: false,
+                statistics: PoolStatistics::default(),
+            }),
+            filled: Condvar::new(),
+            drained: Condvar::new(),
+            capacity: capacity.max(1),
+        });
+        let generate = Arc::new(generate);
+        for _ in 0..threads.max(1) {
+            let shared = Arc::clone(&shared);
+            let generate = Arc::clone(&generate);
+            thread::spawn(move || shared.fill(&*generate));
+        }
+        Self { shared }
+    }
+}
+
+impl<B> KeyPool<B> {
+    /// Take the oldest key, waiting for one if the queue is empty
+    pub fn take(&self) -> Result<B, PGPError> {
+        let mut state = self.shared.lock();
+        if state.keys.is_empty() {
+            state.statistics.starved += 1;
+            let start = Instant::now();
+            state = self
+                .shared
+                .filled
+                .wait_while(state, |state| state.keys.is_empty())
+                .unwrap();
+            state.statistics.waited += start.elapsed();
+        }
+        let key = state.keys.pop_front().ok_or(PGPError::MysteriousError)?;
+        state.statistics.taken += 1;
+        self.shared.drained.notify_one();
+        key
+    }
+
+    /// A key generator for `Search::new` taking keys from the pool
+    pub fn generator(self: &Arc<Self>) -> impl FnMut() -> Result<B, PGPError> {
+        let pool = Arc::clone(self);
+        move || pool.take()
+    }
+
+    /// Get the current counters
+    pub fn statistics(&self) -> PoolStatistics {
+        let state = self.shared.lock();
+        PoolStatistics {
+            queued: state.keys.len(),
+            ..state.statistics
+        }
+    }
+}
+
+impl<B> Drop for KeyPool<B> {
+    fn drop(&mut self) {
+        self.shared.lock().stopped = true;
+        self.shared.drained.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod key_pool_test {
+    use super::KeyPool;
+    use crate::pgp_backends::PGPError;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Wait until the background threads are idle
+    fn settle<B: Send + 'static>(pool: &KeyPool<B>, queued: usize) {
+        for _ in 0..500 {
+            if pool.statistics().queued == queued {
+                break;
+            }
+            thread::sleep(Duration::from_millis(2));
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+
+    #[test]
+    fn bounded() {
+        let count = Arc::new(AtomicU32::new(0));
+        let counted = Arc::clone(&count);
+        let pool = KeyPool::new(move || Ok(counted.fetch_add(1, Ordering::SeqCst)), 3, 4);
+        settle(&pool, 4);
+        let statistics = pool.statistics();
+        assert_eq!(statistics.queued, 4);
+        assert_eq!(statistics.generated, 4);
+        assert_eq!(count.load(Ordering::SeqCst), 4);
+
+        let mut keys: Vec<u32> = (0..10).map(|_| pool.take().unwrap()).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        assert_eq!(keys.len(), 10);
+        settle(&pool, 4);
+        let statistics = pool.statistics();
+        assert_eq!(statistics.taken, 10);
+        assert_eq!(statistics.generated, 14);
+    }
+
+    #[test]
+    fn starved() {
+        let pool = Arc::new(KeyPool::new(
+            || {
+                thread::sleep(Duration::from_millis(30));
+                Ok(())
+            },
+            1,
+            1,
+        ));
+        let mut generate = pool.generator();
+        generate().unwrap();
+        generate().unwrap();
+        let statistics = pool.statistics();
+        assert_eq!(statistics.taken, 2);
+        assert!(statistics.starved >= 1);
+        assert!(statistics.waited >= Duration::from_millis(10));
+
+        settle(&pool, 1);
+        generate().unwrap();
+        assert_eq!(pool.statistics().starved, statistics.starved);
+    }
+
+    #[test]
+    fn failures() {
+        let pool: KeyPool<()> = KeyPool::new(|| Err(PGPError::KeyGenerationFailed), 1, 2);
+        assert!(matches!(pool.take(), Err(PGPError::KeyGenerationFailed)));
+    }
+}
@@ -0,0 +1,380 @@
+//! Keys shared between search threads
+//!
+//! By default every search thread generates a key of its own and walks its whole creation time
+//! window. A `KeyShare` instead splits the window of one key into disjoint slices, one for each
+//! search thread, and only generates the next key once every slice was handed out. An RSA key then
+//! costs one key generation for the whole machine instead of one per thread.
+//!
+//! The slices of a key are `SharedKey`s. Once one of them matched, it's retired together with the
+//! others. Slices that matched at the same time race to retire the key, only the winner keeps its
+//! result, so that no two results share the same key material.
+//!
+//! Renewing a key is deterministic, so slices of the same key that were renewed equally often
+//! carry the same key material again, and share its identifier and retirement.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use crate::pgp_backends::{ArmoredKey, Backend, Digest, PGPError, UniversalError, UserID};
+
+/// Identifier of the next key material
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A key, or a slice of a key shared with other threads
+#[derive(Debug, Clone)]
+pub struct SharedKey<B> {
+    key: B,
+    /// Key materials of the generated key and the keys renewed from it
+    lineage: Arc<Lineage>,
+    /// Number of times the key was renewed
+    renewals: usize,
+    material: Arc<Material>,
+}
+
+/// One key material, shared by every slice carrying it
+#[derive(Debug)]
+struct Material {
+    id: u64,
+    /// Set once a slice of the key matched
+    retired: AtomicBool,
+}
+
+/// Key materials of a generated key, by number of renewals
+#[derive(Debug, Default)]
+struct Lineage {
+    materials: Mutex<Vec<Arc<Material>>>,
+}
+
+/// Slices of the latest key and the generator of the next one
+struct State<B> {
+    slices: Vec<SharedKey<B>>,
+    generate: Box<dyn FnMut() -> Result<B, PGPError> + Send>,
+    generated: u64,
+}
+
+/// Hands out slices of keys to search threads
+pub struct KeyShare<B> {
+    state: Mutex<State<B>>,
+    parts: usize,
+}
+
+impl Lineage {
+    /// Get the key material after `renewals` renewals
+    fn material(&self, renewals: usize) -> Arc<Material> {
+        let mut materials = self.materials.lock().unwrap();
+        while materials.len() <= renewals {
+            materials.push(Arc::new(Material {
+                id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                retired: AtomicBool::new(false),
+            }));
+        }
+        Arc::clone(&materials[renewals])
+    }
+}
+
+impl<B> SharedKey<B> {
+    /// Wrap a key that isn't shared with anyone
+    pub fn new(key: B) -> Self {
+        Self::with_lineage(key, Arc::default())
+    }
+
+    /// Wrap a freshly generated key, or a slice of it
+    fn with_lineage(key: B, lineage: Arc<Lineage>) -> Self {
+        Self {
+            key,
+            material: lineage.material(0),
+            lineage,
+            renewals: 0,
+        }
+    }
+
+    /// Get the identifier of the key material, slices carrying the same material share it
+    pub fn id(&self) -> u64 {
+        self.material.id
+    }
+
+    /// Stop searching this key in every thread, after one of its slices matched
+    ///
+    /// Returns `false` if another slice retired the key first, its match has to be dropped then.
+    pub fn retire(&self) -> bool {
+        !self.material.retired.swap(true, Ordering::AcqRel)
+    }
+
+    /// Check whether a slice of the key matched
+    pub fn is_retired(&self) -> bool {
+        self.material.retired.load(Ordering::Relaxed)
+    }
+
+    /// Unwrap the key
+    pub fn into_inner(self) -> B {
+        self.key
+    }
+}
+
+/// A retired key has no fingerprints left and can't be renewed
+impl<B: Backend> Backend for SharedKey<B> {
+    fn fingerprint_digest(&self) -> Digest {
+        self.key.fingerprint_digest()
+    }
+
+    fn fingerprints(&mut self, out: &mut [Digest]) -> usize {
+        if self.is_retired() {
+            return 0;
+        }
+        self.key.fingerprints(out)
+    }
+
+    fn shuffle(&mut self) -> Result<(), PGPError> {
+        self.key.shuffle()
+    }
+
+    fn advance(&mut self, count: usize) -> Result<(), PGPError> {
+        self.key.advance(count)
+    }
+
+    fn renew(&mut self) -> Result<bool, PGPError> {
+        if self.is_retired() {
+            return Ok(false);
+        }
+        if !self.key.renew()? {
+            return Ok(false);
+        }
+        // Slices renewed as often as this one carry the same key material
+        self.renewals += 1;
+        self.material = self.lineage.material(self.renewals);
+        Ok(true)
+    }
+
+    fn slice(&mut self, index: usize, count: usize) -> Result<bool, PGPError> {
+        self.key.slice(index, count)
+    }
+
+    fn get_armored_results(self, uid: &UserID) -> Result<ArmoredKey, UniversalError> {
+        self.key.get_armored_results(uid)
+    }
+}
+
+impl<B: Backend + Clone> KeyShare<B> {
+    /// Split every key made by `generate` into `parts` slices
+    pub fn new<G>(generate: G, parts: usize) -> Self
+    where
+        G: FnMut() -> Result<B, PGPError> + Send + 'static,
+    {
+        Self {
+            state: Mutex::new(State {
+                slices: Vec::new(),
+                generate: Box::new(generate),
+                generated: 0,
+            }),
+            parts: parts.max(1),
+        }
+    }
+
+    /// Take the next slice, generating a new key once every slice of the latest one was taken
+    ///
+    /// Slices of retired keys are skipped. Keys the backend can't slice are handed out whole.
+    pub fn take(&self) -> Result<SharedKey<B>, PGPError> {
+        let mut state = self.state.lock().unwrap();
+        while let Some(slice) = state.slices.pop() {
+            if !slice.is_retired() {
+                return Ok(slice);
+            }
+        }
+        let key = (state.generate)()?;
+        state.generated += 1;
+        let lineage = Arc::<Lineage>::default();
+        let mut slices = Vec::with_capacity(self.parts);
+        for index in 0..self.parts {
+            let mut slice = key.clone();
+            match slice.slice(index, self.parts) {
+                Ok(true) => slices.push(SharedKey::with_lineage(slice, Arc::clone(&lineage))),
+                Ok(false) => return Ok(SharedKey::with_lineage(key, lineage)),
+                // Windows smaller than the number of parts leave some slices empty
+                Err(PGPError::EmptyCreationWindow) => {}
+                Err(error) => return Err(error),
+            }
+        }
+        // Hand out the first slice first
+        slices.reverse();
+        let first = slices.pop().ok_or(PGPError::EmptyCreationWindow)?;
+        state.slices = slices;
+        Ok(first)
+    }
+
+    /// A key generator for `Search::new` taking slices from the share
+    pub fn generator(self: &Arc<Self>) -> impl FnMut() -> Result<SharedKey<B>, PGPError> {
+        let share = Arc::clone(self);
+        move || share.take()
+    }
+
+    /// Get the number of keys generated so far
+    pub fn generated(&self) -> u64 {
+        self.state.lock().unwrap().generated
+    }
+}
+
+#[cfg(test)]
+mod key_share_test {
+    use super::{KeyShare, SharedKey};
+    use crate::pgp_backends::{ArmoredKey, Backend, Digest, PGPError, UniversalError, UserID};
+    use std::sync::Arc;
+
+    /// Backend walking the timestamps `start..end` upwards, its digest is its key number and
+    /// its timestamp
+    #[derive(Debug, Clone)]
+    struct MockBackend {
+        number: u8,
+        timestamp: u32,
+        end: u32,
+        sliceable: bool,
+    }
+
+    impl Backend for MockBackend {
+        fn fingerprint_digest(&self) -> Digest {
+            let mut digest = [0u8; 20];
+            digest[0] = self.number;
+            digest[16..].copy_from_slice(&self.timestamp.to_be_bytes());
+            Digest::from(digest)
+        }
+
+        fn shuffle(&mut self) -> Result<(), PGPError> {
+            if self.timestamp + 1 >= self.end {
+                return Err(PGPError::CreationWindowExhausted);
+            }
+            self.timestamp += 1;
+            Ok(())
+        }
+
+        fn renew(&mut self) -> Result<bool, PGPError> {
+            self.number += 100;
+            Ok(true)
+        }
+
+        fn slice(&mut self, index: usize, count: usize) -> Result<bool, PGPError> {
+            if !self.sliceable {
+                return Ok(false);
+            }
+            let size = self.end - self.timestamp;
+            let bound = |index: usize| self.timestamp + size * index as u32 / count as u32;
+            let (start, end) = (bound(index), bound(index + 1));
+            if start == end {
+                return Err(PGPError::EmptyCreationWindow);
+            }
+            self.timestamp = start;
+            self.end = end;
+            Ok(true)
+        }
+
+        fn get_armored_results(self, _uid: &UserID) -> Result<ArmoredKey, UniversalError> {
+            unimplemented!()
+        }
+    }
+
+    /// Generator of numbered keys with the timestamps `0..size`
+    fn generator(size: u32, sliceable: bool) -> impl FnMut() -> Result<MockBackend, PGPError> {
+        let mut number = 0;
+        move || {
+            number += 1;
+            Ok(MockBackend {
+                number,
+                timestamp: 0,
+                end: size,
+                sliceable,
+            })
+        }
+    }
+
+    /// Every timestamp of a slice
+    fn walk(mut slice: SharedKey<MockBackend>) -> Vec<u32> {
+        let mut timestamps = vec![slice.key.timestamp];
+        while slice.shuffle().is_ok() {
+            timestamps.push(slice.key.timestamp);
+        }
+        timestamps
+    }
+
+    #[test]
+    fn slices() {
+        let share = Arc::new(KeyShare::new(generator(100, true), 3));
+        let mut take = share.generator();
+        let mut timestamps = Vec::new();
+        for _ in 0..3 {
+            let slice = take().unwrap();
+            assert_eq!(slice.key.number, 1);
+            timestamps.extend(walk(slice));
+        }
+        assert_eq!(timestamps, (0..100).collect::<Vec<u32>>());
+        assert_eq!(share.generated(), 1);
+
+        // The next key once every slice was taken
+        assert_eq!(take().unwrap().key.number, 2);
+        assert_eq!(share.generated(), 2);
+    }
+
+    #[test]
+    fn retired() {
+        let share = KeyShare::new(generator(100, true), 4);
+        let first = share.take().unwrap();
+        let mut second = share.take().unwrap();
+        assert_eq!(first.id(), second.id());
+        assert!(first.retire());
+        assert!(second.is_retired());
+        assert!(!second.retire());
+        let mut digests = [Digest::default(); 8];
+        assert_eq!(second.fingerprints(&mut digests), 0);
+        assert!(!second.renew().unwrap());
+        assert!(first.is_retired());
+
+        // The slices left of a retired key are skipped
+        let mut next = share.take().unwrap();
+        assert_eq!(next.key.number, 2);
+        assert!(!next.is_retired());
+        assert_ne!(next.id(), first.id());
+
+        // A renewed slice no longer carries the key material of its siblings
+        let sibling = share.take().unwrap();
+        assert!(next.renew().unwrap());
+        assert_ne!(next.id(), sibling.id());
+        assert!(sibling.retire());
+        assert!(!next.is_retired());
+        assert_eq!(share.generated(), 2);
+        assert_eq!(
+            SharedKey::new(next.into_inner()).fingerprints(&mut digests),
+            1
+        );
+    }
+
+    #[test]
+    fn renewed_siblings() {
+        let share = KeyShare::new(generator(100, true), 3);
+        let mut first = share.take().unwrap();
+        let mut second = share.take().unwrap();
+        let third = share.take().unwrap();
+        assert!(first.renew().unwrap());
+        assert!(second.renew().unwrap());
+        assert_eq!(first.key.number, second.key.number);
+        assert_eq!(first.id(), second.id());
+        assert_ne!(first.id(), third.id());
+
+        // Retiring the renewed material stops the siblings carrying it, not the others
+        assert!(second.retire());
+        assert!(first.is_retired());
+        assert!(!first.retire());
+        assert!(!third.is_retired());
+        assert!(!first.renew().unwrap());
+        assert_ne!(second.key.number, third.key.number);
+    }
+
+    #[test]
+    fn small_and_unsliceable() {
+        // Two timestamps for three threads
+        let share = KeyShare::new(generator(2, true), 3);
+        assert_eq!(walk(share.take().unwrap()), [0]);
+        assert_eq!(walk(share.take().unwrap()), [1]);
+        assert_eq!(share.take().unwrap().key.number, 2);
+
+        let share = KeyShare::new(generator(10, false), 3);
+        assert_eq!(walk(share.take().unwrap()).len(), 10);
+        assert_eq!(share.take().unwrap().key.number, 2);
+    }
+}
@@ -8,6 +8,24 @@
 //! let backend = DefaultBackend::new(CipherSuite::Curve25519).unwrap();
 //! println!("Fingerprint: {}", backend.fingerprint());
 //! ```
+//!
+//! Searching with custom acceptance logic:
+//!
+//! ```rust,no_run
+//! use vanity_gpg::matcher::{from_fn, Candidate, Matcher};
+//! use vanity_gpg::search::{Search, Step};
+//! use vanity_gpg::{CipherSuite, DefaultBackend};
+//!
+//! let matcher = from_fn(|candidate: &Candidate| candidate.digest()[19] == 0)
+//!     .and(from_fn(|candidate: &Candidate| candidate.hex().contains("CAFE")));
+//! let mut search = Search::new(matcher, || DefaultBackend::new(CipherSuite::Curve25519)).unwrap();
+//! loop {
+//!     if let Step::Found { digest, .. } = search.run(100000, |_, _, _| {}).unwrap() {
+//!         println!("Found: {:?}", digest);
+//!         break;
+//!     }
+//! }
+//! ```
 
 extern crate anyhow;
 extern crate byteorder;
@@ -17,6 +35,8 @@ extern crate chrono;
 extern crate pgp;
 #[cfg(feature = "rpgp")]
 extern crate rand;
+extern crate regex;
+extern crate regex_syntax;
 #[cfg(feature = "sequoia")]
 extern crate sequoia_openpgp;
 #[cfg(feature = "rpgp")]
@@ -25,12 +45,19 @@ extern crate sha1;
 extern crate smallvec;
 extern crate thiserror;
 
+pub mod key_pool;
+pub mod key_share;
+pub mod matcher;
 pub mod pgp_backends;
+pub mod search;
 #[cfg(feature = "rpgp")]
 pub use pgp_backends::RPGPBackend;
 #[cfg(feature = "sequoia")]
 pub use pgp_backends::SequoiaBackend;
-pub use pgp_backends::{ArmoredKey, Backend, CipherSuite, DefaultBackend, UserID};
+pub use pgp_backends::{
+    digest_to_hex, sha1_to_hex, ArmoredKey, Backend, CipherSuite, DefaultBackend, KeyVersion,
+    UserID, Variation,
+};
 
 #[cfg(test)]
 mod meaningless_test {
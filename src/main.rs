@@ -7,36 +7,54 @@ extern crate backtrace;
 extern crate clap;
 extern crate colored;
 extern crate indicatif;
+#[cfg(target_os = "linux")]
+extern crate libc;
 extern crate log;
 extern crate mimalloc;
 extern crate rayon;
-extern crate regex;
 
 extern crate vanity_gpg;
 
+mod bench;
 mod logger;
+mod stats;
+mod topology;
 
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 use backtrace::Backtrace;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use log::{debug, info, warn, Level};
-use rayon::ThreadPoolBuilder;
-use regex::Regex;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 
 use std::env;
 use std::fmt;
-use std::fs::File;
+use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
 use std::panic;
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-use vanity_gpg::{Backend, CipherSuite, DefaultBackend, UserID};
+use vanity_gpg::key_pool::KeyPool;
+use vanity_gpg::key_share::{KeyShare, SharedKey};
+use vanity_gpg::matcher::{
+    from_fn, Candidate, Leaderboard, LeetTable, MatchTarget, Matcher, NamedPattern, PatternMatcher,
+    PatternSet, Preset, Scorer, WordPosition, Wordlist,
+};
+use vanity_gpg::pgp_backends::{
+    parse_timestamp, unix_timestamp, Algorithms, CreationWindow, KeyOptions, KeyVersion, PGPError,
+    Walk,
+};
+use vanity_gpg::search::{ReshufflePolicy, Search, Step};
+use vanity_gpg::{Backend, CipherSuite, DefaultBackend, UserID, Variation};
 
+use bench::Bench;
 use logger::{IndicatifBackend, ProgressLogger, ProgressLoggerBackend};
+use stats::{json_string, Rates, Statistics, RATE_WINDOWS};
+use topology::{pin_current_thread, Topology};
 
 #[global_allocator]
 static ALLOC: mimalloc::MiMalloc = mimalloc::MiMalloc;
@@ -57,10 +75,21 @@ const PKG_DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
 /// Program repository (from `Cargo.toml`)
 const PKG_REPOSITORY: &str = env!("CARGO_PKG_REPOSITORY");
 
-/// Key reshuffle limit
+/// Key reshuffle limit, the most an adaptive limit goes up to
 const KEY_RESHUFFLE_LIMIT: usize = 60000000; // One month ago at worst
+/// Change of the reshuffle limit (as a factor) that is logged as info
+const RESHUFFLE_LIMIT_LOG_CHANGE: f64 = 1.25;
 /// Counter threshold
 const COUNTER_THRESHOLD: usize = 133331; // Just a random number
+/// Interval between two writes of the statistics file
+const STATS_FILE_INTERVAL: Duration = Duration::from_secs(1);
+/// Units for displaying long durations
+const DURATION_UNITS: [(&str, f64); 4] = [
+    ("y", 31557600.0),
+    ("d", 86400.0),
+    ("h", 3600.0),
+    ("m", 60.0),
+];
 
 /// Commandline option parser with `Clap`
 #[derive(Parser, Debug)]
@@ -70,17 +99,126 @@ struct Opts {
     #[clap(
         short = 'j',
         long = "jobs",
-        help = "Number of threads",
+        help = "Number of threads, one per usable CPU by default (see --physical-cores and --numa-node)"
+    )]
+    jobs: Option<usize>,
+    /// Pin workers to CPUs
+    #[clap(
+        long = "pin-threads",
+        help = "Pin each worker to its own CPU, spread over physical cores first (Linux only)"
+    )]
+    pin_threads: bool,
+    /// Use physical cores only
+    #[clap(
+        long = "physical-cores",
+        help = "Only run workers on the first logical CPU of every physical core (Linux only)"
+    )]
+    physical_cores: bool,
+    /// Background key generation threads
+    #[clap(
+        long = "keygen-threads",
+        help = "Generate keys on this many background threads instead of the workers, taken from the default -j",
+        default_value = "0"
+    )]
+    keygen_threads: usize,
+    /// Size of the key queue
+    #[clap(
+        long = "key-pool",
+        help = "Number of keys the background threads keep ready",
         default_value = "8"
     )]
-    jobs: usize,
-    /// Regex pattern for matching fingerprints
+    key_pool: usize,
+    /// Share every key between the workers
+    #[clap(
+        long = "share-keys",
+        help = "Split the creation times of every key between all workers instead of giving each worker a key of its own, the next key is only generated once the whole window was handed out"
+    )]
+    share_keys: bool,
+    /// Use one NUMA node only
+    #[clap(
+        long = "numa-node",
+        help = "Only run workers on the CPUs of this NUMA node (Linux only)"
+    )]
+    numa_node: Option<usize>,
+    /// Regex patterns for matching fingerprints
     #[clap(
         short = 'p',
         long = "pattern",
-        help = "Regex pattern for matching fingerprints"
+        help = "Regex pattern for matching fingerprints, can be named with NAME=PATTERN",
+        multiple_occurrences = true
+    )]
+    patterns: Vec<String>,
+    /// File with more patterns
+    #[clap(
+        short = 'f',
+        long = "pattern-file",
+        help = "File with one pattern (NAME=PATTERN or PATTERN) per line"
+    )]
+    pattern_file: Option<String>,
+    /// Patterns that reject a candidate
+    #[clap(
+        short = 'x',
+        long = "exclude",
+        help = "Reject fingerprints matching this pattern, even if they match -p",
+        multiple_occurrences = true
+    )]
+    excludes: Vec<String>,
+    /// File with more exclusion patterns
+    #[clap(
+        long = "exclude-file",
+        help = "File with one exclusion pattern (NAME=PATTERN or PATTERN) per line"
+    )]
+    exclude_file: Option<String>,
+    /// Built-in pattern presets
+    #[clap(
+        long = "preset",
+        help = "Built-in pattern preset as NAME[:PARAMETER] (e.g. repeat-tail:10), see `presets`",
+        multiple_occurrences = true
+    )]
+    presets: Vec<String>,
+    /// Wordlist file
+    #[clap(
+        short = 'w',
+        long = "wordlist",
+        help = "File with one word per line, matched with letters spelled as hex look-alikes"
     )]
-    pattern: String,
+    wordlist: Option<String>,
+    /// Minimum word length
+    #[clap(
+        long = "word-min-length",
+        help = "Minimum length of words from the wordlist",
+        default_value = "5"
+    )]
+    word_min_length: usize,
+    /// Word position
+    #[clap(
+        long = "word-position",
+        help = "Where words from the wordlist have to appear",
+        default_value = "anywhere",
+        possible_values = &[ "prefix", "suffix", "anywhere" ]
+    )]
+    word_position: String,
+    /// Leetspeak substitutions
+    #[clap(
+        long = "leet",
+        help = "Extra letter substitutions for the wordlist (e.g. \"r=2,g=6\", \"t=\" removes one)",
+        default_value = ""
+    )]
+    leet: String,
+    /// Rendering of the fingerprint to match on
+    #[clap(
+        long = "match-on",
+        help = "Rendering of the fingerprint that patterns are applied to, also used for logs",
+        default_value = "fingerprint",
+        possible_values = &[ "fingerprint", "keyid-long", "keyid-short", "grouped" ]
+    )]
+    match_on: String,
+    /// Name saved keys after the rendering
+    #[clap(
+        long = "name-after-target",
+        help = "Name saved keys after the --match-on rendering instead of the full fingerprint"
+    )]
+    name_after_target: bool,
     /// Cipher suite
     #[clap(
         short = 'c',
@@ -90,6 +228,42 @@ struct Opts {
         possible_values = &[ "Ed25519", "RSA2048", "RSA3072", "RSA4096", "NISTP256", "NISTP384", "NISTP521" ],
     )]
     cipher_suite: String,
+    /// Part of the key changed between fingerprints
+    #[clap(
+        long = "vary",
+        help = "Part of the key changed between fingerprints, the public exponent is only available for RSA keys",
+        default_value = "creation-time",
+        possible_values = &[ "creation-time", "rsa-exponent" ]
+    )]
+    vary: String,
+    /// OpenPGP key version
+    #[clap(
+        long = "key-version",
+        help = "OpenPGP key version, v5 (LibrePGP) and v6 (RFC 9580) keys have 64 hex digit SHA-256 fingerprints",
+        default_value = "v4",
+        possible_values = &[ "v4", "v5", "v6" ]
+    )]
+    key_version: String,
+    /// Oldest creation time
+    #[clap(
+        long = "not-before",
+        help = "Oldest creation time of the keys, as a Unix timestamp or a UTC date like 2024-03-01T12:00"
+    )]
+    not_before: Option<String>,
+    /// Newest creation time
+    #[clap(
+        long = "not-after",
+        help = "Newest creation time of the keys, never later than the time they are generated"
+    )]
+    not_after: Option<String>,
+    /// Order of the creation time walk
+    #[clap(
+        long = "walk",
+        help = "Walk the creation times backward from the newest, forward from the oldest, or in a random order without repeats",
+        default_value = "backward",
+        possible_values = &[ "backward", "forward", "random" ]
+    )]
+    walk: String,
     /// User ID
     #[clap(short = 'u', long = "user-id", help = "OpenPGP compatible user ID")]
     user_id: Option<String>,
@@ -107,17 +281,110 @@ struct Opts {
         parse(from_occurrences)
     )]
     verbose: u8,
+    /// Scorer for keeping the best fingerprints
+    #[clap(
+        short = 's',
+        long = "score",
+        help = "Keep the best scored keys, exported when the time limit is reached",
+        possible_values = &[ "run", "edge-run", "palindrome", "hexspeak" ],
+        requires = "time_limit"
+    )]
+    score: Option<String>,
+    /// Size of the leaderboard
+    #[clap(
+        long = "keep",
+        help = "Number of best scored keys to keep",
+        default_value = "10"
+    )]
+    keep: usize,
+    /// Statistics file
+    #[clap(
+        long = "stats-file",
+        help = "Keep writing the statistics as JSON to this file (every second and when stopping)"
+    )]
+    stats_file: Option<String>,
+    /// Reshuffle limit
+    #[clap(
+        long = "reshuffle-limit",
+        help = "Shuffles per key before generating a new one, \"auto\" picks it from the measured costs of the cipher suite",
+        default_value = "auto"
+    )]
+    reshuffle_limit: String,
+    /// Time limit
+    #[clap(
+        short = 't',
+        long = "time-limit",
+        help = "Stop after the given time (e.g. 90s, 30m, 6h, 2d)"
+    )]
+    time_limit: Option<String>,
+    /// Run something other than the search
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+/// Subcommands
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Estimate how hard the patterns are to match
+    Estimate {
+        /// Hash rate to estimate with
+        #[clap(
+            short = 'r',
+            long = "rate",
+            help = "Hash rate (hash/s) to estimate with, benchmarked if omitted"
+        )]
+        rate: Option<f64>,
+        /// Benchmark duration
+        #[clap(
+            long = "bench-seconds",
+            help = "Seconds to benchmark for if no rate is given",
+            default_value = "3"
+        )]
+        bench_seconds: u64,
+    },
+    /// List the built-in presets (or the ones given with --preset) and their difficulty
+    Presets,
+    /// Benchmark each part of the search separately, on one and on all threads
+    Bench {
+        /// Duration of each measurement
+        #[clap(
+            long = "seconds",
+            help = "Seconds to run each measurement for, once on one thread and once on all",
+            default_value = "1"
+        )]
+        seconds: f64,
+        /// Cipher suites to benchmark
+        #[clap(
+            long = "suite",
+            help = "Cipher suite to benchmark, all of them if omitted",
+            multiple_occurrences = true,
+            possible_values = &bench::SUITES
+        )]
+        suites: Vec<String>,
+        /// Output format
+        #[clap(
+            long = "format",
+            help = "Output format",
+            default_value = "table",
+            possible_values = &[ "table", "json" ]
+        )]
+        format: String,
+    },
 }
 
 /// Counter for statistics
 #[derive(Debug)]
 struct Counter {
-    total: AtomicUsize,
+    statistics: Statistics,
     success: AtomicUsize,
+    excluded: AtomicUsize,
+    pattern_names: Vec<String>,
+    pattern_success: Vec<AtomicUsize>,
+    key_pool: Option<Arc<KeyPool<DefaultBackend>>>,
 }
 
 /// Wrapper for the backends
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Key<B: Backend> {
     backend: B,
 }
@@ -128,6 +395,124 @@ fn save_file(file_name: String, content: &str) -> Result<(), Error> {
     Ok(file.write_all(content.as_bytes())?)
 }
 
+/// Save string to a new file, failing instead of overwriting an existing one
+fn save_new_file(file_name: String, content: &str) -> Result<(), Error> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(file_name)?;
+    Ok(file.write_all(content.as_bytes())?)
+}
+
+/// Parse a pattern definition, anonymous patterns are named after their position
+fn parse_pattern(
+    definition: &str,
+    index: usize,
+    target: MatchTarget,
+) -> Result<NamedPattern, Error> {
+    match definition.split_once('=') {
+        Some((name, pattern))
+            if !name.is_empty()
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') =>
+        {
+            Ok(NamedPattern::with_target(name, pattern, target)?)
+        }
+        _ => Ok(NamedPattern::with_target(
+            format!("p{}", index + 1),
+            definition,
+            target,
+        )?),
+    }
+}
+
+/// Parse a duration like `90s`, `30m`, `6h` or `2d` (seconds without unit)
+fn parse_duration(duration: &str) -> Result<Duration, Error> {
+    let duration = duration.trim();
+    let (number, unit) = match duration.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(index) => duration.split_at(index),
+        None => (duration, "s"),
+    };
+    let multiplier = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return Err(anyhow!("Unknown time unit in \"{}\"", duration)),
+    };
+    Ok(Duration::from_secs_f64(
+        number.trim().parse::<f64>()? * multiplier,
+    ))
+}
+
+/// Parse the reshuffle limit, a number of shuffles or `auto`
+fn parse_reshuffle_policy(limit: &str) -> Result<ReshufflePolicy, Error> {
+    match limit {
+        "auto" => Ok(ReshufflePolicy::adaptive(KEY_RESHUFFLE_LIMIT)),
+        limit => Ok(ReshufflePolicy::Fixed(limit.parse()?)),
+    }
+}
+
+/// Load the wordlist into a named pattern
+fn load_wordlist(opts: &Opts, target: MatchTarget) -> Result<Option<NamedPattern>, Error> {
+    let file_name = match &opts.wordlist {
+        Some(file_name) => file_name,
+        None => return Ok(None),
+    };
+    let wordlist = Wordlist::new(
+        fs::read_to_string(file_name)?
+            .lines()
+            .filter(|line| !line.trim().is_empty() && !line.starts_with('#')),
+        &LeetTable::parse(&opts.leet)?,
+        opts.word_min_length,
+        WordPosition::from_str(&opts.word_position)?,
+        target,
+    )?;
+    Ok(Some(NamedPattern::from_matcher(
+        "words",
+        format!(
+            "{} words from \"{}\" ({})",
+            wordlist.words().len(),
+            file_name,
+            opts.word_position
+        ),
+        PatternMatcher::Words(Box::new(wordlist)),
+    )))
+}
+
+/// Collect patterns from the commandline and the pattern file, plus presets and wordlists
+fn load_patterns(
+    patterns: &[String],
+    pattern_file: &Option<String>,
+    extra: Vec<NamedPattern>,
+    target: MatchTarget,
+    allow_empty: bool,
+) -> Result<PatternSet, Error> {
+    let mut definitions = patterns.to_vec();
+    if let Some(file_name) = pattern_file {
+        definitions.extend(
+            fs::read_to_string(file_name)?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from),
+        );
+    }
+    if definitions.is_empty() && extra.is_empty() && !allow_empty {
+        return Err(anyhow!(
+            "No pattern specified, use -p, --pattern-file, --preset or --wordlist"
+        ));
+    }
+    let mut named_patterns = definitions
+        .iter()
+        .enumerate()
+        .map(|(index, definition)| parse_pattern(definition, index, target))
+        .collect::<Result<Vec<NamedPattern>, Error>>()?;
+    named_patterns.extend(extra);
+    Ok(PatternSet::new(named_patterns)?)
+}
+
 /// Set panic hook with repository information
 fn setup_panic_hook() {
     panic::set_hook(Box::new(move |panic_info: &panic::PanicInfo| {
@@ -168,59 +553,362 @@ fn setup_logger<B: 'static + ProgressLoggerBackend>(
     Ok(cloned_backend)
 }
 
-/// Sub-thread that display status summary
-fn setup_summary<B: ProgressLoggerBackend>(logger_backend: Arc<Mutex<B>>, counter: Arc<Counter>) {
+/// Format seconds into a human readable duration
+fn format_duration(seconds: f64) -> String {
+    if !seconds.is_finite() {
+        return String::from("forever");
+    }
+    if seconds < DURATION_UNITS[DURATION_UNITS.len() - 1].1 {
+        return format!("{:.1}s", seconds);
+    }
+    if seconds >= DURATION_UNITS[0].1 * 1e6 {
+        return format!("{:.2e}y", seconds / DURATION_UNITS[0].1);
+    }
+    let mut remaining = seconds;
+    let mut parts: Vec<String> = Vec::new();
+    for (unit, length) in DURATION_UNITS.iter() {
+        if remaining >= *length || !parts.is_empty() {
+            let count = (remaining / length).floor();
+            remaining -= count * length;
+            parts.push(format!("{}{}", count, unit));
+        }
+        if parts.len() == 2 {
+            break;
+        }
+    }
+    parts.join(" ")
+}
+
+/// Measure the hash rate of the search loop with `jobs` threads
+fn benchmark_rate(
+    pool: &ThreadPool,
+    cipher_suite: &CipherSuite,
+    options: KeyOptions,
+    patterns: &PatternSet,
+    jobs: usize,
+    duration: Duration,
+) -> f64 {
+    let total = AtomicUsize::new(0);
+    pool.scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|_| {
+                let mut search = Search::new(patterns, || {
+                    DefaultBackend::with_options(cipher_suite.clone(), options)
+                })
+                .unwrap();
+                let start = Instant::now();
+                let mut count: usize = 0;
+                while start.elapsed() < duration {
+                    let step = search.run(1000, |_, _, _| {}).unwrap();
+                    if let Step::Found { .. } = step {
+                        debug!("Benchmark found a match, ignoring");
+                    }
+                    count += step.tested();
+                }
+                total.fetch_add(count, Ordering::SeqCst);
+            });
+        }
+    });
+    total.load(Ordering::SeqCst) as f64 / duration.as_secs_f64()
+}
+
+/// Log the reshuffle limit of a search and the costs behind it
+///
+/// Small changes are only logged at the debug level, `logged` is the limit last logged as info.
+fn log_reshuffle_limit<B: Backend, M: Matcher, G: FnMut() -> Result<B, PGPError>>(
+    thread_id: usize,
+    search: &Search<B, M, G>,
+    logged: &mut usize,
+) {
+    let limit = search.reshuffle_limit();
+    let costs = search.costs();
+    let message = format!(
+        "({}): Reshuffle limit {} (new key: {}, candidate: {})",
+        thread_id,
+        limit,
+        costs
+            .keygen
+            .map_or(String::from("-"), |cost| format!("{:.3}ms", cost * 1e3)),
+        costs
+            .candidate
+            .map_or(String::from("-"), |cost| format!("{:.1}ns", cost * 1e9)),
+    );
+    let change = limit.max(*logged) as f64 / limit.min(*logged).max(1) as f64;
+    if change > RESHUFFLE_LIMIT_LOG_CHANGE {
+        *logged = limit;
+        info!("{}", message);
+    } else if limit != *logged {
+        debug!("{}", message);
+    }
+}
+
+/// Print the difficulty of each pattern on fingerprints of `bytes` bytes
+fn print_estimate(patterns: &PatternSet, rate: f64, bytes: usize) {
+    println!("Estimated with {:.2} hash/s:", rate);
+    let describe = |name: &str, probability: Option<f64>| match probability {
+        Some(probability) if probability > 0.0 => println!(
+            "  {}: probability {:.3e}, {:.3e} hashes per match, ~{} per match",
+            name,
+            probability,
+            1.0 / probability,
+            format_duration(1.0 / (probability * rate))
+        ),
+        Some(_) => println!("  {}: can never match", name),
+        None => println!("  {}: can not be estimated (needs the regex engine)", name),
+    };
+    for pattern in patterns.patterns() {
+        describe(pattern.name(), pattern.matcher().match_probability(bytes));
+    }
+    if patterns.len() > 1 {
+        describe("(any)", patterns.match_probability(bytes));
+    }
+}
+
+/// Print presets with their difficulty on fingerprints of `bytes` bytes
+fn print_presets(presets: &[Preset], target: MatchTarget, bytes: usize) {
+    println!(
+        "Presets (use with --preset NAME[:PARAMETER]), matched on the {}:",
+        target
+    );
+    for preset in presets {
+        let pattern = match preset.to_pattern(target, bytes) {
+            Ok(pattern) => pattern,
+            Err(error) => {
+                println!("  {:<20} {}", preset.to_string(), error);
+                continue;
+            }
+        };
+        println!("  {:<20} {}", preset.to_string(), preset.description());
+        match pattern.matcher().match_probability(bytes) {
+            Some(probability) if probability > 0.0 => println!(
+                "  {:<20} probability {:.3e}, {:.3e} hashes per match",
+                "",
+                probability,
+                1.0 / probability
+            ),
+            _ => println!("  {:<20} can not be estimated", ""),
+        }
+    }
+}
+
+/// Sub-thread that display status summary, returns when the time limit is reached
+fn setup_summary<B: ProgressLoggerBackend, T>(
+    logger_backend: Arc<Mutex<B>>,
+    counter: Arc<Counter>,
+    probability: Option<f64>,
+    leaderboard: Option<Arc<Leaderboard<T>>>,
+    time_limit: Option<Duration>,
+    target: MatchTarget,
+    stats_file: Option<String>,
+) {
     let start = Instant::now();
+    let mut rates = Rates::new();
+    let mut last_write = start;
     loop {
         thread::sleep(Duration::from_millis(100));
+        rates.sample(&counter.statistics);
+        let stopping = time_limit.is_some_and(|time_limit| start.elapsed() >= time_limit);
+        if let Some(stats_file) = &stats_file {
+            if stopping || last_write.elapsed() >= STATS_FILE_INTERVAL {
+                last_write = Instant::now();
+                if let Err(error) = save_file(stats_file.clone(), &counter.to_json(&rates)) {
+                    warn!("Failed to write statistics to {}: {}", stats_file, error);
+                }
+            }
+        }
+        if stopping {
+            return;
+        }
         debug!("Updating counter information");
-        let secs_elapsed = start.elapsed().as_secs();
+        let hash_rate = rates.rate(RATE_WINDOWS[1].1).unwrap_or(0.0);
+        let eta = match probability {
+            Some(probability) if probability > 0.0 && hash_rate > 0.0 => format!(
+                ", next match in ~{}",
+                format_duration(1.0 / (probability * hash_rate))
+            ),
+            _ => String::new(),
+        };
+        let standings = leaderboard
+            .as_ref()
+            .map(|leaderboard| leaderboard.standings())
+            .unwrap_or_default();
+        let board = match standings.first() {
+            Some((score, fingerprint)) => format!(
+                ", best {} [{}], leaderboard: {}",
+                score,
+                target.render(fingerprint),
+                standings
+                    .iter()
+                    .map(|(score, _)| score.to_string())
+                    .collect::<Vec<String>>()
+                    .join(" ")
+            ),
+            None => String::new(),
+        };
         logger_backend.lock().unwrap().set_message(&format!(
-            "Summary: {} (avg. {:.2} hash/s)",
+            "Summary: {} ({}{}){}",
             &counter,
-            counter.get_total() as f64 / secs_elapsed as f64
+            format_rates(&counter.statistics, &rates),
+            eta,
+            board
         ));
     }
 }
 
+/// Render the rolling rates, the spread between threads and the number of new keys
+fn format_rates(statistics: &Statistics, rates: &Rates) -> String {
+    let format_rate = |rate: Option<f64>| match rate {
+        Some(rate) => format!("{:.0}", rate),
+        None => String::from("-"),
+    };
+    let mut parts = vec![format!("avg. {} hash/s", format_rate(rates.average()))];
+    for (name, window) in RATE_WINDOWS {
+        parts.push(format!("{}: {}", name, format_rate(rates.rate(window))));
+    }
+    // The slowest and the fastest thread, to spot stragglers
+    let thread_rates = rates.thread_rates(RATE_WINDOWS[1].1);
+    let slowest = thread_rates
+        .iter()
+        .enumerate()
+        .filter_map(|(index, rate)| rate.map(|rate| (index, rate)))
+        .min_by(|a, b| a.1.total_cmp(&b.1));
+    let fastest = thread_rates
+        .iter()
+        .filter_map(|rate| *rate)
+        .max_by(|a, b| a.total_cmp(b));
+    if let (Some((index, slowest)), Some(fastest)) = (slowest, fastest) {
+        if thread_rates.len() > 1 {
+            parts.push(format!(
+                "threads {:.0}-{:.0} (slowest: {})",
+                slowest, fastest, index
+            ));
+        }
+    }
+    parts.push(format!(
+        "{} keys, {} shuffles",
+        statistics.get_keys(),
+        statistics.get_shuffles()
+    ));
+    parts.join(", ")
+}
+
 impl Counter {
     /// Create new instance
-    fn new() -> Self {
+    fn new(
+        pattern_names: Vec<String>,
+        threads: usize,
+        key_pool: Option<Arc<KeyPool<DefaultBackend>>>,
+    ) -> Self {
+        let pattern_success = pattern_names.iter().map(|_| AtomicUsize::new(0)).collect();
         Self {
-            total: AtomicUsize::new(0),
+            statistics: Statistics::new(threads),
             success: AtomicUsize::new(0),
+            excluded: AtomicUsize::new(0),
+            pattern_names,
+            pattern_success,
+            key_pool,
         }
     }
 
-    /// Count towards total numbers of fingerprints generated
-    fn count_total(&self, accumulated_counts: usize) {
-        self.total.fetch_add(accumulated_counts, Ordering::SeqCst);
+    /// Count towards total numbers of fingerprints matched, and towards the matched patterns
+    fn count_success(&self, patterns: &[usize]) {
+        self.success.fetch_add(1, Ordering::SeqCst);
+        for index in patterns {
+            self.pattern_success[*index].fetch_add(1, Ordering::SeqCst);
+        }
     }
 
-    /// Count towards total numbers of fingerprints matched
-    fn count_success(&self) {
-        self.success.fetch_add(1, Ordering::SeqCst);
+    /// Count a fingerprint that matched but was rejected by an exclusion pattern
+    fn count_excluded(&self) {
+        self.excluded.fetch_add(1, Ordering::SeqCst);
     }
 
     /// Get number of total fingerprints generated
-    fn get_total(&self) -> usize {
-        self.total.load(Ordering::SeqCst)
+    fn get_total(&self) -> u64 {
+        self.statistics.get_hashes()
     }
 
     /// Get number of total fingerprints matched
     fn get_success(&self) -> usize {
         self.success.load(Ordering::SeqCst)
     }
+
+    /// Get number of fingerprints rejected by exclusion patterns
+    fn get_excluded(&self) -> usize {
+        self.excluded.load(Ordering::SeqCst)
+    }
+
+    /// Get number of fingerprints matched by each pattern
+    fn get_pattern_success(&self) -> Vec<(&str, usize)> {
+        self.pattern_names
+            .iter()
+            .zip(self.pattern_success.iter())
+            .map(|(name, success)| (name.as_str(), success.load(Ordering::SeqCst)))
+            .collect()
+    }
+
+    /// Render the counters and the statistics as a JSON object
+    fn to_json(&self, rates: &Rates) -> String {
+        let patterns = self
+            .get_pattern_success()
+            .iter()
+            .map(|(name, success)| format!("{}: {}", json_string(name), success))
+            .collect::<Vec<String>>()
+            .join(", ");
+        let key_pool = match &self.key_pool {
+            Some(key_pool) => {
+                let statistics = key_pool.statistics();
+                format!(
+                    "{{\"queued\": {}, \"generated\": {}, \"taken\": {}, \"starved\": {}, \
+                     \"waited\": {:.3}}}",
+                    statistics.queued,
+                    statistics.generated,
+                    statistics.taken,
+                    statistics.starved,
+                    statistics.waited.as_secs_f64()
+                )
+            }
+            None => String::from("null"),
+        };
+        format!(
+            "{{\"matched\": {}, \"excluded\": {}, \"patterns\": {{{}}}, \"key_pool\": {}, \
+             \"statistics\": {}}}\n",
+            self.get_success(),
+            self.get_excluded(),
+            patterns,
+            key_pool,
+            self.statistics.to_json(rates)
+        )
+    }
 }
 
 impl fmt::Display for Counter {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pattern_success = self
+            .get_pattern_success()
+            .iter()
+            .map(|(name, success)| format!("{}: {}", name, success))
+            .collect::<Vec<String>>()
+            .join(", ");
         write!(
             f,
-            "{} matched, {} total",
+            "{} matched ({}), {} excluded, {} total",
             self.get_success(),
+            pattern_success,
+            self.get_excluded(),
             self.get_total(),
-        )
+        )?;
+        if let Some(key_pool) = &self.key_pool {
+            let statistics = key_pool.statistics();
+            write!(
+                f,
+                ", {} keys ready, starved {} times ({:.1}s)",
+                statistics.queued,
+                statistics.starved,
+                statistics.waited.as_secs_f64()
+            )?;
+        }
+        Ok(())
     }
 }
 
@@ -235,25 +923,38 @@ impl<B: Backend> Key<B> {
         self.backend.fingerprint()
     }
 
-    /// Rehash the key
-    fn shuffle(&mut self) -> Result<(), Error> {
-        Ok(self.backend.shuffle()?)
-    }
-
-    /// Save armored keys
-    fn save_key(self, user_id: &UserID, dry_run: bool) -> Result<(), Error> {
+    /// Save armored keys, `label` names the patterns that matched
+    ///
+    /// Files are named after the full fingerprint, or after the `target` rendering if
+    /// `name_after_target` is set. Renderings are shared by many keys, the fingerprint is appended
+    /// once the rendering is taken. Existing files are never overwritten.
+    fn save_key(
+        self,
+        user_id: &UserID,
+        dry_run: bool,
+        label: &str,
+        target: MatchTarget,
+        name_after_target: bool,
+    ) -> Result<(), Error> {
         if dry_run {
             return Ok(());
         }
         let fingerprint = self.get_fingerprint();
-        info!("saving [{}]", &fingerprint);
+        info!("saving [{}] ({})", target.render(&fingerprint), label);
+        let stem = match target.render_file_name(&fingerprint) {
+            rendering if !name_after_target || rendering == fingerprint => fingerprint,
+            rendering if Path::new(&format!("{}-{}-private.asc", rendering, label)).exists() => {
+                format!("{}-{}", rendering, fingerprint)
+            }
+            rendering => rendering,
+        };
         let armored_keys = self.backend.get_armored_results(user_id)?;
-        save_file(
-            format!("{}-private.asc", &fingerprint),
+        save_new_file(
+            format!("{}-{}-private.asc", &stem, label),
             armored_keys.get_private_key(),
         )?;
-        save_file(
-            format!("{}-public.asc", &fingerprint),
+        save_new_file(
+            format!("{}-{}-public.asc", &stem, label),
             armored_keys.get_public_key(),
         )?;
         Ok(())
@@ -276,47 +977,370 @@ fn main() -> Result<(), Error> {
         "if you met any issue, please file an issue report to \"{}\"",
         PKG_REPOSITORY
     );
-    let counter = Arc::new(Counter::new());
-
-    let pool = ThreadPoolBuilder::new()
-        .num_threads(opts.jobs + 1)
-        .build()?;
+    let presets = opts
+        .presets
+        .iter()
+        .map(|preset| Preset::from_str(preset))
+        .collect::<Result<Vec<Preset>, _>>()?;
+    let target = MatchTarget::from_str(&opts.match_on)?;
+    let variation = Variation::from_str(&opts.vary)?;
+    if variation == Variation::RsaExponent
+        && !matches!(
+            CipherSuite::from_str(&opts.cipher_suite)?.get_signing_key_algorithm(),
+            Algorithms::Rsa(_)
+        )
+    {
+        return Err(PGPError::VariationNotSupported(format!(
+            "{} with {}",
+            variation, opts.cipher_suite
+        ))
+        .into());
+    }
+    let window = CreationWindow::new(
+        opts.not_before
+            .as_deref()
+            .map(parse_timestamp)
+            .transpose()?
+            .unwrap_or(0),
+        opts.not_after
+            .as_deref()
+            .map(parse_timestamp)
+            .transpose()?
+            .unwrap_or(u32::MAX),
+        Walk::from_str(&opts.walk)?,
+    )?;
+    // Keys are generated now at the latest, fail early if none could be dated inside the window
+    window.until(unix_timestamp(SystemTime::now())?)?;
+    let version = KeyVersion::from_str(&opts.key_version)?;
+    let digest_bytes = version.digest_bytes();
+    let key_options = KeyOptions {
+        variation,
+        window,
+        version,
+    };
+    if let Some(Command::Presets) = &opts.command {
+        logger_backend.lock().unwrap().finish();
+        if presets.is_empty() {
+            print_presets(&Preset::DEFAULTS, target, digest_bytes);
+        } else {
+            print_presets(&presets, target, digest_bytes);
+        }
+        return Ok(());
+    }
+    let scorer = opts.score.as_deref().map(Scorer::from_str).transpose()?;
+    let time_limit = opts.time_limit.as_deref().map(parse_duration).transpose()?;
+    let reshuffle_policy = if opts.share_keys {
+        if opts.reshuffle_limit != "auto" {
+            warn!(
+                "Ignoring the reshuffle limit, shared keys are searched through their whole window"
+            );
+        }
+        if variation == Variation::RsaExponent {
+            warn!("Keys varying the public exponent can't be shared, every worker gets its own");
+        }
+        ReshufflePolicy::Fixed(usize::MAX)
+    } else {
+        let reshuffle_policy = parse_reshuffle_policy(&opts.reshuffle_limit)?;
+        info!("Reshuffle limit: {}", reshuffle_policy);
+        reshuffle_policy
+    };
+    info!(
+        "Key version: {} ({} hex digit fingerprints)",
+        version,
+        digest_bytes * 2
+    );
+    info!(
+        "Creation times: {} to {}, walking {}",
+        window.not_before(),
+        window.not_after(),
+        window.walk()
+    );
+    let mut extra = presets
+        .iter()
+        .map(|preset| preset.to_pattern(target, digest_bytes))
+        .collect::<Result<Vec<NamedPattern>, _>>()?;
+    extra.extend(load_wordlist(&opts, target)?);
+    let patterns = Arc::new(load_patterns(
+        &opts.patterns,
+        &opts.pattern_file,
+        extra,
+        target,
+        scorer.is_some() || matches!(opts.command, Some(Command::Bench { .. })),
+    )?);
+    for pattern in patterns.patterns() {
+        info!(
+            "Pattern \"{}\": {} ({})",
+            pattern.name(),
+            pattern.pattern(),
+            pattern.matcher()
+        );
+    }
+    info!("Combined pattern: {}", patterns.combined());
+    let excludes = Arc::new(load_patterns(
+        &opts.excludes,
+        &opts.exclude_file,
+        Vec::new(),
+        target,
+        true,
+    )?);
+    for pattern in excludes.patterns() {
+        info!(
+            "Exclusion pattern \"{}\": {} ({})",
+            pattern.name(),
+            pattern.pattern(),
+            pattern.matcher()
+        );
+    }
+    let mut topology = Topology::detect();
+    if let Some(node) = opts.numa_node {
+        topology = topology.numa_node(node)?;
+    }
+    if opts.physical_cores {
+        topology = topology.physical_cores();
+    }
+    let cpus = topology.spread().ids();
+    let restricted = opts.numa_node.is_some() || opts.physical_cores;
+    let jobs = opts
+        .jobs
+        .unwrap_or_else(|| cpus.len().saturating_sub(opts.keygen_threads).max(1));
+    info!("Usable CPUs: {:?}, running {} worker(s)", cpus, jobs);
+    if opts.pin_threads && jobs > cpus.len() {
+        warn!(
+            "{} workers on {} CPU(s), some workers will share a CPU",
+            jobs,
+            cpus.len()
+        );
+    }
+    let pool = ThreadPoolBuilder::new().num_threads(jobs + 1).build()?;
     let user_id = UserID::from(opts.user_id);
 
-    for thread_id in 0..opts.jobs {
+    if let Some(Command::Estimate {
+        rate,
+        bench_seconds,
+    }) = &opts.command
+    {
+        let rate = match rate {
+            Some(rate) => *rate,
+            None => {
+                warn!("Benchmarking for {} second(s)", bench_seconds);
+                benchmark_rate(
+                    &pool,
+                    &CipherSuite::from_str(&opts.cipher_suite)?,
+                    key_options,
+                    &patterns,
+                    jobs,
+                    Duration::from_secs(*bench_seconds),
+                )
+            }
+        };
+        logger_backend.lock().unwrap().finish();
+        print_estimate(&patterns, rate, digest_bytes);
+        return Ok(());
+    }
+
+    if let Some(Command::Bench {
+        seconds,
+        suites,
+        format,
+    }) = &opts.command
+    {
+        let suites = if suites.is_empty() {
+            bench::SUITES
+                .iter()
+                .map(|suite| suite.to_string())
+                .collect()
+        } else {
+            suites.clone()
+        };
+        warn!(
+            "Benchmarking for {} second(s) per measurement with {} thread(s)",
+            seconds, jobs
+        );
+        let mut bench = Bench::new(&pool, jobs, Duration::from_secs_f64(*seconds));
+        #[cfg(feature = "sequoia")]
+        bench.backend("sequoia", &suites, |suite| {
+            vanity_gpg::SequoiaBackend::with_options(suite, key_options)
+        })?;
+        #[cfg(feature = "rpgp")]
+        bench.backend("rpgp", &suites, |suite| {
+            vanity_gpg::RPGPBackend::with_options(suite, key_options)
+        })?;
+        bench.hex(version)?;
+        bench.matchers(&patterns, version)?;
+        logger_backend.lock().unwrap().finish();
+        match format.as_str() {
+            "json" => println!("{}", bench.to_json()),
+            _ => println!("{}", bench.to_table()),
+        }
+        return Ok(());
+    }
+
+    let key_pool = match opts.keygen_threads {
+        0 => None,
+        threads => {
+            info!(
+                "Generating keys on {} background thread(s), keeping {} ready",
+                threads, opts.key_pool
+            );
+            let cipher_suite = CipherSuite::from_str(&opts.cipher_suite)?;
+            Some(Arc::new(KeyPool::new(
+                move || DefaultBackend::with_options(cipher_suite.clone(), key_options),
+                threads,
+                opts.key_pool,
+            )))
+        }
+    };
+    let key_share = if opts.share_keys {
+        info!("Sharing every key between {} worker(s)", jobs);
+        let key_pool = key_pool.clone();
+        let cipher_suite = CipherSuite::from_str(&opts.cipher_suite)?;
+        Some(Arc::new(KeyShare::new(
+            move || match &key_pool {
+                Some(key_pool) => key_pool.take(),
+                None => DefaultBackend::with_options(cipher_suite.clone(), key_options),
+            },
+            jobs,
+        )))
+    } else {
+        None
+    };
+    let counter = Arc::new(Counter::new(
+        patterns
+            .patterns()
+            .iter()
+            .map(|pattern| pattern.name().to_string())
+            .collect(),
+        jobs,
+        key_pool.clone(),
+    ));
+    let leaderboard = scorer.map(|_| Arc::new(Leaderboard::new(opts.keep)));
+
+    for thread_id in 0..jobs {
         let user_id_cloned = user_id.clone();
-        let pattern = Regex::new(&opts.pattern)?;
+        let placement = if opts.pin_threads {
+            vec![cpus[thread_id % cpus.len()]]
+        } else if restricted {
+            cpus.clone()
+        } else {
+            Vec::new()
+        };
+        let scoring = scorer.zip(leaderboard.clone());
+        let patterns = Arc::clone(&patterns);
+        let excludes = Arc::clone(&excludes);
         let dry_run = opts.dry_run;
+        let name_after_target = opts.name_after_target;
         let cipher_suite = CipherSuite::from_str(&opts.cipher_suite)?;
         let counter_cloned = Arc::clone(&counter);
+        let key_pool = key_pool.clone();
+        let key_share = key_share.clone();
         info!("({}): Spawning thread", thread_id);
         pool.spawn(move || {
-            let mut key = Key::new(DefaultBackend::new(cipher_suite.clone()).unwrap());
-            let mut reshuffle_counter: usize = KEY_RESHUFFLE_LIMIT;
-            let mut report_counter: usize = 0;
-            loop {
-                let fingerprint = key.get_fingerprint();
-                if pattern.is_match(&fingerprint) {
-                    warn!("({}): [{}] matched", thread_id, &fingerprint);
-                    counter_cloned.count_success();
-                    key.save_key(&user_id_cloned, dry_run).unwrap_or(());
-                    key = Key::new(DefaultBackend::new(cipher_suite.clone()).unwrap());
-                } else if reshuffle_counter == 0 {
-                    info!(
-                        "({}): Reshuffle limit reached, generating new primary key",
-                        thread_id
+            if !placement.is_empty() {
+                match pin_current_thread(&placement) {
+                    Ok(()) => info!("({}): Running on CPU(s) {:?}", thread_id, placement),
+                    Err(error) => warn!("({}): Failed to pin thread: {}", thread_id, error),
+                }
+            }
+            // Exclusions are only checked for candidates that matched
+            let matcher = (&*patterns).and(from_fn(|candidate: &Candidate<'_>| {
+                if excludes.is_match(candidate.digest()) {
+                    debug!(
+                        "({}): [{}] excluded",
+                        thread_id,
+                        target.render_digest(candidate.digest())
                     );
-                    key = Key::new(DefaultBackend::new(cipher_suite.clone()).unwrap());
-                    reshuffle_counter = KEY_RESHUFFLE_LIMIT;
-                } else {
-                    info!("({}): [{}] is not a match", thread_id, fingerprint);
-                    reshuffle_counter -= 1;
-                    key.shuffle().unwrap_or(());
+                    counter_cloned.count_excluded();
+                    return false;
                 }
-                report_counter += 1;
-                if report_counter >= COUNTER_THRESHOLD {
-                    counter_cloned.count_total(report_counter);
-                    report_counter = 0;
+                true
+            }));
+            let mut search = Search::new(matcher, || match (&key_share, &key_pool) {
+                (Some(key_share), _) => key_share.take(),
+                (None, Some(key_pool)) => key_pool.take().map(SharedKey::new),
+                (None, None) => DefaultBackend::with_options(cipher_suite.clone(), key_options)
+                    .map(SharedKey::new),
+            })
+            .unwrap()
+            .with_reshuffle_policy(reshuffle_policy);
+            let statistics = counter_cloned.statistics.thread(thread_id);
+            statistics.count_key();
+            let mut logged_limit = 0;
+            loop {
+                let step = search
+                    .run(COUNTER_THRESHOLD, |key, candidate, matched| {
+                        if let Some((scorer, leaderboard)) = &scoring {
+                            let score = scorer.score(candidate.digest());
+                            if score > leaderboard.threshold()
+                                && !excludes.is_match(candidate.digest())
+                                && leaderboard.offer(
+                                    score,
+                                    candidate.digest(),
+                                    key.base().id(),
+                                    || key.to_key().unwrap(),
+                                )
+                            {
+                                info!(
+                                    "({}): [{}] scored {}",
+                                    thread_id,
+                                    target.render_digest(candidate.digest()),
+                                    score
+                                );
+                            }
+                        }
+                        if !matched {
+                            info!(
+                                "({}): [{}] is not a match",
+                                thread_id,
+                                target.render_digest(candidate.digest())
+                            );
+                        }
+                    })
+                    .unwrap();
+                statistics.count_hashes(step.tested());
+                log_reshuffle_limit(thread_id, &search, &mut logged_limit);
+                match step {
+                    Step::Found { key, digest, .. } => {
+                        statistics.count_key();
+                        // The other workers stop searching the key, if it's shared
+                        if !key.retire() {
+                            info!(
+                                "({}): [{}] dropped, another slice of the key matched first",
+                                thread_id,
+                                target.render_digest(&digest)
+                            );
+                            continue;
+                        }
+                        let matched = patterns.matches(&digest);
+                        let label = matched
+                            .iter()
+                            .map(|index| patterns.patterns()[*index].describe_match(&digest))
+                            .collect::<Vec<String>>()
+                            .join("+");
+                        warn!(
+                            "({}): [{}] matched {}",
+                            thread_id,
+                            target.render_digest(&digest),
+                            label
+                        );
+                        counter_cloned.count_success(&matched);
+                        if let Err(error) = Key::new(key.into_inner()).save_key(
+                            &user_id_cloned,
+                            dry_run,
+                            &label,
+                            target,
+                            name_after_target,
+                        ) {
+                            warn!("({}): Failed to save the key: {}", thread_id, error);
+                        }
+                    }
+                    Step::Regenerated { .. } => {
+                        info!(
+                            "({}): Reshuffle limit reached, generating new primary key",
+                            thread_id
+                        );
+                        statistics.count_key();
+                    }
+                    Step::Exhausted { .. } => {}
                 }
             }
         });
@@ -325,7 +1349,52 @@ fn main() -> Result<(), Error> {
     // Setup summary
     let logger_backend_cloned = Arc::clone(&logger_backend);
     let counter_cloned = Arc::clone(&counter);
-    pool.install(move || setup_summary(logger_backend_cloned, counter_cloned));
+    let probability = patterns.match_probability(digest_bytes);
+    let leaderboard_cloned = leaderboard.clone();
+    pool.install(move || {
+        setup_summary(
+            logger_backend_cloned,
+            counter_cloned,
+            probability,
+            leaderboard_cloned,
+            time_limit,
+            target,
+            opts.stats_file,
+        )
+    });
+    warn!("Time limit reached");
+
+    // Export the leaderboard
+    if let (Some(scorer), Some(leaderboard)) = (scorer, leaderboard) {
+        for entry in leaderboard.take() {
+            let fingerprint = entry.fingerprint();
+            warn!("[{}] scored {}", target.render(&fingerprint), entry.score());
+            let label = format!("{}{}", scorer, entry.score());
+            let key = entry.into_payload();
+            // Keys that were already saved for a pattern match aren't exported twice
+            if !key.retire() {
+                warn!(
+                    "[{}] skipped, the key was already saved",
+                    target.render(&fingerprint)
+                );
+                continue;
+            }
+            if let Err(error) = Key::new(key.into_inner()).save_key(
+                &user_id,
+                opts.dry_run,
+                &label,
+                target,
+                opts.name_after_target,
+            ) {
+                warn!(
+                    "[{}] failed to save: {}",
+                    target.render(&fingerprint),
+                    error
+                );
+            }
+        }
+    }
+    logger_backend.lock().unwrap().finish();
 
     Ok(())
 }
@@ -0,0 +1,462 @@
+//! Nibble-level DFA
+//!
+//! Compiles a fingerprint regex into a deterministic automaton over the 16 hex digits, then
+//! folds every pair of nibble transitions into a byte transition, so that the binary digest can be
+//! walked directly without building the hex string.
+//!
+//! Only the subset of the regex syntax that makes sense on an uppercase hex string is supported;
+//! anything else (word boundaries, characters outside `0-9A-F`, automata that grow too big) makes
+//! `NibbleDfa::from_pattern` return `None` so the caller can fall back to `regex::Regex`.
+
+use regex_syntax::hir::{Anchor, Hir, HirKind, Literal, RepetitionKind, RepetitionRange};
+use regex_syntax::Parser;
+
+use super::{class_to_set, hex_value, NibbleSet, ANY_NIBBLE};
+
+use std::collections::HashMap;
+
+/// Maximum number of NFA states before giving up
+const NFA_STATE_LIMIT: usize = 1 << 16;
+/// Maximum number of DFA states before giving up
+const DFA_STATE_LIMIT: usize = 1 << 14;
+
+/// State that can never reach a match
+const DEAD: u32 = 0;
+/// State that has already matched
+const MATCHED: u32 = 1;
+
+/// NFA states
+#[derive(Debug, Clone)]
+enum NfaState {
+    /// Consume one nibble in the set
+    Nibble { set: NibbleSet, next: usize },
+    /// Epsilon transitions
+    Split(Vec<usize>),
+    /// Only passable at the start of the input
+    AssertStart(usize),
+    /// Only passable at the end of the input
+    AssertEnd(usize),
+    /// Accepting state
+    Match,
+}
+
+/// Thompson construction over nibbles
+#[derive(Debug, Default)]
+struct NfaBuilder {
+    states: Vec<NfaState>,
+}
+
+/// Regex compiled into a byte-indexed DFA
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NibbleDfa {
+    /// `transitions[state * 256 + byte]`, two nibbles per step
+    transitions: Vec<u32>,
+    /// Whether a state accepts when the input ends there (`$` anchors)
+    accept_at_end: Vec<bool>,
+    /// Start state
+    start: u32,
+    /// Nibble-level transitions, `nibble_transitions[state * 16 + nibble]`
+    nibble_transitions: Vec<u32>,
+}
+
+impl NfaBuilder {
+    /// Add a new state
+    fn push(&mut self, state: NfaState) -> Option<usize> {
+        if self.states.len() >= NFA_STATE_LIMIT {
+            return None;
+        }
+        self.states.push(state);
+        Some(self.states.len() - 1)
+    }
+
+    /// Compile `hir` so that it continues to `next`, returning the entry state
+    fn compile(&mut self, hir: &Hir, next: usize) -> Option<usize> {
+        match hir.kind() {
+            HirKind::Empty => Some(next),
+            HirKind::Literal(Literal::Unicode(c)) => {
+                let value = hex_value(*c)?;
+                self.push(NfaState::Nibble {
+                    set: 1 << value,
+                    next,
+                })
+            }
+            HirKind::Literal(Literal::Byte(b)) => {
+                let value = hex_value(*b as char)?;
+                self.push(NfaState::Nibble {
+                    set: 1 << value,
+                    next,
+                })
+            }
+            HirKind::Class(class) => self.push(NfaState::Nibble {
+                set: class_to_set(class),
+                next,
+            }),
+            HirKind::Anchor(Anchor::StartText) | HirKind::Anchor(Anchor::StartLine) => {
+                self.push(NfaState::AssertStart(next))
+            }
+            HirKind::Anchor(Anchor::EndText) | HirKind::Anchor(Anchor::EndLine) => {
+                self.push(NfaState::AssertEnd(next))
+            }
+            HirKind::WordBoundary(_) => None,
+            HirKind::Group(group) => self.compile(&group.hir, next),
+            HirKind::Concat(items) => {
+                let mut entry = next;
+                for item in items.iter().rev() {
+                    entry = self.compile(item, entry)?;
+                }
+                Some(entry)
+            }
+            HirKind::Alternation(branches) => {
+                let entries = branches
+                    .iter()
+                    .map(|branch| self.compile(branch, next))
+                    .collect::<Option<Vec<usize>>>()?;
+                self.push(NfaState::Split(entries))
+            }
+            HirKind::Repetition(repetition) => {
+                let (min, max) = match &repetition.kind {
+                    RepetitionKind::ZeroOrOne => (0, Some(1)),
+                    RepetitionKind::ZeroOrMore => (0, None),
+                    RepetitionKind::OneOrMore => (1, None),
+                    RepetitionKind::Range(RepetitionRange::Exactly(n)) => (*n, Some(*n)),
+                    RepetitionKind::Range(RepetitionRange::AtLeast(n)) => (*n, None),
+                    RepetitionKind::Range(RepetitionRange::Bounded(n, m)) => (*n, Some(*m)),
+                };
+                let mut entry = match max {
+                    None => self.compile_star(&repetition.hir, next)?,
+                    Some(max) => {
+                        let mut entry = next;
+                        for _ in min..max {
+                            let body = self.compile(&repetition.hir, entry)?;
+                            entry = self.push(NfaState::Split(vec![body, next]))?;
+                        }
+                        entry
+                    }
+                };
+                for _ in 0..min {
+                    entry = self.compile(&repetition.hir, entry)?;
+                }
+                Some(entry)
+            }
+        }
+    }
+
+    /// Compile `hir*` continuing to `next`
+    fn compile_star(&mut self, hir: &Hir, next: usize) -> Option<usize> {
+        let split = self.push(NfaState::Split(Vec::new()))?;
+        let body = self.compile(hir, split)?;
+        self.states[split] = NfaState::Split(vec![body, next]);
+        Some(split)
+    }
+
+    /// Epsilon closure of `states`, sorted and deduplicated
+    fn closure(&self, states: &[usize], at_start: bool, at_end: bool) -> Vec<usize> {
+        let mut visited = vec![false; self.states.len()];
+        let mut stack: Vec<usize> = states.to_vec();
+        let mut result = Vec::new();
+        while let Some(state) = stack.pop() {
+            if visited[state] {
+                continue;
+            }
+            visited[state] = true;
+            result.push(state);
+            match &self.states[state] {
+                NfaState::Split(targets) => stack.extend(targets),
+                NfaState::AssertStart(next) if at_start => stack.push(*next),
+                NfaState::AssertEnd(next) if at_end => stack.push(*next),
+                _ => {}
+            }
+        }
+        result.sort_unstable();
+        result
+    }
+
+    /// Check whether a set of states contains the accepting state
+    fn is_accepting(&self, states: &[usize]) -> bool {
+        states
+            .iter()
+            .any(|state| matches!(self.states[*state], NfaState::Match))
+    }
+}
+
+impl NibbleDfa {
+    /// Compile a regex pattern
+    ///
+    /// Returns `None` if the pattern uses features that can not be expressed over hex digits, or
+    /// if the automaton would be too large.
+    pub fn from_pattern(pattern: &str) -> Option<Self> {
+        let hir = Parser::new().parse(pattern).ok()?;
+        let mut nfa = NfaBuilder::default();
+        let accept = nfa.push(NfaState::Match)?;
+        let pattern_entry = nfa.compile(&hir, accept)?;
+        // Unanchored search, equivalent to a leading `(?s:.)*?`
+        let entry = nfa.push(NfaState::Split(Vec::new()))?;
+        let skip = nfa.push(NfaState::Nibble {
+            set: ANY_NIBBLE,
+            next: entry,
+        })?;
+        nfa.states[entry] = NfaState::Split(vec![pattern_entry, skip]);
+        Self::from_nfa(&nfa, entry)
+    }
+
+    /// Build a DFA that never matches
+    pub fn never() -> Self {
+        Self {
+            transitions: [[DEAD; 256], [MATCHED; 256]].concat(),
+            accept_at_end: vec![false, true],
+            start: DEAD,
+            nibble_transitions: [[DEAD; 16], [MATCHED; 16]].concat(),
+        }
+    }
+
+    /// Subset construction
+    fn from_nfa(nfa: &NfaBuilder, entry: usize) -> Option<Self> {
+        let mut sets: Vec<Vec<usize>> = vec![Vec::new(), Vec::new()];
+        let mut accept_at_end = vec![false, true];
+        let mut nibble_transitions: Vec<u32> = vec![DEAD; 16];
+        nibble_transitions.extend([MATCHED; 16]);
+        let mut known: HashMap<Vec<usize>, u32> = HashMap::new();
+
+        let start_set = nfa.closure(&[entry], true, false);
+        let start = if start_set.is_empty() {
+            DEAD
+        } else if nfa.is_accepting(&start_set) {
+            MATCHED
+        } else {
+            let end_set = nfa.closure(&start_set, true, true);
+            known.insert(start_set.clone(), 2);
+            accept_at_end.push(nfa.is_accepting(&end_set));
+            sets.push(start_set);
+            nibble_transitions.extend([DEAD; 16]);
+            2
+        };
+
+        let mut pending = 2;
+        while pending < sets.len() {
+            for nibble in 0..16u8 {
+                let targets: Vec<usize> = sets[pending]
+                    .iter()
+                    .filter_map(|state| match &nfa.states[*state] {
+                        NfaState::Nibble { set, next } if set & (1 << nibble) != 0 => Some(*next),
+                        _ => None,
+                    })
+                    .collect();
+                let target_set = nfa.closure(&targets, false, false);
+                let target = if target_set.is_empty() {
+                    DEAD
+                } else if nfa.is_accepting(&target_set) {
+                    MATCHED
+                } else if let Some(id) = known.get(&target_set) {
+                    *id
+                } else {
+                    if sets.len() >= DFA_STATE_LIMIT {
+                        return None;
+                    }
+                    let id = sets.len() as u32;
+                    let end_set = nfa.closure(&target_set, false, true);
+                    accept_at_end.push(nfa.is_accepting(&end_set));
+                    known.insert(target_set.clone(), id);
+                    sets.push(target_set);
+                    nibble_transitions.extend([DEAD; 16]);
+                    id
+                };
+                nibble_transitions[pending * 16 + nibble as usize] = target;
+            }
+            pending += 1;
+        }
+
+        let mut transitions = Vec::with_capacity(sets.len() * 256);
+        for state in 0..sets.len() {
+            for byte in 0..=255u8 {
+                let middle = nibble_transitions[state * 16 + (byte >> 4) as usize];
+                transitions.push(nibble_transitions[middle as usize * 16 + (byte & 0xF) as usize]);
+            }
+        }
+
+        Some(Self {
+            transitions,
+            accept_at_end,
+            start,
+            nibble_transitions,
+        })
+    }
+
+    /// Number of DFA states, including the dead and the matched state
+    pub fn len(&self) -> usize {
+        self.accept_at_end.len()
+    }
+
+    /// Check whether the DFA only has the two builtin states
+    pub fn is_empty(&self) -> bool {
+        self.len() <= 2
+    }
+
+    /// Probability for a uniformly random string of `nibbles` hex digits to match
+    ///
+    /// This walks the automaton once per position while keeping the share of all strings that
+    /// sit in each state, i.e. it counts accepted strings divided by `16^nibbles`.
+    pub fn match_probability(&self, nibbles: usize) -> f64 {
+        let mut distribution = vec![0f64; self.len()];
+        distribution[self.start as usize] = 1.0;
+        for _ in 0..nibbles {
+            let mut next_distribution = vec![0f64; self.len()];
+            for (state, share) in distribution.iter().enumerate() {
+                if *share == 0.0 {
+                    continue;
+                }
+                for nibble in 0..16 {
+                    let target = self.nibble_transitions[(state << 4) | nibble];
+                    next_distribution[target as usize] += share / 16.0;
+                }
+            }
+            distribution = next_distribution;
+        }
+        distribution
+            .iter()
+            .zip(self.accept_at_end.iter())
+            .enumerate()
+            .filter(|(state, (_, accept))| *state == MATCHED as usize || **accept)
+            .map(|(_, (share, _))| share)
+            .sum()
+    }
+
+    /// Test the binary fingerprint, two nibbles per byte
+    #[inline]
+    pub fn is_match(&self, digest: &[u8]) -> bool {
+        let mut state = self.start;
+        for byte in digest {
+            if state <= MATCHED {
+                break;
+            }
+            state = self.transitions[((state as usize) << 8) | *byte as usize];
+        }
+        state == MATCHED || self.accept_at_end[state as usize]
+    }
+
+    /// Test a sequence of nibbles
+    pub fn is_match_nibbles(&self, nibbles: &[u8]) -> bool {
+        let mut state = self.start;
+        for nibble in nibbles {
+            if state <= MATCHED {
+                break;
+            }
+            state = self.nibble_transitions[((state as usize) << 4) | (*nibble & 0xF) as usize];
+        }
+        state == MATCHED || self.accept_at_end[state as usize]
+    }
+}
+
+#[cfg(test)]
+mod dfa_test {
+    use super::NibbleDfa;
+    use crate::matcher::test_util::{digests, README_PATTERN};
+    use crate::pgp_backends::sha1_to_hex;
+    use regex::Regex;
+
+    /// Seed of the random digests
+    const SEED: u64 = 0xFEDC_BA98_7654_3210;
+
+    /// Compare the DFA with `regex` on random and planted digests
+    fn assert_agrees(pattern: &str, planted: &[[u8; 20]]) {
+        let regex = Regex::new(pattern).unwrap();
+        let dfa = NibbleDfa::from_pattern(pattern).unwrap();
+        for digest in digests(SEED, 20000).iter().chain(planted) {
+            let hex = sha1_to_hex(digest);
+            assert_eq!(
+                dfa.is_match(digest),
+                regex.is_match(&hex),
+                "{} on {}",
+                pattern,
+                hex
+            );
+        }
+    }
+
+    #[test]
+    fn never() {
+        let dfa = NibbleDfa::never();
+        assert!(!dfa.is_match(&[0u8; 20]));
+        assert_eq!(dfa.match_probability(40), 0.0);
+    }
+
+    #[test]
+    fn unsupported_patterns() {
+        assert!(NibbleDfa::from_pattern(r"\bDEAD").is_none());
+        assert!(NibbleDfa::from_pattern("dead").is_none());
+        assert!(NibbleDfa::from_pattern("(").is_none());
+    }
+
+    #[test]
+    fn readme_pattern() {
+        let mut planted = [[0u8; 20]; 3];
+        planted[0][15..].copy_from_slice(&[0xBE; 5]);
+        planted[1][15..].copy_from_slice(&[0xAA; 5]);
+        planted[2][13..].copy_from_slice(&[0x01, 0x14, 0x51, 0x41, 0x91, 0x98, 0x10]);
+        assert_agrees(README_PATTERN, &planted);
+        let dfa = NibbleDfa::from_pattern(README_PATTERN).unwrap();
+        for digest in planted.iter() {
+            assert!(dfa.is_match(digest));
+        }
+    }
+
+    #[test]
+    fn anchors_and_classes() {
+        let mut planted = [[0u8; 20]; 2];
+        planted[0][..2].copy_from_slice(&[0xDE, 0xAD]);
+        planted[1][18..].copy_from_slice(&[0xBE, 0xEF]);
+        assert_agrees("^DEAD", &planted);
+        assert_agrees("BEEF$", &planted);
+        assert_agrees("^DEAD|BEEF$", &planted);
+        assert_agrees("^[0-9]{3}", &planted);
+        assert_agrees("[A-F]{4}$", &planted);
+        assert_agrees("(?i)beef$", &planted);
+        assert_agrees("^(DE)+A?D", &planted);
+        assert_agrees("00", &planted);
+        assert_agrees("^.*$", &planted);
+        assert_agrees("^$", &planted);
+        assert_agrees("", &planted);
+    }
+
+    #[test]
+    fn match_probability() {
+        let assert_close = |pattern: &str, expected: f64| {
+            let probability = NibbleDfa::from_pattern(pattern)
+                .unwrap()
+                .match_probability(40);
+            assert!(
+                (probability - expected).abs() <= expected * 1e-9,
+                "{}: {} != {}",
+                pattern,
+                probability,
+                expected
+            );
+        };
+        assert_close("^A", 1.0 / 16.0);
+        assert_close("^AAAA", 16f64.powi(-4));
+        assert_close("^A|B$", 2.0 / 16.0 - 1.0 / 256.0);
+        assert_close("^[0-7]", 0.5);
+        assert_close("(8B){5}$", 16f64.powi(-10));
+        assert_close("", 1.0);
+        assert_close("^$", 0.0);
+        // Count accepted strings by brute force on a shorter input
+        for pattern in ["AA", "A[0-3]$|^B", "(12|21)+3?$"] {
+            let dfa = NibbleDfa::from_pattern(pattern).unwrap();
+            let accepted = (0..1u32 << 16)
+                .filter(|value| {
+                    let nibbles: Vec<u8> = (0..4).map(|i| (value >> (12 - 4 * i)) as u8).collect();
+                    dfa.is_match_nibbles(&nibbles)
+                })
+                .count();
+            assert_eq!(dfa.match_probability(4), accepted as f64 / 65536.0);
+        }
+    }
+
+    #[test]
+    fn nibbles_and_bytes_agree() {
+        let dfa = NibbleDfa::from_pattern("^1.{2}F|AB$").unwrap();
+        for digest in digests(SEED, 1000) {
+            let nibbles: Vec<u8> = digest.iter().flat_map(|b| [b >> 4, b & 0xF]).collect();
+            assert_eq!(dfa.is_match(&digest), dfa.is_match_nibbles(&nibbles));
+        }
+    }
+}
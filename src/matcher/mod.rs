@@ -0,0 +1,228 @@
+//! Fingerprint matchers
+//!
+//! This module contains the logic for testing candidate fingerprints without going through the
+//! full hex string and `Regex` machinery for every single candidate.
+mod dfa;
+mod nibble;
+mod preset;
+mod score;
+mod set;
+mod target;
+#[cfg(test)]
+pub(crate) mod test_util;
+mod traits;
+mod wordlist;
+
+use regex::Regex;
+use regex_syntax::hir::Class;
+use thiserror::Error;
+
+use crate::pgp_backends::MAX_DIGEST_BYTES;
+
+pub use self::dfa::NibbleDfa;
+pub use self::nibble::{NibbleMask, NibbleMirror, NibblePrefilter};
+pub use self::preset::Preset;
+pub use self::score::{Leaderboard, LeaderboardEntry, Scorer};
+pub use self::set::{NamedPattern, PatternSet};
+pub use self::target::MatchTarget;
+pub use self::traits::{from_fn, And, Candidate, FnMatcher, Matcher, Not, Or};
+pub use self::wordlist::{LeetTable, Word, WordPosition, Wordlist};
+
+use std::fmt;
+
+/// Length of a SHA-1 (v4) fingerprint in bytes
+pub const FINGERPRINT_BYTES: usize = 20;
+/// Length of a SHA-1 (v4) fingerprint in nibbles (hex characters)
+pub const FINGERPRINT_NIBBLES: usize = FINGERPRINT_BYTES * 2;
+/// Length of the longest fingerprint in bytes
+pub const MAX_FINGERPRINT_BYTES: usize = MAX_DIGEST_BYTES;
+/// Length of the longest fingerprint in nibbles
+pub const MAX_FINGERPRINT_NIBBLES: usize = MAX_FINGERPRINT_BYTES * 2;
+
+/// Set of allowed nibbles at one position, one bit per hex digit
+pub(crate) type NibbleSet = u16;
+
+/// Every hex digit
+pub(crate) const ANY_NIBBLE: NibbleSet = 0xFFFF;
+
+/// Errors from setting up matchers
+#[derive(Clone, Debug, Error)]
+pub enum MatcherError {
+    #[error("Scorer not supported: {0}")]
+    ScorerNotSupported(String),
+    #[error("Invalid preset: {0}")]
+    InvalidPreset(String),
+    #[error("Rendering not supported: {0}")]
+    TargetNotSupported(String),
+    #[error("Patterns for different renderings can not be combined")]
+    MixedTargets,
+    #[error("Invalid wordlist: {0}")]
+    InvalidWordlist(String),
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(#[from] regex::Error),
+}
+
+/// A compiled `-p` pattern
+#[derive(Debug, Clone)]
+pub enum PatternMatcher {
+    /// Pattern compiled into a DFA over the part of the binary digest that makes up the target
+    Dfa { dfa: NibbleDfa, target: MatchTarget },
+    /// Pattern that needs the real regex engine, optionally behind a nibble prefilter
+    Regex {
+        prefilter: Option<NibblePrefilter>,
+        regex: Regex,
+        target: MatchTarget,
+    },
+    /// Palindrome over the trailing nibbles of the part of the binary digest that makes up the
+    /// target
+    Mirror {
+        mirror: NibbleMirror,
+        target: MatchTarget,
+    },
+    /// Words spelled in hex
+    Words(Box<Wordlist>),
+}
+
+/// Get the value of an uppercase hex digit
+pub(crate) fn hex_value(c: char) -> Option<u8> {
+    match c {
+        '0'..='9' => Some(c as u8 - b'0'),
+        'A'..='F' => Some(c as u8 - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Collect the hex digits contained in a character class
+pub(crate) fn class_to_set(class: &Class) -> NibbleSet {
+    let mut set: NibbleSet = 0;
+    for (value, c) in "0123456789ABCDEF".chars().enumerate() {
+        let contained = match class {
+            Class::Unicode(unicode) => unicode
+                .iter()
+                .any(|range| range.start() <= c && c <= range.end()),
+            Class::Bytes(bytes) => bytes
+                .iter()
+                .any(|range| range.start() <= c as u8 && c as u8 <= range.end()),
+        };
+        if contained {
+            set |= 1 << value;
+        }
+    }
+    set
+}
+
+impl PatternMatcher {
+    /// Compile a pattern for the full fingerprint
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        Self::with_target(pattern, MatchTarget::Fingerprint)
+    }
+
+    /// Compile a pattern for a rendering, preferring the DFA and falling back to the regex engine
+    ///
+    /// The DFA and the prefilter work on hex digits only, so the grouped rendering always goes
+    /// through the regex engine.
+    pub fn with_target(pattern: &str, target: MatchTarget) -> Result<Self, regex::Error> {
+        let regex = Regex::new(pattern)?;
+        if target.is_plain() {
+            if let Some(dfa) = NibbleDfa::from_pattern(pattern) {
+                return Ok(PatternMatcher::Dfa { dfa, target });
+            }
+        }
+        let prefilter = match target {
+            MatchTarget::Fingerprint => NibblePrefilter::from_pattern(pattern),
+            _ => None,
+        };
+        Ok(PatternMatcher::Regex {
+            prefilter,
+            regex,
+            target,
+        })
+    }
+
+    /// Pattern that never matches
+    pub fn never(target: MatchTarget) -> Self {
+        PatternMatcher::Dfa {
+            dfa: NibbleDfa::never(),
+            target,
+        }
+    }
+
+    /// Get the rendering the pattern is matched against
+    pub fn target(&self) -> MatchTarget {
+        match self {
+            PatternMatcher::Dfa { target, .. }
+            | PatternMatcher::Regex { target, .. }
+            | PatternMatcher::Mirror { target, .. } => *target,
+            PatternMatcher::Words(wordlist) => wordlist.matcher().target(),
+        }
+    }
+
+    /// Exact probability for a random fingerprint of `bytes` bytes to match, if the pattern has
+    /// an automaton
+    pub fn match_probability(&self, bytes: usize) -> Option<f64> {
+        match self {
+            PatternMatcher::Dfa { dfa, target } => {
+                Some(dfa.match_probability(target.nibbles(bytes)))
+            }
+            PatternMatcher::Regex { .. } => None,
+            PatternMatcher::Mirror { mirror, target } => {
+                Some(mirror.match_probability(target.nibbles(bytes) / 2))
+            }
+            PatternMatcher::Words(wordlist) => wordlist.matcher().match_probability(bytes),
+        }
+    }
+
+    /// Test the binary fingerprint
+    #[inline]
+    pub fn is_match(&self, digest: &[u8]) -> bool {
+        match self {
+            PatternMatcher::Dfa { dfa, target } => match target.digest_range(digest.len()) {
+                Some(range) => dfa.is_match(&digest[range]),
+                None => dfa.is_match(digest),
+            },
+            PatternMatcher::Regex {
+                prefilter,
+                regex,
+                target,
+            } => {
+                prefilter
+                    .as_ref()
+                    .is_none_or(|prefilter| prefilter.is_match(digest))
+                    && regex.is_match(&target.render_digest(digest))
+            }
+            PatternMatcher::Mirror { mirror, target } => match target.digest_range(digest.len()) {
+                Some(range) => mirror.is_match(&digest[range]),
+                None => mirror.is_match(digest),
+            },
+            PatternMatcher::Words(wordlist) => wordlist.is_match(digest),
+        }
+    }
+}
+
+impl fmt::Display for PatternMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatternMatcher::Dfa { dfa, .. } => write!(f, "DFA with {} states", dfa.len()),
+            PatternMatcher::Regex {
+                prefilter: Some(prefilter),
+                ..
+            } => write!(
+                f,
+                "regex with a nibble prefilter of {} branch(es)",
+                prefilter.masks().len()
+            ),
+            PatternMatcher::Regex {
+                prefilter: None, ..
+            } => write!(f, "regex without prefilter"),
+            PatternMatcher::Mirror { mirror, .. } => {
+                write!(f, "palindrome check over {} nibbles", mirror.length())
+            }
+            PatternMatcher::Words(wordlist) => write!(
+                f,
+                "{} words as {}",
+                wordlist.words().len(),
+                wordlist.matcher()
+            ),
+        }
+    }
+}
@@ -0,0 +1,458 @@
+//! Nibble constraints on raw digests
+//!
+//! Anchored prefixes and suffixes of a regex pin nibbles at fixed positions of the fingerprint.
+//! Those positions can be tested against the binary digest with a couple of `AND`s, so the hex
+//! conversion and the real `Regex` only run on the (rare) candidates that survive.
+//!
+//! Suffixes are kept apart from prefixes and aligned to the end of the digest when testing, so
+//! the same prefilter works for fingerprints of every length.
+
+use regex_syntax::hir::{Anchor, Hir, HirKind, Literal, RepetitionKind, RepetitionRange};
+use regex_syntax::Parser;
+
+use super::{class_to_set, hex_value, NibbleSet, MAX_FINGERPRINT_BYTES, MAX_FINGERPRINT_NIBBLES};
+
+/// Fixed-position nibble constraints on a digest
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NibbleMask {
+    mask: [u8; MAX_FINGERPRINT_BYTES],
+    value: [u8; MAX_FINGERPRINT_BYTES],
+    start: usize,
+    end: usize,
+}
+
+/// Necessary condition for a regex to match, evaluated on the raw digest
+///
+/// Every branch has a mask for the start of the digest and one for its end. The end masks are
+/// built for a digest of `MAX_FINGERPRINT_BYTES` and shifted to the end of shorter ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NibblePrefilter {
+    masks: Vec<(NibbleMask, NibbleMask)>,
+}
+
+/// Palindrome over the trailing nibbles of a digest
+///
+/// Mirrored positions can't be expressed as a regex (no backreferences) or as a reasonably sized
+/// DFA, so this one is checked directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NibbleMirror {
+    length: usize,
+}
+
+/// Get the per-position nibble sets of an expression that always matches the same length
+fn fixed_sets(hir: &Hir) -> Option<Vec<NibbleSet>> {
+    match hir.kind() {
+        HirKind::Empty => Some(Vec::new()),
+        HirKind::Literal(Literal::Unicode(c)) => {
+            Some(vec![hex_value(*c).map_or(0, |value| 1 << value)])
+        }
+        HirKind::Literal(Literal::Byte(b)) => {
+            Some(vec![hex_value(*b as char).map_or(0, |value| 1 << value)])
+        }
+        HirKind::Class(class) => Some(vec![class_to_set(class)]),
+        HirKind::Group(group) => fixed_sets(&group.hir),
+        HirKind::Concat(items) => {
+            let mut sets = Vec::new();
+            for item in items {
+                sets.append(&mut fixed_sets(item)?);
+            }
+            Some(sets)
+        }
+        HirKind::Alternation(branches) => {
+            let mut result: Option<Vec<NibbleSet>> = None;
+            for branch in branches {
+                let sets = fixed_sets(branch)?;
+                result = match result {
+                    None => Some(sets),
+                    Some(merged) if merged.len() == sets.len() => {
+                        Some(merged.iter().zip(sets).map(|(a, b)| a | b).collect())
+                    }
+                    Some(_) => return None,
+                }
+            }
+            result
+        }
+        HirKind::Repetition(repetition) => match &repetition.kind {
+            RepetitionKind::Range(RepetitionRange::Exactly(count)) => {
+                let sets = fixed_sets(&repetition.hir)?;
+                Some(sets.repeat(*count as usize))
+            }
+            _ => None,
+        },
+        HirKind::Anchor(_) | HirKind::WordBoundary(_) => None,
+    }
+}
+
+/// Get the nibble sets that are mandatory at either end of an expression
+///
+/// Returns the sets and whether they cover the whole expression. Only fixed-length expressions
+/// and repetitions of them are understood, so the result is the same for both ends.
+fn mandatory_sets(hir: &Hir) -> (Vec<NibbleSet>, bool) {
+    if let Some(sets) = fixed_sets(hir) {
+        return (sets, true);
+    }
+    match hir.kind() {
+        HirKind::Repetition(repetition) => {
+            let minimum = match &repetition.kind {
+                RepetitionKind::OneOrMore => 1,
+                RepetitionKind::Range(RepetitionRange::AtLeast(min))
+                | RepetitionKind::Range(RepetitionRange::Bounded(min, _)) => *min as usize,
+                _ => 0,
+            };
+            match fixed_sets(&repetition.hir) {
+                Some(sets) => (sets.repeat(minimum), false),
+                None => (Vec::new(), false),
+            }
+        }
+        HirKind::Group(group) => mandatory_sets(&group.hir),
+        _ => (Vec::new(), false),
+    }
+}
+
+/// Check whether an item is an anchor at the start of the fingerprint
+fn is_start_anchor(hir: &Hir) -> bool {
+    matches!(
+        hir.kind(),
+        HirKind::Anchor(Anchor::StartText) | HirKind::Anchor(Anchor::StartLine)
+    )
+}
+
+/// Check whether an item is an anchor at the end of the fingerprint
+fn is_end_anchor(hir: &Hir) -> bool {
+    matches!(
+        hir.kind(),
+        HirKind::Anchor(Anchor::EndText) | HirKind::Anchor(Anchor::EndLine)
+    )
+}
+
+/// Build a mask from per-position nibble sets, `None` if some position allows no nibble at all
+fn sets_to_mask(sets: &[NibbleSet], offset: usize) -> Option<NibbleMask> {
+    let mut mask = NibbleMask::new();
+    for (position, set) in sets.iter().enumerate() {
+        match set.count_ones() {
+            0 => return None,
+            1 => mask.set(offset + position, set.trailing_zeros() as u8),
+            _ => {}
+        }
+    }
+    Some(mask)
+}
+
+/// Analyse one branch of the top level alternation
+///
+/// Returns the masks for the start and the end of the digest, or `None` if the branch can never
+/// match. Both masks pass everything if it has no anchored constraints.
+fn analyse_branch(branch: &Hir) -> Option<(NibbleMask, NibbleMask)> {
+    let single = [branch.clone()];
+    let items: &[Hir] = match branch.kind() {
+        HirKind::Concat(items) => items,
+        _ => &single,
+    };
+
+    let mut prefix: Vec<NibbleSet> = Vec::new();
+    if items.first().is_some_and(is_start_anchor) {
+        for item in &items[1..] {
+            let (mut sets, complete) = mandatory_sets(item);
+            prefix.append(&mut sets);
+            if !complete {
+                break;
+            }
+        }
+    }
+
+    let mut suffix: Vec<NibbleSet> = Vec::new();
+    if items.last().is_some_and(is_end_anchor) {
+        for item in items[..items.len() - 1].iter().rev() {
+            let (mut sets, complete) = mandatory_sets(item);
+            sets.append(&mut suffix);
+            suffix = sets;
+            if !complete {
+                break;
+            }
+        }
+    }
+
+    if prefix.len() > MAX_FINGERPRINT_NIBBLES || suffix.len() > MAX_FINGERPRINT_NIBBLES {
+        return None;
+    }
+    Some((
+        sets_to_mask(&prefix, 0)?,
+        sets_to_mask(&suffix, MAX_FINGERPRINT_NIBBLES - suffix.len())?,
+    ))
+}
+
+impl Default for NibbleMask {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NibbleMask {
+    /// Create a mask without constraints
+    pub fn new() -> Self {
+        Self {
+            mask: [0; MAX_FINGERPRINT_BYTES],
+            value: [0; MAX_FINGERPRINT_BYTES],
+            start: MAX_FINGERPRINT_BYTES,
+            end: 0,
+        }
+    }
+
+    /// Require the nibble at `position` (counted from the start of the hex string) to be `nibble`
+    pub fn set(&mut self, position: usize, nibble: u8) {
+        let index = position / 2;
+        let (mask, value) = if position & 1 == 0 {
+            (0xF0, (nibble & 0xF) << 4)
+        } else {
+            (0x0F, nibble & 0xF)
+        };
+        self.mask[index] |= mask;
+        self.value[index] = (self.value[index] & !mask) | value;
+        self.start = self.start.min(index);
+        self.end = self.end.max(index + 1);
+    }
+
+    /// Check whether the mask has no constraints at all
+    pub fn is_empty(&self) -> bool {
+        self.start >= self.end
+    }
+
+    /// Test the digest against the mask
+    ///
+    /// Digests too short for the constrained positions don't match.
+    #[inline]
+    pub fn is_match(&self, digest: &[u8]) -> bool {
+        self.is_match_shifted(digest, 0)
+    }
+
+    /// Test the end of the digest against a mask built for a digest of `MAX_FINGERPRINT_BYTES`
+    #[inline]
+    pub fn is_match_end(&self, digest: &[u8]) -> bool {
+        match MAX_FINGERPRINT_BYTES.checked_sub(digest.len()) {
+            Some(shift) => self.is_match_shifted(digest, shift),
+            None => false,
+        }
+    }
+
+    /// Test the digest against the mask moved `shift` bytes towards the start
+    #[inline(always)]
+    fn is_match_shifted(&self, digest: &[u8], shift: usize) -> bool {
+        if self.start >= self.end {
+            return true;
+        }
+        if self.start < shift || self.end - shift > digest.len() {
+            return false;
+        }
+        self.mask[self.start..self.end]
+            .iter()
+            .zip(&self.value[self.start..self.end])
+            .zip(&digest[self.start - shift..self.end - shift])
+            .all(|((mask, value), byte)| byte & mask == *value)
+    }
+}
+
+impl NibblePrefilter {
+    /// Build a prefilter from a regex pattern
+    ///
+    /// Returns `None` if the pattern could not be parsed or does not pin any nibble in at least
+    /// one of its branches, since the prefilter would accept everything in that case.
+    pub fn from_pattern(pattern: &str) -> Option<Self> {
+        let hir = Parser::new().parse(pattern).ok()?;
+        let branches = match hir.kind() {
+            HirKind::Alternation(branches) => branches.clone(),
+            HirKind::Group(group) => match group.hir.kind() {
+                HirKind::Alternation(branches) => branches.clone(),
+                _ => vec![hir.clone()],
+            },
+            _ => vec![hir.clone()],
+        };
+        let mut masks = Vec::new();
+        for branch in &branches {
+            match analyse_branch(branch) {
+                Some((head, tail)) if head.is_empty() && tail.is_empty() => return None,
+                Some(pair) => masks.push(pair),
+                None => {}
+            }
+        }
+        Some(Self { masks })
+    }
+
+    /// Get the masks for the start and the end of the digest of every branch
+    pub fn masks(&self) -> &[(NibbleMask, NibbleMask)] {
+        &self.masks
+    }
+
+    /// Test whether the digest may match the pattern
+    #[inline]
+    pub fn is_match(&self, digest: &[u8]) -> bool {
+        self.masks
+            .iter()
+            .any(|(head, tail)| head.is_match(digest) && tail.is_match_end(digest))
+    }
+}
+
+impl NibbleMirror {
+    /// Require the last `length` nibbles to read the same backwards
+    pub fn tail(length: usize) -> Self {
+        Self {
+            length: length.min(MAX_FINGERPRINT_NIBBLES),
+        }
+    }
+
+    /// Number of mirrored nibbles
+    pub fn length(&self) -> usize {
+        self.length
+    }
+
+    /// Probability for a random fingerprint of `bytes` bytes to match
+    pub fn match_probability(&self, bytes: usize) -> f64 {
+        16f64.powi(-((self.length.min(bytes * 2) / 2) as i32))
+    }
+
+    /// Test the binary fingerprint
+    #[inline]
+    pub fn is_match(&self, digest: &[u8]) -> bool {
+        let nibble = |position: usize| {
+            let byte = digest[position / 2];
+            if position & 1 == 0 {
+                byte >> 4
+            } else {
+                byte & 0xF
+            }
+        };
+        let end = digest.len() * 2;
+        let (mut left, mut right) = (end.saturating_sub(self.length), end.saturating_sub(1));
+        while left < right {
+            if nibble(left) != nibble(right) {
+                return false;
+            }
+            left += 1;
+            right -= 1;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod nibble_test {
+    use super::{NibbleMask, NibbleMirror, NibblePrefilter};
+    use crate::matcher::test_util::{digests, README_PATTERN};
+    use crate::pgp_backends::sha1_to_hex;
+    use regex::Regex;
+
+    /// Seed of the random digests
+    const SEED: u64 = 0x0123_4567_89AB_CDEF;
+
+    #[test]
+    fn mask_set() {
+        let mut mask = NibbleMask::new();
+        assert!(mask.is_empty());
+        mask.set(39, 0xA);
+        mask.set(0, 0x1);
+        assert!(mask.is_match(&[0x10, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x0A]));
+        assert!(!mask.is_match(&[0x01, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x0A]));
+    }
+
+    #[test]
+    fn unanchored_patterns_are_not_filtered() {
+        assert!(NibblePrefilter::from_pattern("DEAD").is_none());
+        assert!(NibblePrefilter::from_pattern("A{10}$|BEEF").is_none());
+        assert!(NibblePrefilter::from_pattern("[0-9]{4}$").is_none());
+        assert!(NibblePrefilter::from_pattern("(").is_none());
+    }
+
+    #[test]
+    fn anchored_patterns() {
+        let prefilter = NibblePrefilter::from_pattern("^DEAD.*BEEF$").unwrap();
+        let mut digest = [0u8; 20];
+        digest[0] = 0xDE;
+        digest[1] = 0xAD;
+        assert!(!prefilter.is_match(&digest));
+        digest[18] = 0xBE;
+        digest[19] = 0xEF;
+        assert!(prefilter.is_match(&digest));
+
+        let prefilter = NibblePrefilter::from_pattern("(8B){5,20}$").unwrap();
+        let mut digest = [0u8; 20];
+        for byte in digest[15..].iter_mut() {
+            *byte = 0x8B;
+        }
+        assert!(prefilter.is_match(&digest));
+        digest[15] = 0x8C;
+        assert!(!prefilter.is_match(&digest));
+    }
+
+    #[test]
+    fn anchored_patterns_v6() {
+        let prefilter = NibblePrefilter::from_pattern("^DEAD.*BEEF$").unwrap();
+        let mut digest = [0u8; 32];
+        digest[0] = 0xDE;
+        digest[1] = 0xAD;
+        assert!(!prefilter.is_match(&digest));
+        digest[30] = 0xBE;
+        digest[31] = 0xEF;
+        assert!(prefilter.is_match(&digest));
+
+        // Suffixes longer than a v4 fingerprint
+        let prefilter = NibblePrefilter::from_pattern("0{48}$").unwrap();
+        assert!(prefilter.is_match(&[0u8; 32]));
+        assert!(!prefilter.is_match(&[0u8; 20]));
+    }
+
+    #[test]
+    fn impossible_branches_are_dropped() {
+        let prefilter = NibblePrefilter::from_pattern("dead$|^BEEF").unwrap();
+        assert_eq!(prefilter.masks().len(), 1);
+    }
+
+    #[test]
+    fn prefilter_agrees_with_regex() {
+        let regex = Regex::new(README_PATTERN).unwrap();
+        let prefilter = NibblePrefilter::from_pattern(README_PATTERN).unwrap();
+        let mut samples = digests(SEED, 100000);
+        // Plant a few matches
+        samples[0][15..].copy_from_slice(&[0xBE; 5]);
+        samples[1][15..].copy_from_slice(&[0xAA; 5]);
+        samples[2][13..].copy_from_slice(&[0x01, 0x14, 0x51, 0x41, 0x91, 0x98, 0x10]);
+        for digest in samples.iter() {
+            if regex.is_match(&sha1_to_hex(digest)) {
+                assert!(prefilter.is_match(digest));
+            }
+        }
+        assert!(prefilter.is_match(&samples[0]));
+        assert!(prefilter.is_match(&samples[1]));
+        assert!(prefilter.is_match(&samples[2]));
+    }
+
+    #[test]
+    fn mirror() {
+        let mirror = NibbleMirror::tail(5);
+        assert_eq!(mirror.length(), 5);
+        assert_eq!(mirror.match_probability(20), 1.0 / 256.0);
+        let mut digest = [0u8; 20];
+        digest[17..].copy_from_slice(&[0x0A, 0xBC, 0xBA]);
+        assert!(mirror.is_match(&digest));
+        assert!(!NibbleMirror::tail(6).is_match(&digest));
+        digest[19] = 0xBB;
+        assert!(!mirror.is_match(&digest));
+
+        let mirror = NibbleMirror::tail(6);
+        for digest in digests(SEED, 1000) {
+            let hex = sha1_to_hex(&digest);
+            let tail: String = hex[34..].chars().rev().collect();
+            assert_eq!(mirror.is_match(&digest), hex[34..] == tail);
+        }
+
+        // The tail of longer fingerprints
+        let mut digest = [0u8; 32];
+        digest[29..].copy_from_slice(&[0x0A, 0xBC, 0xBA]);
+        assert!(NibbleMirror::tail(5).is_match(&digest));
+        digest[0] = 0xFF;
+        assert!(NibbleMirror::tail(5).is_match(&digest));
+        assert!(!NibbleMirror::tail(64).is_match(&digest));
+        assert_eq!(
+            NibbleMirror::tail(64).match_probability(20),
+            16f64.powi(-20)
+        );
+    }
+}
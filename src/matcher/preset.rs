@@ -0,0 +1,321 @@
+//! Built-in pattern presets
+//!
+//! Presets are shorthands for commonly wanted patterns (`--preset repeat-tail:10`), so nobody has
+//! to type the long alternations from the README again. They expand into regular named patterns.
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::score::HEXSPEAK_WORDS;
+use super::{
+    MatchTarget, MatcherError, NamedPattern, NibbleMirror, PatternMatcher, MAX_FINGERPRINT_NIBBLES,
+};
+
+/// Hex digits in ascending order
+const HEX_DIGITS: &str = "0123456789ABCDEF";
+
+/// A built-in pattern with its parameter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// The last N nibbles are the same
+    RepeatTail(usize),
+    /// The first N nibbles are the same
+    RepeatHead(usize),
+    /// Starts or ends with a hexspeak word of at least N nibbles
+    Hexspeak(usize),
+    /// The last N nibbles are a palindrome
+    PalindromeTail(usize),
+    /// N ascending consecutive hex digits anywhere (e.g. `01234567`)
+    AscendingRun(usize),
+}
+
+impl Preset {
+    /// Every preset with its default parameter
+    pub const DEFAULTS: [Preset; 5] = [
+        Preset::RepeatTail(10),
+        Preset::RepeatHead(8),
+        Preset::Hexspeak(6),
+        Preset::PalindromeTail(12),
+        Preset::AscendingRun(8),
+    ];
+
+    /// Get the name used on the commandline
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preset::RepeatTail(_) => "repeat-tail",
+            Preset::RepeatHead(_) => "repeat-head",
+            Preset::Hexspeak(_) => "hexspeak",
+            Preset::PalindromeTail(_) => "palindrome-tail",
+            Preset::AscendingRun(_) => "ascending-run",
+        }
+    }
+
+    /// Get the parameter
+    pub fn parameter(&self) -> usize {
+        match self {
+            Preset::RepeatTail(length)
+            | Preset::RepeatHead(length)
+            | Preset::Hexspeak(length)
+            | Preset::PalindromeTail(length)
+            | Preset::AscendingRun(length) => *length,
+        }
+    }
+
+    /// Human readable description
+    pub fn description(&self) -> String {
+        match self {
+            Preset::RepeatTail(length) => format!("last {} characters are the same", length),
+            Preset::RepeatHead(length) => format!("first {} characters are the same", length),
+            Preset::Hexspeak(length) => format!(
+                "starts or ends with a hexspeak word of at least {} characters",
+                length
+            ),
+            Preset::PalindromeTail(length) => {
+                format!("last {} characters are a palindrome", length)
+            }
+            Preset::AscendingRun(length) => format!("{} ascending hex digits in a row", length),
+        }
+    }
+
+    /// Valid parameters
+    fn range(&self) -> (usize, usize) {
+        match self {
+            Preset::RepeatTail(_) | Preset::RepeatHead(_) => (1, MAX_FINGERPRINT_NIBBLES),
+            Preset::Hexspeak(_) => (
+                3,
+                HEXSPEAK_WORDS.iter().map(|word| word.len()).max().unwrap(),
+            ),
+            Preset::PalindromeTail(_) => (2, MAX_FINGERPRINT_NIBBLES),
+            Preset::AscendingRun(_) => (2, HEX_DIGITS.len()),
+        }
+    }
+
+    /// Same preset with another parameter
+    fn with_parameter(&self, parameter: usize) -> Self {
+        match self {
+            Preset::RepeatTail(_) => Preset::RepeatTail(parameter),
+            Preset::RepeatHead(_) => Preset::RepeatHead(parameter),
+            Preset::Hexspeak(_) => Preset::Hexspeak(parameter),
+            Preset::PalindromeTail(_) => Preset::PalindromeTail(parameter),
+            Preset::AscendingRun(_) => Preset::AscendingRun(parameter),
+        }
+    }
+
+    /// Expand into a named pattern for a rendering of a fingerprint of `bytes` bytes
+    pub fn to_pattern(
+        &self,
+        target: MatchTarget,
+        bytes: usize,
+    ) -> Result<NamedPattern, MatcherError> {
+        if self.parameter() > target.nibbles(bytes) {
+            return Err(MatcherError::InvalidPreset(format!(
+                "{} (longer than the {} rendering)",
+                self, target
+            )));
+        }
+        let name = format!("{}-{}", self.name(), self.parameter());
+        let pattern = match self {
+            Preset::RepeatTail(length) => format!("(?:{})$", repeats(*length)),
+            Preset::RepeatHead(length) => format!("^(?:{})", repeats(*length)),
+            Preset::Hexspeak(length) => {
+                let words = HEXSPEAK_WORDS
+                    .iter()
+                    .filter(|word| word.len() >= *length)
+                    .copied()
+                    .collect::<Vec<&str>>()
+                    .join("|");
+                format!("^(?:{0})|(?:{0})$", words)
+            }
+            Preset::PalindromeTail(length) => {
+                return Ok(NamedPattern::from_matcher(
+                    name,
+                    format!("palindrome over the last {} characters", length),
+                    PatternMatcher::Mirror {
+                        mirror: NibbleMirror::tail(*length),
+                        target,
+                    },
+                ))
+            }
+            Preset::AscendingRun(length) => (0..=HEX_DIGITS.len() - length)
+                .map(|start| &HEX_DIGITS[start..start + length])
+                .collect::<Vec<&str>>()
+                .join("|"),
+        };
+        Ok(NamedPattern::with_target(name, pattern, target)?)
+    }
+}
+
+/// Alternation of every hex digit repeated `length` times
+fn repeats(length: usize) -> String {
+    HEX_DIGITS
+        .chars()
+        .map(|c| format!("{}{{{}}}", c, length))
+        .collect::<Vec<String>>()
+        .join("|")
+}
+
+impl FromStr for Preset {
+    type Err = MatcherError;
+
+    /// Parse `NAME` or `NAME:PARAMETER`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, parameter) = match s.split_once(':') {
+            Some((name, parameter)) => (name, Some(parameter)),
+            None => (s, None),
+        };
+        let preset = Preset::DEFAULTS
+            .iter()
+            .find(|preset| preset.name() == name.to_lowercase())
+            .ok_or_else(|| MatcherError::InvalidPreset(s.to_string()))?;
+        let parameter = match parameter {
+            Some(parameter) => parameter
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| MatcherError::InvalidPreset(s.to_string()))?,
+            None => return Ok(*preset),
+        };
+        let (min, max) = preset.range();
+        if parameter < min || parameter > max {
+            return Err(MatcherError::InvalidPreset(format!(
+                "{} (parameter must be between {} and {})",
+                s, min, max
+            )));
+        }
+        Ok(preset.with_parameter(parameter))
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.name(), self.parameter())
+    }
+}
+
+#[cfg(test)]
+mod preset_test {
+    use super::Preset;
+    use crate::matcher::test_util::digest;
+    use crate::matcher::MatchTarget;
+
+    #[test]
+    fn parse() {
+        for preset in Preset::DEFAULTS.iter() {
+            assert_eq!(&preset.name().parse::<Preset>().unwrap(), preset);
+            assert_eq!(&preset.to_string().parse::<Preset>().unwrap(), preset);
+        }
+        assert_eq!(
+            "repeat-tail:12".parse::<Preset>().unwrap(),
+            Preset::RepeatTail(12)
+        );
+        assert!("repeat-tail:0".parse::<Preset>().is_err());
+        assert!("repeat-tail:65".parse::<Preset>().is_err());
+        assert!("ascending-run:17".parse::<Preset>().is_err());
+        assert!("repeat-tail:x".parse::<Preset>().is_err());
+        assert!("nonexistent".parse::<Preset>().is_err());
+    }
+
+    #[test]
+    fn expand() {
+        let cases = [
+            (
+                Preset::RepeatTail(10),
+                "0123456789ABCDEF0123456789AAAAAAAAAAAAAA",
+                "0123456789ABCDEF0123456789ABCDAAAAAAAAA0",
+            ),
+            (
+                Preset::RepeatHead(8),
+                "7777777789ABCDEF0123456789ABCDEF01234567",
+                "7777777089ABCDEF0123456789ABCDEF01234567",
+            ),
+            (
+                Preset::Hexspeak(6),
+                "0123456789ABCDEF0123456789ABCDEF01C0FFEE",
+                "0123456789ABCDEF0123C0FFEE89ABCDEF012345",
+            ),
+            (
+                Preset::PalindromeTail(12),
+                "0123456789ABCDEF0123456789AB123456654321",
+                "0123456789ABCDEF0123456789AB123456654320",
+            ),
+            (
+                Preset::AscendingRun(8),
+                "0000000000000000456789AB0000000000000000",
+                "0000000000000000456789A00000000000000000",
+            ),
+        ];
+        for (preset, hit, miss) in cases.iter() {
+            let pattern = preset.to_pattern(MatchTarget::Fingerprint, 20).unwrap();
+            assert!(pattern.matcher().is_match(&digest(hit)), "{}", preset);
+            assert!(!pattern.matcher().is_match(&digest(miss)), "{}", preset);
+        }
+    }
+
+    #[test]
+    fn difficulty() {
+        let probability = |preset: Preset| {
+            preset
+                .to_pattern(MatchTarget::Fingerprint, 20)
+                .unwrap()
+                .matcher()
+                .match_probability(20)
+                .unwrap()
+        };
+        let expected = 16f64.powi(-9);
+        assert!((probability(Preset::RepeatTail(10)) - expected).abs() < expected * 1e-9);
+        assert_eq!(probability(Preset::PalindromeTail(12)), 16f64.powi(-6));
+        assert!(probability(Preset::RepeatTail(10)) > probability(Preset::RepeatTail(11)));
+        assert!(probability(Preset::AscendingRun(8)) > probability(Preset::AscendingRun(9)));
+        assert!(probability(Preset::Hexspeak(6)) > probability(Preset::Hexspeak(8)));
+    }
+
+    #[test]
+    fn target() {
+        let pattern = Preset::RepeatHead(4)
+            .to_pattern(MatchTarget::KeyIdShort, 20)
+            .unwrap();
+        let mut digest = [0u8; 20];
+        digest[16..].copy_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        assert!(!pattern.matcher().is_match(&digest));
+        digest[16..].copy_from_slice(&[0x77, 0x77, 0x12, 0x34]);
+        assert!(pattern.matcher().is_match(&digest));
+        assert!(Preset::PalindromeTail(12)
+            .to_pattern(MatchTarget::KeyIdShort, 20)
+            .is_err());
+
+        // Key IDs of v5 and v6 keys are the head of the fingerprint
+        let pattern = Preset::RepeatHead(4)
+            .to_pattern(MatchTarget::KeyIdLong, 32)
+            .unwrap();
+        let mut digest = [0u8; 32];
+        digest[..2].copy_from_slice(&[0x12, 0x34]);
+        assert!(!pattern.matcher().is_match(&digest));
+        digest[..2].copy_from_slice(&[0xAA, 0xAA]);
+        assert!(pattern.matcher().is_match(&digest));
+        let pattern = Preset::PalindromeTail(8)
+            .to_pattern(MatchTarget::KeyIdShort, 32)
+            .unwrap();
+        assert_eq!(pattern.matcher().target(), MatchTarget::KeyIdShort);
+        let mut digest = [0x01u8; 32];
+        digest[28..].copy_from_slice(&[0x12, 0x34, 0x43, 0x21]);
+        assert!(!pattern.matcher().is_match(&digest));
+        digest[..4].copy_from_slice(&[0x12, 0x34, 0x43, 0x21]);
+        assert!(pattern.matcher().is_match(&digest));
+        digest[0] = 0x13;
+        assert!(!pattern.matcher().is_match(&digest));
+        assert_eq!(
+            pattern.matcher().match_probability(32),
+            Some(16f64.powi(-4))
+        );
+        let mut digest = [0x01u8; 20];
+        digest[..4].copy_from_slice(&[0x12, 0x34, 0x43, 0x21]);
+        assert!(!pattern.matcher().is_match(&digest));
+        digest[16..].copy_from_slice(&[0x12, 0x34, 0x43, 0x21]);
+        assert!(pattern.matcher().is_match(&digest));
+        assert!(Preset::RepeatTail(41)
+            .to_pattern(MatchTarget::Fingerprint, 20)
+            .is_err());
+        assert!(Preset::RepeatTail(41)
+            .to_pattern(MatchTarget::Fingerprint, 32)
+            .is_ok());
+    }
+}
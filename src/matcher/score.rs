@@ -0,0 +1,446 @@
+//! Fingerprint scoring
+//!
+//! Instead of a fixed pattern, every candidate gets a score and only the best ones are kept.
+//! Scorers work on the raw digest, since they run for every single candidate.
+
+use std::cmp::{Ordering as CmpOrdering, Reverse};
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use super::{MatcherError, MAX_FINGERPRINT_NIBBLES};
+use crate::pgp_backends::Digest;
+
+/// Words that can be spelled with hex digits (plus `0` for `O`, `1` for `I`, `5` for `S`)
+pub(crate) const HEXSPEAK_WORDS: &[&str] = &[
+    "ACE", "ADD", "BAD", "BED", "BEE", "CAB", "DAD", "FAB", "FAD", "FEE", "B0B", "ABBA", "ACED",
+    "BABE", "BADE", "BEAD", "BEEF", "CAFE", "CEDE", "C0DE", "C0FFEE", "DEAD", "DEAF", "DECAF",
+    "DECADE", "DEED", "FACE", "FADE", "FEED", "F00D", "B00B", "0DD", "5AFE", "5EED", "C0C0A",
+    "DEC0DE", "FACADE", "EFFACE", "A11", "1DEA", "DEFACE", "C0DEC", "ACCE55", "BA5E", "CA5E",
+    "0FF1CE", "BADC0DE", "D15EA5E", "DEADBEEF", "DEADC0DE", "FEEDFACE", "CAFEBABE", "5CA1AB1E",
+];
+
+/// Built-in scorers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scorer {
+    /// Longest run of a single repeated nibble anywhere
+    Run,
+    /// Longest run of a single repeated nibble at the start or at the end
+    EdgeRun,
+    /// Longest palindromic substring
+    Palindrome,
+    /// Number of nibbles covered by non-overlapping hexspeak words
+    Hexspeak,
+}
+
+/// Hexspeak words indexed by their first three nibbles
+#[derive(Debug, Clone)]
+struct HexspeakTable {
+    words: Vec<Vec<Vec<u8>>>,
+}
+
+/// An entry on the leaderboard
+#[derive(Debug)]
+pub struct LeaderboardEntry<T> {
+    score: u32,
+    digest: Digest,
+    /// Identifies the key material of the candidate
+    key: u64,
+    payload: T,
+}
+
+/// Bounded collection of the best scored candidates, shared between threads
+///
+/// Candidates of the same key material only differ in their creation time, so at most one of
+/// them is kept, the best scored.
+#[derive(Debug)]
+pub struct Leaderboard<T> {
+    capacity: usize,
+    threshold: AtomicU32,
+    entries: Mutex<BinaryHeap<Reverse<LeaderboardEntry<T>>>>,
+}
+
+/// Split a digest into nibbles, only the first `2 * digest.len()` are used
+fn to_nibbles(digest: &[u8]) -> [u8; MAX_FINGERPRINT_NIBBLES] {
+    let mut nibbles = [0u8; MAX_FINGERPRINT_NIBBLES];
+    for (byte, slots) in digest.iter().zip(nibbles.chunks_mut(2)) {
+        slots[0] = byte >> 4;
+        slots[1] = byte & 0xF;
+    }
+    nibbles
+}
+
+/// Length of the longest run of identical nibbles
+fn longest_run(nibbles: &[u8]) -> u32 {
+    let mut longest = 0;
+    let mut current = 0;
+    let mut previous = None;
+    for nibble in nibbles {
+        if previous == Some(*nibble) {
+            current += 1;
+        } else {
+            current = 1;
+            previous = Some(*nibble);
+        }
+        longest = longest.max(current);
+    }
+    longest
+}
+
+/// Length of the run of identical nibbles at the start
+fn leading_run<'a, I: Iterator<Item = &'a u8>>(mut nibbles: I) -> u32 {
+    match nibbles.next() {
+        Some(first) => 1 + nibbles.take_while(|nibble| *nibble == first).count() as u32,
+        None => 0,
+    }
+}
+
+/// Length of the longest palindromic substring, by expanding around every center
+fn longest_palindrome(nibbles: &[u8]) -> u32 {
+    let length = nibbles.len();
+    let mut longest = 0;
+    for center in 0..(2 * length).saturating_sub(1) {
+        let mut left = center / 2;
+        let mut right = left + center % 2;
+        if nibbles[left] != nibbles[right] {
+            continue;
+        }
+        while left > 0 && right + 1 < length && nibbles[left - 1] == nibbles[right + 1] {
+            left -= 1;
+            right += 1;
+        }
+        longest = longest.max(right - left + 1);
+    }
+    longest as u32
+}
+
+impl HexspeakTable {
+    /// Build the lookup table from the built-in word list
+    fn new() -> Self {
+        let mut words = vec![Vec::new(); 1 << 12];
+        for word in HEXSPEAK_WORDS {
+            let nibbles: Vec<u8> = word
+                .chars()
+                .map(|c| c.to_digit(16).expect("Invalid hexspeak word") as u8)
+                .collect();
+            let key =
+                ((nibbles[0] as usize) << 8) | ((nibbles[1] as usize) << 4) | nibbles[2] as usize;
+            words[key].push(nibbles);
+        }
+        Self { words }
+    }
+
+    /// Maximum number of nibbles covered by non-overlapping words
+    fn coverage(&self, nibbles: &[u8]) -> u32 {
+        // best[i]: best coverage of the first i nibbles
+        let mut best = [0u32; MAX_FINGERPRINT_NIBBLES + 1];
+        for start in 0..nibbles.len() {
+            best[start + 1] = best[start + 1].max(best[start]);
+            if start + 3 > nibbles.len() {
+                continue;
+            }
+            let key = ((nibbles[start] as usize) << 8)
+                | ((nibbles[start + 1] as usize) << 4)
+                | nibbles[start + 2] as usize;
+            for word in &self.words[key] {
+                let end = start + word.len();
+                if end <= nibbles.len() && nibbles[start..end] == word[..] {
+                    best[end] = best[end].max(best[start] + word.len() as u32);
+                }
+            }
+        }
+        best[nibbles.len()]
+    }
+}
+
+/// Lazily built hexspeak table
+fn hexspeak_table() -> &'static HexspeakTable {
+    static TABLE: std::sync::OnceLock<HexspeakTable> = std::sync::OnceLock::new();
+    TABLE.get_or_init(HexspeakTable::new)
+}
+
+impl Scorer {
+    /// All built-in scorers
+    pub const ALL: [Scorer; 4] = [
+        Scorer::Run,
+        Scorer::EdgeRun,
+        Scorer::Palindrome,
+        Scorer::Hexspeak,
+    ];
+
+    /// Get the name used on the commandline
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scorer::Run => "run",
+            Scorer::EdgeRun => "edge-run",
+            Scorer::Palindrome => "palindrome",
+            Scorer::Hexspeak => "hexspeak",
+        }
+    }
+
+    /// Score a digest, higher is better
+    #[inline]
+    pub fn score(&self, digest: &[u8]) -> u32 {
+        let all = to_nibbles(digest);
+        let nibbles = &all[..(digest.len() * 2).min(MAX_FINGERPRINT_NIBBLES)];
+        match self {
+            Scorer::Run => longest_run(nibbles),
+            Scorer::EdgeRun => leading_run(nibbles.iter()).max(leading_run(nibbles.iter().rev())),
+            Scorer::Palindrome => longest_palindrome(nibbles),
+            Scorer::Hexspeak => hexspeak_table().coverage(nibbles),
+        }
+    }
+}
+
+impl FromStr for Scorer {
+    type Err = MatcherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scorer::ALL
+            .iter()
+            .find(|scorer| scorer.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| MatcherError::ScorerNotSupported(s.to_string()))
+    }
+}
+
+impl fmt::Display for Scorer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl<T> PartialEq for LeaderboardEntry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == CmpOrdering::Equal
+    }
+}
+
+impl<T> Eq for LeaderboardEntry<T> {}
+
+impl<T> PartialOrd for LeaderboardEntry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<CmpOrdering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for LeaderboardEntry<T> {
+    fn cmp(&self, other: &Self) -> CmpOrdering {
+        self.score
+            .cmp(&other.score)
+            .then_with(|| self.digest.cmp(&other.digest))
+    }
+}
+
+impl<T> LeaderboardEntry<T> {
+    /// Get the score
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    /// Get the fingerprint
+    pub fn fingerprint(&self) -> String {
+        self.digest.to_hex()
+    }
+
+    /// Take the payload
+    pub fn into_payload(self) -> T {
+        self.payload
+    }
+}
+
+impl<T> Leaderboard<T> {
+    /// Create a leaderboard keeping the best `capacity` entries
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            threshold: AtomicU32::new(0),
+            entries: Mutex::new(BinaryHeap::with_capacity(capacity + 1)),
+        }
+    }
+
+    /// Lowest score that is still rejected, cheap to check without locking
+    #[inline]
+    pub fn threshold(&self) -> u32 {
+        self.threshold.load(Ordering::Relaxed)
+    }
+
+    /// Offer a candidate of the key material `key`, `payload` is only called if the candidate
+    /// makes it onto the board
+    ///
+    /// A candidate replaces the entry of the same key material if it scores higher.
+    pub fn offer<F: FnOnce() -> T>(&self, score: u32, digest: &[u8], key: u64, payload: F) -> bool {
+        if self.capacity == 0 || score <= self.threshold() {
+            return false;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        match entries.iter().find(|Reverse(entry)| entry.key == key) {
+            Some(Reverse(existing)) if existing.score >= score => return false,
+            Some(_) => entries.retain(|Reverse(entry)| entry.key != key),
+            None if entries.len() >= self.capacity => match entries.peek() {
+                Some(Reverse(lowest)) if lowest.score >= score => return false,
+                _ => {}
+            },
+            None => {}
+        }
+        entries.push(Reverse(LeaderboardEntry {
+            score,
+            digest: Digest::new(digest),
+            key,
+            payload: payload(),
+        }));
+        if entries.len() > self.capacity {
+            entries.pop();
+        }
+        if entries.len() >= self.capacity {
+            if let Some(Reverse(lowest)) = entries.peek() {
+                self.threshold.store(lowest.score, Ordering::Relaxed);
+            }
+        }
+        true
+    }
+
+    /// Get scores and fingerprints, best first
+    pub fn standings(&self) -> Vec<(u32, String)> {
+        let entries = self.entries.lock().unwrap();
+        let mut standings: Vec<(u32, String)> = entries
+            .iter()
+            .map(|Reverse(entry)| (entry.score, entry.fingerprint()))
+            .collect();
+        standings.sort_by(|a, b| b.cmp(a));
+        standings
+    }
+
+    /// Take all entries, best first
+    pub fn take(&self) -> Vec<LeaderboardEntry<T>> {
+        let mut entries = self.entries.lock().unwrap();
+        let heap = std::mem::take(&mut *entries);
+        self.threshold.store(0, Ordering::Relaxed);
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(entry)| entry)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod score_test {
+    use super::{Leaderboard, Scorer};
+    use crate::matcher::test_util::digest;
+
+    #[test]
+    fn run() {
+        let scorer = Scorer::Run;
+        assert_eq!(
+            scorer.score(&digest("0123456789ABCDEF0123456789ABCDEF01234567")),
+            1
+        );
+        assert_eq!(
+            scorer.score(&digest("0123456789AAAAAAAAA56789ABCDEF0123456788")),
+            9
+        );
+        assert_eq!(scorer.score(&[0u8; 20]), 40);
+    }
+
+    #[test]
+    fn edge_run() {
+        let scorer = Scorer::EdgeRun;
+        assert_eq!(
+            scorer.score(&digest("0123456789AAAAAAAAA56789ABCDEF0123456788")),
+            2
+        );
+        assert_eq!(
+            scorer.score(&digest("FFF3456789AAAAAAAAA56789ABCDEF0123456788")),
+            3
+        );
+        assert_eq!(scorer.score(&[0u8; 20]), 40);
+    }
+
+    #[test]
+    fn palindrome() {
+        let scorer = Scorer::Palindrome;
+        assert_eq!(
+            scorer.score(&digest("0123456789ABCDEF0123456789ABCDEF01234567")),
+            1
+        );
+        assert_eq!(
+            scorer.score(&digest("0123456789ABCDEF01234ABCBA56789CDEF01234")),
+            5
+        );
+        assert_eq!(
+            scorer.score(&digest("0123456789ABCDEF01234ABBA89ABCDEF0123456")),
+            4
+        );
+        assert_eq!(
+            scorer.score(&digest("12345678900987654321ABCDEF0123ABCDEF0123")),
+            20
+        );
+    }
+
+    #[test]
+    fn hexspeak() {
+        let scorer = Scorer::Hexspeak;
+        assert_eq!(
+            scorer.score(&digest("DEADBEEF12345678901234567890123456789012")),
+            8
+        );
+        assert_eq!(
+            scorer.score(&digest("C0FFEE1234567890123456789012345678CAFE12")),
+            10
+        );
+        assert_eq!(
+            scorer.score(&digest("1234567890123456789012345678901234567890")),
+            0
+        );
+    }
+
+    #[test]
+    fn parse() {
+        for scorer in Scorer::ALL.iter() {
+            assert_eq!(&scorer.name().parse::<Scorer>().unwrap(), scorer);
+        }
+        assert!("nope".parse::<Scorer>().is_err());
+    }
+
+    #[test]
+    fn leaderboard() {
+        let leaderboard: Leaderboard<u32> = Leaderboard::new(3);
+        for score in [5, 1, 7, 3, 9, 2] {
+            let mut digest = [0u8; 20];
+            digest[0] = score as u8;
+            leaderboard.offer(score, &digest, score as u64, || score);
+        }
+        assert_eq!(leaderboard.threshold(), 5);
+        assert!(!leaderboard.offer(4, &[0u8; 20], 4, || unreachable!()));
+        let standings: Vec<u32> = leaderboard.standings().iter().map(|(s, _)| *s).collect();
+        assert_eq!(standings, vec![9, 7, 5]);
+        let payloads: Vec<u32> = leaderboard
+            .take()
+            .into_iter()
+            .map(|entry| entry.into_payload())
+            .collect();
+        assert_eq!(payloads, vec![9, 7, 5]);
+    }
+
+    #[test]
+    fn leaderboard_per_key() {
+        let leaderboard: Leaderboard<u32> = Leaderboard::new(3);
+        for (score, key) in [(5, 1), (6, 2), (7, 1), (3, 3), (4, 3)] {
+            let mut digest = [0u8; 20];
+            digest[0] = score as u8;
+            leaderboard.offer(score, &digest, key, || score);
+        }
+        // Worse candidates of a key already on the board are rejected
+        assert!(!leaderboard.offer(6, &[6u8; 20], 1, || unreachable!()));
+        let standings: Vec<u32> = leaderboard.standings().iter().map(|(s, _)| *s).collect();
+        assert_eq!(standings, vec![7, 6, 4]);
+        assert_eq!(leaderboard.threshold(), 4);
+
+        // A better candidate replaces the entry of its key instead of the lowest entry
+        assert!(leaderboard.offer(8, &[8u8; 20], 2, || 8));
+        let standings: Vec<u32> = leaderboard.standings().iter().map(|(s, _)| *s).collect();
+        assert_eq!(standings, vec![8, 7, 4]);
+    }
+}
@@ -0,0 +1,314 @@
+//! Named pattern sets
+//!
+//! All patterns are folded into a single alternation for the hot loop. The individual patterns
+//! are only evaluated once the combined one hits, to find out which of them matched. Patterns
+//! that aren't regexes (like the palindrome preset) are checked one by one after the alternation.
+
+use super::{MatchTarget, MatcherError, PatternMatcher};
+
+/// A pattern with a human readable name
+#[derive(Debug, Clone)]
+pub struct NamedPattern {
+    name: String,
+    pattern: String,
+    matcher: PatternMatcher,
+    regex: bool,
+}
+
+/// A set of named patterns
+#[derive(Debug, Clone)]
+pub struct PatternSet {
+    patterns: Vec<NamedPattern>,
+    combined: PatternMatcher,
+    separate: Vec<usize>,
+}
+
+impl NamedPattern {
+    /// Compile a named pattern for the full fingerprint
+    pub fn new<N: Into<String>, P: Into<String>>(
+        name: N,
+        pattern: P,
+    ) -> Result<Self, regex::Error> {
+        Self::with_target(name, pattern, MatchTarget::Fingerprint)
+    }
+
+    /// Compile a named pattern for a rendering of the fingerprint
+    pub fn with_target<N: Into<String>, P: Into<String>>(
+        name: N,
+        pattern: P,
+        target: MatchTarget,
+    ) -> Result<Self, regex::Error> {
+        let pattern = pattern.into();
+        let matcher = PatternMatcher::with_target(&pattern, target)?;
+        Ok(Self {
+            name: name.into(),
+            pattern,
+            matcher,
+            regex: true,
+        })
+    }
+
+    /// Wrap a matcher that wasn't compiled from a regex, the description is shown as its pattern
+    pub fn from_matcher<N: Into<String>, D: Into<String>>(
+        name: N,
+        description: D,
+        matcher: PatternMatcher,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            pattern: description.into(),
+            matcher,
+            regex: false,
+        }
+    }
+
+    /// Get the name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the source pattern
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Get the compiled matcher
+    pub fn matcher(&self) -> &PatternMatcher {
+        &self.matcher
+    }
+
+    /// Label for a matching digest, the name plus the matched word for wordlists
+    pub fn describe_match(&self, digest: &[u8]) -> String {
+        match &self.matcher {
+            PatternMatcher::Words(wordlist) => match wordlist.find(digest) {
+                Some(word) => format!("{}-{}", self.name, word.word()),
+                None => self.name.clone(),
+            },
+            _ => self.name.clone(),
+        }
+    }
+}
+
+impl PatternSet {
+    /// Build a set from named patterns, an empty set never matches
+    ///
+    /// All regex patterns have to be compiled for the same rendering.
+    pub fn new(patterns: Vec<NamedPattern>) -> Result<Self, MatcherError> {
+        let separate = patterns
+            .iter()
+            .enumerate()
+            .filter(|(_, pattern)| !pattern.regex)
+            .map(|(index, _)| index)
+            .collect::<Vec<usize>>();
+        let mut targets = patterns
+            .iter()
+            .filter(|pattern| pattern.regex)
+            .map(|pattern| pattern.matcher().target());
+        let target = match targets.next() {
+            Some(target) => target,
+            None => {
+                return Ok(Self {
+                    patterns,
+                    combined: PatternMatcher::never(MatchTarget::Fingerprint),
+                    separate,
+                })
+            }
+        };
+        if targets.any(|other| other != target) {
+            return Err(MatcherError::MixedTargets);
+        }
+        let combined = patterns
+            .iter()
+            .filter(|pattern| pattern.regex)
+            .map(|pattern| format!("(?:{})", pattern.pattern()))
+            .collect::<Vec<String>>()
+            .join("|");
+        Ok(Self {
+            combined: PatternMatcher::with_target(&combined, target)?,
+            patterns,
+            separate,
+        })
+    }
+
+    /// Get the patterns
+    pub fn patterns(&self) -> &[NamedPattern] {
+        &self.patterns
+    }
+
+    /// Get the number of patterns
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Check whether the set is empty
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Get the combined matcher
+    pub fn combined(&self) -> &PatternMatcher {
+        &self.combined
+    }
+
+    /// Probability for a random fingerprint of `bytes` bytes to match any of the patterns
+    ///
+    /// Separately checked patterns are treated as independent of the rest, so the result is only
+    /// exact when there are none.
+    pub fn match_probability(&self, bytes: usize) -> Option<f64> {
+        let mut miss = 1.0 - self.combined.match_probability(bytes)?;
+        for index in &self.separate {
+            miss *= 1.0 - self.patterns[*index].matcher().match_probability(bytes)?;
+        }
+        Some(1.0 - miss)
+    }
+
+    /// Test whether any of the patterns matches
+    #[inline]
+    pub fn is_match(&self, digest: &[u8]) -> bool {
+        self.combined.is_match(digest)
+            || self
+                .separate
+                .iter()
+                .any(|index| self.patterns[*index].matcher().is_match(digest))
+    }
+
+    /// Get the indices of every pattern that matches
+    pub fn matches(&self, digest: &[u8]) -> Vec<usize> {
+        self.patterns
+            .iter()
+            .enumerate()
+            .filter(|(_, pattern)| pattern.matcher().is_match(digest))
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod set_test {
+    use super::{NamedPattern, PatternSet};
+    use crate::matcher::{
+        LeetTable, MatchTarget, NibbleMirror, PatternMatcher, WordPosition, Wordlist,
+    };
+
+    #[test]
+    fn attribution() {
+        let set = PatternSet::new(vec![
+            NamedPattern::new("dead", "^DEAD").unwrap(),
+            NamedPattern::new("beef", "BEEF$").unwrap(),
+            NamedPattern::new("lower", "beef$").unwrap(),
+        ])
+        .unwrap();
+        let mut digest = [0u8; 20];
+        assert!(!set.is_match(&digest));
+        assert!(set.matches(&digest).is_empty());
+
+        digest[18..].copy_from_slice(&[0xBE, 0xEF]);
+        assert!(set.is_match(&digest));
+        assert_eq!(set.matches(&digest), vec![1]);
+
+        digest[..2].copy_from_slice(&[0xDE, 0xAD]);
+        assert_eq!(set.matches(&digest), vec![0, 1]);
+        assert_eq!(set.patterns()[1].name(), "beef");
+    }
+
+    #[test]
+    fn empty_set() {
+        let set = PatternSet::new(Vec::new()).unwrap();
+        assert!(!set.is_match(&[0u8; 20]));
+        assert_eq!(set.match_probability(20), Some(0.0));
+    }
+
+    #[test]
+    fn separate_matcher() {
+        let set = PatternSet::new(vec![
+            NamedPattern::new("beef", "BEEF$").unwrap(),
+            NamedPattern::from_matcher(
+                "mirror",
+                "palindrome",
+                PatternMatcher::Mirror {
+                    mirror: NibbleMirror::tail(4),
+                    target: MatchTarget::Fingerprint,
+                },
+            ),
+        ])
+        .unwrap();
+        let mut digest = [0u8; 20];
+        digest[18..].copy_from_slice(&[0xAB, 0xBA]);
+        assert_eq!(set.matches(&digest), vec![1]);
+        assert!(set.is_match(&digest));
+        digest[18..].copy_from_slice(&[0xBE, 0xEF]);
+        assert_eq!(set.matches(&digest), vec![0]);
+        assert!(set.is_match(&digest));
+        digest[18..].copy_from_slice(&[0xBE, 0xEE]);
+        assert!(!set.is_match(&digest));
+
+        let probability = set.match_probability(20).unwrap();
+        let expected = 1.0 - (1.0 - 1.0 / 65536.0) * (1.0 - 1.0 / 256.0);
+        assert!((probability - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn targets() {
+        let set = PatternSet::new(vec![
+            NamedPattern::with_target("head", "^BEEF", MatchTarget::KeyIdShort).unwrap(),
+            NamedPattern::with_target("tail", "0000$", MatchTarget::KeyIdShort).unwrap(),
+        ])
+        .unwrap();
+        let mut digest = [0u8; 20];
+        digest[16..18].copy_from_slice(&[0xBE, 0xEF]);
+        assert_eq!(set.matches(&digest), vec![0, 1]);
+        digest[0] = 0x11;
+        assert_eq!(set.matches(&digest), vec![0, 1]);
+        digest[19] = 0x11;
+        assert_eq!(set.matches(&digest), vec![0]);
+        let expected = 2.0 / 65536.0 - 1.0 / 65536.0 / 65536.0;
+        assert!((set.match_probability(20).unwrap() - expected).abs() < 1e-15);
+
+        let grouped =
+            NamedPattern::with_target("grouped", "BEEF  0", MatchTarget::Grouped).unwrap();
+        digest[8..10].copy_from_slice(&[0xBE, 0xEF]);
+        assert!(grouped.matcher().is_match(&digest));
+        digest[8..12].copy_from_slice(&[0x0B, 0xEE, 0xF0, 0x00]);
+        assert!(!grouped.matcher().is_match(&digest));
+
+        assert!(PatternSet::new(vec![
+            NamedPattern::new("fingerprint", "^BEEF").unwrap(),
+            NamedPattern::with_target("keyid", "^BEEF", MatchTarget::KeyIdLong).unwrap(),
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn describe_match() {
+        let wordlist = Wordlist::new(
+            ["coffee", "deadbeef"],
+            &LeetTable::default(),
+            4,
+            WordPosition::Suffix,
+            MatchTarget::Fingerprint,
+        )
+        .unwrap();
+        let set = PatternSet::new(vec![
+            NamedPattern::new("beef", "BEEF$").unwrap(),
+            NamedPattern::from_matcher(
+                "words",
+                "wordlist",
+                PatternMatcher::Words(Box::new(wordlist)),
+            ),
+        ])
+        .unwrap();
+        let mut digest = [0u8; 20];
+        digest[16..].copy_from_slice(&[0xDE, 0xAD, 0xBE, 0xEF]);
+        let labels: Vec<String> = set
+            .matches(&digest)
+            .iter()
+            .map(|index| set.patterns()[*index].describe_match(&digest))
+            .collect();
+        assert_eq!(labels, vec!["beef", "words-deadbeef"]);
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        assert!(NamedPattern::new("broken", "(").is_err());
+    }
+}
@@ -0,0 +1,210 @@
+//! Renderings of the fingerprint that patterns are matched against
+//!
+//! Most people see a key as its key ID or as GnuPG's grouped display rather than as the raw 40
+//! (or 64) hex digits, so patterns can be applied to any of those instead.
+//!
+//! v4 key IDs are the end of the fingerprint, while the key IDs of v5 and v6 keys are its start.
+//! The grouped rendering always covers every digit, even though GnuPG shortens the display of v5
+//! fingerprints.
+
+use std::fmt;
+use std::ops::Range;
+use std::str::FromStr;
+
+use super::{MatcherError, FINGERPRINT_BYTES};
+use crate::pgp_backends::digest_to_hex;
+
+/// Number of hex digits in a long key ID
+const KEY_ID_LONG_NIBBLES: usize = 16;
+/// Number of hex digits in a short key ID
+const KEY_ID_SHORT_NIBBLES: usize = 8;
+/// Number of hex digits in one group of the grouped display
+const GROUP_NIBBLES: usize = 4;
+
+/// What a pattern is matched against
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MatchTarget {
+    /// The full 40 (or 64) hex digit fingerprint
+    Fingerprint,
+    /// The long key ID, i.e. the last (or first) 16 hex digits
+    KeyIdLong,
+    /// The short key ID, i.e. the last (or first) 8 hex digits
+    KeyIdShort,
+    /// GnuPG's display in groups of four, with an extra space in the middle
+    Grouped,
+}
+
+impl MatchTarget {
+    /// All renderings
+    pub const ALL: [MatchTarget; 4] = [
+        MatchTarget::Fingerprint,
+        MatchTarget::KeyIdLong,
+        MatchTarget::KeyIdShort,
+        MatchTarget::Grouped,
+    ];
+
+    /// Get the name used on the commandline
+    pub fn name(&self) -> &'static str {
+        match self {
+            MatchTarget::Fingerprint => "fingerprint",
+            MatchTarget::KeyIdLong => "keyid-long",
+            MatchTarget::KeyIdShort => "keyid-short",
+            MatchTarget::Grouped => "grouped",
+        }
+    }
+
+    /// Number of hex digits in the rendering of a fingerprint of `bytes` bytes
+    pub fn nibbles(&self, bytes: usize) -> usize {
+        match self {
+            MatchTarget::Fingerprint | MatchTarget::Grouped => bytes * 2,
+            MatchTarget::KeyIdLong => KEY_ID_LONG_NIBBLES,
+            MatchTarget::KeyIdShort => KEY_ID_SHORT_NIBBLES,
+        }
+    }
+
+    /// Check whether the rendering is a plain run of hex digits
+    pub fn is_plain(&self) -> bool {
+        *self != MatchTarget::Grouped
+    }
+
+    /// Part of a binary digest of `bytes` bytes that makes up the rendering, if it is a plain run
+    /// of hex digits
+    #[inline]
+    pub fn digest_range(&self, bytes: usize) -> Option<Range<usize>> {
+        let length = self.nibbles(bytes) / 2;
+        match self {
+            MatchTarget::Grouped => None,
+            MatchTarget::Fingerprint => Some(0..bytes),
+            _ if bytes == FINGERPRINT_BYTES => Some(bytes - length..bytes),
+            _ => Some(0..length),
+        }
+    }
+
+    /// Render a hex fingerprint as returned by `Backend::fingerprint()`
+    ///
+    /// The key ID layout is picked by the length of the fingerprint.
+    pub fn render(&self, fingerprint: &str) -> String {
+        match self.digest_range(fingerprint.len() / 2) {
+            Some(range) => fingerprint
+                .get(range.start * 2..range.end * 2)
+                .unwrap_or(fingerprint)
+                .to_string(),
+            None => {
+                let mut grouped = String::with_capacity(fingerprint.len() * 5 / 4 + 1);
+                for (index, group) in fingerprint.as_bytes().chunks(GROUP_NIBBLES).enumerate() {
+                    if index > 0 {
+                        grouped.push(' ');
+                    }
+                    if index * GROUP_NIBBLES * 2 == fingerprint.len() {
+                        grouped.push(' ');
+                    }
+                    grouped.push_str(std::str::from_utf8(group).unwrap_or_default());
+                }
+                grouped
+            }
+        }
+    }
+
+    /// Render a binary digest
+    pub fn render_digest(&self, digest: &[u8]) -> String {
+        match self.digest_range(digest.len()) {
+            Some(range) => digest_to_hex(&digest[range]),
+            None => self.render(&digest_to_hex(digest)),
+        }
+    }
+
+    /// Render a hex fingerprint for use in file names (groups are joined with `_`)
+    pub fn render_file_name(&self, fingerprint: &str) -> String {
+        self.render(fingerprint)
+            .split_whitespace()
+            .collect::<Vec<&str>>()
+            .join("_")
+    }
+}
+
+impl FromStr for MatchTarget {
+    type Err = MatcherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MatchTarget::ALL
+            .iter()
+            .find(|target| target.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| MatcherError::TargetNotSupported(s.to_string()))
+    }
+}
+
+impl fmt::Display for MatchTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[cfg(test)]
+mod target_test {
+    use super::MatchTarget;
+
+    const FINGERPRINT: &str = "0123456789ABCDEF0123456789ABCDEFDEADBEEF";
+    const FINGERPRINT_V6: &str = "CB186C4F0609A697E4D52DFA6C722B0C1F1E27C18A56708F6525EC27BAD9ACC9";
+
+    #[test]
+    fn render() {
+        assert_eq!(MatchTarget::Fingerprint.render(FINGERPRINT), FINGERPRINT);
+        assert_eq!(
+            MatchTarget::KeyIdLong.render(FINGERPRINT),
+            "89ABCDEFDEADBEEF"
+        );
+        assert_eq!(MatchTarget::KeyIdShort.render(FINGERPRINT), "DEADBEEF");
+        assert_eq!(
+            MatchTarget::Grouped.render(FINGERPRINT),
+            "0123 4567 89AB CDEF 0123  4567 89AB CDEF DEAD BEEF"
+        );
+        assert_eq!(
+            MatchTarget::Grouped.render_file_name(FINGERPRINT),
+            "0123_4567_89AB_CDEF_0123_4567_89AB_CDEF_DEAD_BEEF"
+        );
+    }
+
+    #[test]
+    fn render_v6() {
+        assert_eq!(
+            MatchTarget::KeyIdLong.render(FINGERPRINT_V6),
+            "CB186C4F0609A697"
+        );
+        assert_eq!(MatchTarget::KeyIdShort.render(FINGERPRINT_V6), "CB186C4F");
+        assert_eq!(
+            MatchTarget::Grouped.render(FINGERPRINT_V6),
+            "CB18 6C4F 0609 A697 E4D5 2DFA 6C72 2B0C  \
+             1F1E 27C1 8A56 708F 6525 EC27 BAD9 ACC9"
+        );
+    }
+
+    #[test]
+    fn digest_range() {
+        for fingerprint in [FINGERPRINT, FINGERPRINT_V6] {
+            let digest = hex::decode(fingerprint).unwrap();
+            for target in MatchTarget::ALL.iter() {
+                if let Some(range) = target.digest_range(digest.len()) {
+                    assert_eq!(
+                        hex::encode_upper(&digest[range.clone()]),
+                        target.render(fingerprint)
+                    );
+                    assert_eq!(range.len() * 2, target.nibbles(digest.len()));
+                }
+                assert_eq!(
+                    target.digest_range(digest.len()).is_some(),
+                    target.is_plain()
+                );
+                assert_eq!(target.render_digest(&digest), target.render(fingerprint));
+            }
+        }
+    }
+
+    #[test]
+    fn parse() {
+        for target in MatchTarget::ALL.iter() {
+            assert_eq!(&target.name().parse::<MatchTarget>().unwrap(), target);
+        }
+        assert!("keyid".parse::<MatchTarget>().is_err());
+    }
+}
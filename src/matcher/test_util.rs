@@ -0,0 +1,28 @@
+//! Fixtures shared by the matcher tests
+
+/// The alternation from the README
+pub(crate) const README_PATTERN: &str = "(8B){5,20}$|(B8){5,20}$|(EB){5,20}$|(BE){5,20}$|(EF){5,20}$|(FE){5,20}$|A{10,40}$|B{10,40}$|C{10,40}$|D{10,40}$|E{10,40}$|F{10,40}$|1{10,40}$|2{10,40}$|3{10,40}$|4{10,40}$|5{10,40}$|6{10,40}$|7{10,40}$|8{10,40}$|9{10,40}$|0{10,40}$|1145141919810$";
+
+/// Deterministic pseudo random digests, a different sequence for every `seed`
+pub(crate) fn digests(seed: u64, count: usize) -> Vec<[u8; 20]> {
+    let mut state = seed;
+    (0..count)
+        .map(|_| {
+            let mut digest = [0u8; 20];
+            for byte in digest.iter_mut() {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                *byte = state as u8;
+            }
+            digest
+        })
+        .collect()
+}
+
+/// Build a digest from a hex string
+pub(crate) fn digest(hex_string: &str) -> [u8; 20] {
+    let mut digest = [0u8; 20];
+    hex::decode_to_slice(hex_string, &mut digest).unwrap();
+    digest
+}
@@ -0,0 +1,232 @@
+//! The `Matcher` trait
+//!
+//! Anything that decides whether a candidate fingerprint is wanted. Matchers get the binary
+//! digest, and the hex string is only rendered (once) if some matcher asks for it. Library users
+//! can plug their own logic into the search loop with `from_fn` and the combinators.
+
+use std::cell::OnceCell;
+use std::sync::Arc;
+
+use regex::Regex;
+
+use super::{
+    NibbleDfa, NibbleMask, NibbleMirror, NibblePrefilter, PatternMatcher, PatternSet, Wordlist,
+};
+use crate::pgp_backends::digest_to_hex;
+
+/// A candidate fingerprint
+#[derive(Debug)]
+pub struct Candidate<'a> {
+    digest: &'a [u8],
+    hex: OnceCell<String>,
+}
+
+/// Decides whether a candidate is accepted
+pub trait Matcher {
+    /// Test a candidate
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool;
+
+    /// Accept candidates accepted by both matchers
+    fn and<M: Matcher>(self, other: M) -> And<Self, M>
+    where
+        Self: Sized,
+    {
+        And(self, other)
+    }
+
+    /// Accept candidates accepted by either matcher
+    fn or<M: Matcher>(self, other: M) -> Or<Self, M>
+    where
+        Self: Sized,
+    {
+        Or(self, other)
+    }
+
+    /// Accept candidates rejected by this matcher
+    fn not(self) -> Not<Self>
+    where
+        Self: Sized,
+    {
+        Not(self)
+    }
+}
+
+/// Both matchers have to accept
+#[derive(Debug, Clone)]
+pub struct And<A, B>(A, B);
+
+/// Either matcher has to accept
+#[derive(Debug, Clone)]
+pub struct Or<A, B>(A, B);
+
+/// The matcher has to reject
+#[derive(Debug, Clone)]
+pub struct Not<A>(A);
+
+/// Matcher from a closure
+#[derive(Debug, Clone)]
+pub struct FnMatcher<F>(F);
+
+/// Build a matcher from a closure
+pub fn from_fn<F: Fn(&Candidate<'_>) -> bool>(f: F) -> FnMatcher<F> {
+    FnMatcher(f)
+}
+
+impl<'a> Candidate<'a> {
+    /// Wrap a binary digest
+    pub fn new(digest: &'a [u8]) -> Self {
+        Self {
+            digest,
+            hex: OnceCell::new(),
+        }
+    }
+
+    /// Get the binary digest
+    pub fn digest(&self) -> &'a [u8] {
+        self.digest
+    }
+
+    /// Get the uppercase hex fingerprint, rendered on first use
+    pub fn hex(&self) -> &str {
+        self.hex.get_or_init(|| digest_to_hex(self.digest))
+    }
+}
+
+impl<A: Matcher, B: Matcher> Matcher for And<A, B> {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        self.0.accepts(candidate) && self.1.accepts(candidate)
+    }
+}
+
+impl<A: Matcher, B: Matcher> Matcher for Or<A, B> {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        self.0.accepts(candidate) || self.1.accepts(candidate)
+    }
+}
+
+impl<A: Matcher> Matcher for Not<A> {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        !self.0.accepts(candidate)
+    }
+}
+
+impl<F: Fn(&Candidate<'_>) -> bool> Matcher for FnMatcher<F> {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        (self.0)(candidate)
+    }
+}
+
+impl<M: Matcher + ?Sized> Matcher for &M {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        (**self).accepts(candidate)
+    }
+}
+
+impl<M: Matcher + ?Sized> Matcher for Box<M> {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        (**self).accepts(candidate)
+    }
+}
+
+impl<M: Matcher + ?Sized> Matcher for Arc<M> {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        (**self).accepts(candidate)
+    }
+}
+
+impl Matcher for Regex {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        self.is_match(candidate.hex())
+    }
+}
+
+impl Matcher for NibbleMask {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        self.is_match(candidate.digest())
+    }
+}
+
+impl Matcher for NibblePrefilter {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        self.is_match(candidate.digest())
+    }
+}
+
+impl Matcher for NibbleDfa {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        self.is_match(candidate.digest())
+    }
+}
+
+impl Matcher for NibbleMirror {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        self.is_match(candidate.digest())
+    }
+}
+
+impl Matcher for Wordlist {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        self.is_match(candidate.digest())
+    }
+}
+
+impl Matcher for PatternMatcher {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        self.is_match(candidate.digest())
+    }
+}
+
+impl Matcher for PatternSet {
+    fn accepts(&self, candidate: &Candidate<'_>) -> bool {
+        self.is_match(candidate.digest())
+    }
+}
+
+#[cfg(test)]
+mod traits_test {
+    use super::{from_fn, Candidate, Matcher};
+    use crate::matcher::NibbleMask;
+    use regex::Regex;
+    use std::cell::Cell;
+
+    #[test]
+    fn combinators() {
+        let mut digest = [0u8; 20];
+        digest[0] = 0xAB;
+        digest[19] = 0xCD;
+        let candidate = Candidate::new(&digest);
+
+        let mut mask = NibbleMask::new();
+        mask.set(0, 0xA);
+        let regex = Regex::new("CD$").unwrap();
+        let never = from_fn(|_: &Candidate<'_>| false);
+
+        assert!(mask.accepts(&candidate));
+        assert!(regex.accepts(&candidate));
+        assert!(!never.accepts(&candidate));
+        assert!((&mask).and(&regex).accepts(&candidate));
+        assert!(!(&mask).and(&never).accepts(&candidate));
+        assert!((&never).or(&regex).accepts(&candidate));
+        assert!(!(&regex).not().accepts(&candidate));
+        assert!((&never).not().and(&mask).or(&never).accepts(&candidate));
+
+        let boxed: Box<dyn Matcher> = Box::new(mask.and(regex.not()));
+        assert!(!boxed.accepts(&candidate));
+    }
+
+    #[test]
+    fn candidate() {
+        let digest = [0x11u8; 20];
+        let calls = Cell::new(0);
+        let counting = from_fn(|candidate: &Candidate<'_>| {
+            calls.set(calls.get() + 1);
+            candidate.hex().starts_with("11")
+        });
+        let candidate = Candidate::new(&digest);
+        assert!(counting.accepts(&candidate));
+        assert!(counting.accepts(&candidate));
+        assert_eq!(calls.get(), 2);
+        assert_eq!(candidate.hex(), "1111111111111111111111111111111111111111");
+        assert_eq!(candidate.digest(), &digest[..]);
+    }
+}
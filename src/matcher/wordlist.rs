@@ -0,0 +1,320 @@
+//! Wordlist matcher
+//!
+//! Words from a dictionary are spelled with hex look-alikes (`coffee` becomes `C0FFEE`) through a
+//! substitution table, and compiled into one alternation. Which word matched is only looked up
+//! once a candidate hits.
+
+use std::fmt;
+use std::str::FromStr;
+
+use super::{hex_value, MatchTarget, MatcherError, PatternMatcher, MAX_FINGERPRINT_BYTES};
+
+/// Default substitutions for letters that aren't hex digits
+const DEFAULT_SUBSTITUTIONS: &[(char, char)] = &[
+    ('g', '9'),
+    ('i', '1'),
+    ('l', '1'),
+    ('o', '0'),
+    ('s', '5'),
+    ('t', '7'),
+    ('z', '2'),
+];
+
+/// Where a word has to appear
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WordPosition {
+    /// At the start of the rendering
+    Prefix,
+    /// At the end of the rendering
+    Suffix,
+    /// Anywhere
+    Anywhere,
+}
+
+/// Letter to hex digit substitutions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LeetTable {
+    letters: [Option<u8>; 26],
+}
+
+/// A word and its hex spelling
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Word {
+    word: String,
+    hex: String,
+}
+
+/// Compiled wordlist
+#[derive(Debug, Clone)]
+pub struct Wordlist {
+    words: Vec<Word>,
+    position: WordPosition,
+    target: MatchTarget,
+    matcher: PatternMatcher,
+}
+
+impl WordPosition {
+    /// Get the name used on the commandline
+    pub fn name(&self) -> &'static str {
+        match self {
+            WordPosition::Prefix => "prefix",
+            WordPosition::Suffix => "suffix",
+            WordPosition::Anywhere => "anywhere",
+        }
+    }
+}
+
+impl FromStr for WordPosition {
+    type Err = MatcherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            WordPosition::Prefix,
+            WordPosition::Suffix,
+            WordPosition::Anywhere,
+        ]
+        .iter()
+        .find(|position| position.name() == s.to_lowercase())
+        .copied()
+        .ok_or_else(|| MatcherError::InvalidWordlist(format!("unknown position \"{}\"", s)))
+    }
+}
+
+impl fmt::Display for WordPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl Default for LeetTable {
+    fn default() -> Self {
+        let mut table = Self {
+            letters: [None; 26],
+        };
+        for letter in 'a'..='f' {
+            table.letters[letter as usize - 'a' as usize] = hex_value(letter.to_ascii_uppercase());
+        }
+        for (letter, digit) in DEFAULT_SUBSTITUTIONS {
+            table.letters[*letter as usize - 'a' as usize] = hex_value(*digit);
+        }
+        table
+    }
+}
+
+impl LeetTable {
+    /// Default table with overrides like `o=0,i=1,s=5`, an empty right side removes a letter
+    pub fn parse(spec: &str) -> Result<Self, MatcherError> {
+        let mut table = Self::default();
+        for entry in spec
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+        {
+            let invalid =
+                || MatcherError::InvalidWordlist(format!("bad substitution \"{}\"", entry));
+            let (letter, digit) = entry.split_once('=').ok_or_else(invalid)?;
+            let mut letters = letter.trim().chars();
+            let letter = match (letters.next(), letters.next()) {
+                (Some(letter), None) if letter.is_ascii_alphabetic() => letter.to_ascii_lowercase(),
+                _ => return Err(invalid()),
+            };
+            let digit = match digit.trim() {
+                "" => None,
+                digit if digit.len() == 1 => Some(
+                    hex_value(digit.chars().next().unwrap().to_ascii_uppercase())
+                        .ok_or_else(invalid)?,
+                ),
+                _ => return Err(invalid()),
+            };
+            table.letters[letter as usize - 'a' as usize] = digit;
+        }
+        Ok(table)
+    }
+
+    /// Spell a word in hex, `None` if some letter has no substitution
+    pub fn translate(&self, word: &str) -> Option<String> {
+        word.chars()
+            .map(|c| match c {
+                '0'..='9' => Some(c),
+                'a'..='z' | 'A'..='Z' => {
+                    self.letters[c.to_ascii_lowercase() as usize - 'a' as usize].map(|value| {
+                        char::from_digit(value as u32, 16)
+                            .unwrap()
+                            .to_ascii_uppercase()
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+impl Word {
+    /// Get the word as written in the wordlist
+    pub fn word(&self) -> &str {
+        &self.word
+    }
+
+    /// Get the hex spelling
+    pub fn hex(&self) -> &str {
+        &self.hex
+    }
+}
+
+impl Wordlist {
+    /// Compile a wordlist, words shorter than `min_length` or with untranslatable letters are
+    /// dropped
+    pub fn new<I: IntoIterator<Item = S>, S: AsRef<str>>(
+        words: I,
+        table: &LeetTable,
+        min_length: usize,
+        position: WordPosition,
+        target: MatchTarget,
+    ) -> Result<Self, MatcherError> {
+        let mut translated: Vec<Word> = words
+            .into_iter()
+            .filter_map(|word| {
+                let word = word.as_ref().trim();
+                let hex = table.translate(word)?;
+                if hex.len() < min_length || hex.len() > target.nibbles(MAX_FINGERPRINT_BYTES) {
+                    return None;
+                }
+                Some(Word {
+                    word: word.to_string(),
+                    hex,
+                })
+            })
+            .collect();
+        translated.sort_by(|a, b| b.hex.len().cmp(&a.hex.len()).then(a.hex.cmp(&b.hex)));
+        translated.dedup_by(|a, b| a.hex == b.hex);
+        if translated.is_empty() {
+            return Err(MatcherError::InvalidWordlist(
+                "no word can be spelled in hex".to_string(),
+            ));
+        }
+        let alternation = translated
+            .iter()
+            .map(|word| word.hex.as_str())
+            .collect::<Vec<&str>>()
+            .join("|");
+        let pattern = match position {
+            WordPosition::Prefix => format!("^(?:{})", alternation),
+            WordPosition::Suffix => format!("(?:{})$", alternation),
+            WordPosition::Anywhere => format!("(?:{})", alternation),
+        };
+        Ok(Self {
+            matcher: PatternMatcher::with_target(&pattern, target)?,
+            words: translated,
+            position,
+            target,
+        })
+    }
+
+    /// Get the words, longest first
+    pub fn words(&self) -> &[Word] {
+        &self.words
+    }
+
+    /// Get the compiled alternation
+    pub fn matcher(&self) -> &PatternMatcher {
+        &self.matcher
+    }
+
+    /// Test the binary fingerprint
+    #[inline]
+    pub fn is_match(&self, digest: &[u8]) -> bool {
+        self.matcher.is_match(digest)
+    }
+
+    /// Find the longest word in a matching fingerprint
+    pub fn find(&self, digest: &[u8]) -> Option<&Word> {
+        let rendered = self.target.render_digest(digest);
+        self.words.iter().find(|word| match self.position {
+            WordPosition::Prefix => rendered.starts_with(&word.hex),
+            WordPosition::Suffix => rendered.ends_with(&word.hex),
+            WordPosition::Anywhere => rendered.contains(&word.hex),
+        })
+    }
+}
+
+#[cfg(test)]
+mod wordlist_test {
+    use super::{LeetTable, WordPosition, Wordlist};
+    use crate::matcher::test_util::digest;
+    use crate::matcher::MatchTarget;
+
+    const WORDS: &[&str] = &[
+        "coffee", "Deadbeef", "bees", "zebra", "toast", "ab", "o'clock",
+    ];
+
+    #[test]
+    fn translate() {
+        let table = LeetTable::default();
+        assert_eq!(table.translate("coffee").unwrap(), "C0FFEE");
+        assert_eq!(table.translate("B00B5").unwrap(), "B00B5");
+        assert_eq!(table.translate("toast").unwrap(), "70A57");
+        assert!(table.translate("zebra").is_none());
+        assert!(table.translate("o'clock").is_none());
+
+        let table = LeetTable::parse("r=2, t=, g=6").unwrap();
+        assert_eq!(table.translate("zebra").unwrap(), "2EB2A");
+        assert!(table.translate("toast").is_none());
+        assert_eq!(table.translate("egg").unwrap(), "E66");
+        assert!(LeetTable::parse("r=x").is_err());
+        assert!(LeetTable::parse("rr=1").is_err());
+        assert!(LeetTable::parse("r").is_err());
+    }
+
+    #[test]
+    fn positions() {
+        let table = LeetTable::default();
+        let hit = digest("C0FFEE0123456789ABCDEF0123456789DEADBEEF");
+        let miss = digest("0C0FFEE123456789ABCDEF0123456789DEADBEE0");
+
+        let prefix = Wordlist::new(
+            WORDS,
+            &table,
+            4,
+            WordPosition::Prefix,
+            MatchTarget::Fingerprint,
+        )
+        .unwrap();
+        assert_eq!(prefix.words().len(), 4);
+        assert!(prefix.is_match(&hit));
+        assert_eq!(prefix.find(&hit).unwrap().word(), "coffee");
+        assert!(!prefix.is_match(&miss));
+
+        let suffix = Wordlist::new(
+            WORDS,
+            &table,
+            4,
+            WordPosition::Suffix,
+            MatchTarget::Fingerprint,
+        )
+        .unwrap();
+        assert_eq!(suffix.find(&hit).unwrap().word(), "Deadbeef");
+        assert!(!suffix.is_match(&miss));
+
+        let anywhere = Wordlist::new(
+            WORDS,
+            &table,
+            6,
+            WordPosition::Anywhere,
+            MatchTarget::Fingerprint,
+        )
+        .unwrap();
+        assert_eq!(anywhere.words().len(), 2);
+        assert!(anywhere.is_match(&miss));
+        assert_eq!(anywhere.find(&miss).unwrap().hex(), "C0FFEE");
+
+        assert!(Wordlist::new(
+            WORDS,
+            &table,
+            10,
+            WordPosition::Anywhere,
+            MatchTarget::Fingerprint,
+        )
+        .is_err());
+        assert!("middle".parse::<WordPosition>().is_err());
+    }
+}
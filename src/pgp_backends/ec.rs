@@ -0,0 +1,489 @@
+//! Elliptic curve point stepping
+//!
+//! Adding the generator `G` to the public point `Q = d·G` of a key gives the public point of the
+//! secret scalar `d + 1`. Once the creation times of a key are used up, it can be replaced by
+//! `Q + G` with a single point addition instead of generating a new key. Only the point in the
+//! packet changes, and the secret scalar is recomputed when the key is exported.
+//!
+//! Only the NIST curves are stepped. Keys on the other curves are still generated afresh:
+//! Cv25519 is only used for the encryption subkey, and no backend offers secp256k1.
+//!
+//! Ed25519 can't be stepped either, even by multiples of `8·B` that keep the scalar clamped.
+//! OpenPGP stores the 32-byte seed of an EdDSA key, not its scalar: the scalar is the clamped
+//! first half of `SHA-512(seed)`, and the second half is the nonce prefix used when signing. A
+//! stepped scalar has no known seed, finding one means inverting SHA-512, so the key could be
+//! searched but never exported in a form GnuPG or Sequoia accept.
+
+use super::PGPError;
+
+/// Number of 64-bit limbs, enough for P-521
+const LIMBS: usize = 9;
+
+/// Little-endian 64-bit limbs
+type Limbs = [u64; LIMBS];
+
+/// Curves whose points can be stepped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EcCurve {
+    NistP256,
+    NistP384,
+    NistP521,
+}
+
+/// Domain parameters of a short Weierstrass curve with `a = -3`, in hex
+struct Parameters {
+    p: &'static str,
+    b: &'static str,
+    n: &'static str,
+    gx: &'static str,
+    gy: &'static str,
+}
+
+impl EcCurve {
+    /// Size of the field in bits
+    pub(crate) fn bits(self) -> usize {
+        match self {
+            EcCurve::NistP256 => 256,
+            EcCurve::NistP384 => 384,
+            EcCurve::NistP521 => 521,
+        }
+    }
+
+    /// Size of a coordinate in bytes
+    pub(crate) fn size(self) -> usize {
+        self.bits().div_ceil(8)
+    }
+
+    /// Get the domain parameters (SEC 2)
+    fn parameters(self) -> Parameters {
+        match self {
+            EcCurve::NistP256 => Parameters {
+                p: "FFFFFFFF00000001000000000000000000000000FFFFFFFFFFFFFFFFFFFFFFFF",
+                b: "5AC635D8AA3A93E7B3EBBD55769886BC651D06B0CC53B0F63BCE3C3E27D2604B",
+                n: "FFFFFFFF00000000FFFFFFFFFFFFFFFFBCE6FAADA7179E84F3B9CAC2FC632551",
+                gx: "6B17D1F2E12C4247F8BCE6E563A440F277037D812DEB33A0F4A13945D898C296",
+                gy: "4FE342E2FE1A7F9B8EE7EB4A7C0F9E162BCE33576B315ECECBB6406837BF51F5",
+            },
+            EcCurve::NistP384 => Parameters {
+                p: "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFE\
+                    FFFFFFFF0000000000000000FFFFFFFF",
+                b: "B3312FA7E23EE7E4988E056BE3F82D19181D9C6EFE8141120314088F5013875A\
+                    C656398D8A2ED19D2A85C8EDD3EC2AEF",
+                n: "FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFC7634D81F4372DDF\
+                    581A0DB248B0A77AECEC196ACCC52973",
+                gx: "AA87CA22BE8B05378EB1C71EF320AD746E1D3B628BA79B9859F741E082542A38\
+                     5502F25DBF55296C3A545E3872760AB7",
+                gy: "3617DE4A96262C6F5D9E98BF9292DC29F8F41DBD289A147CE9DA3113B5F0B8C0\
+                     0A60B1CE1D7E819D7A431D7C90EA0E5F",
+            },
+            EcCurve::NistP521 => Parameters {
+                p: "01FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF\
+                    FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF",
+                b: "0051953EB9618E1C9A1F929A21A0B68540EEA2DA725B99B315F3B8B489918EF1\
+                    09E156193951EC7E937B1652C0BD3BB1BF073573DF883D2C34F1EF451FD46B503F00",
+                n: "01FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF\
+                    FFFA51868783BF2F966B7FCC0148F709A5D03BB5C9B8899C47AEBB6FB71E91386409",
+                gx: "00C6858E06B70404E9CD9E3ECB662395B4429C648139053FB521F828AF606B4D\
+                     3DBAA14B5E77EFE75928FE1DC127A2FFA8DE3348B3C1856A429BF97E7E31C2E5BD66",
+                gy: "011839296A789A3BC0045C8A5FB42C7D1BD998F54449579B446817AFBD17273E\
+                     662C97EE72995EF42640C550B9013FAD0761353C7086A272C24088BE94769FD16650",
+            },
+        }
+    }
+}
+
+/// Arithmetic modulo an odd prime, with values in Montgomery form (`a·R`, `R = 2^576`)
+#[derive(Debug, Clone)]
+struct Field {
+    modulus: Limbs,
+    /// `-p⁻¹ mod 2^64`
+    inverse: u64,
+    /// `R² mod p`
+    r2: Limbs,
+}
+
+impl Field {
+    /// Set up the arithmetic modulo `modulus`
+    fn new(modulus: Limbs) -> Self {
+        // Newton's iteration doubles the number of correct bits every step
+        let mut inverse = 1u64;
+        for _ in 0..6 {
+            inverse = inverse.wrapping_mul(2u64.wrapping_sub(modulus[0].wrapping_mul(inverse)));
+        }
+        let mut field = Self {
+            modulus,
+            inverse: inverse.wrapping_neg(),
+            r2: [0; LIMBS],
+        };
+        let mut r2 = [0; LIMBS];
+        r2[0] = 1;
+        for _ in 0..2 * 64 * LIMBS {
+            r2 = field.add(&r2, &r2);
+        }
+        field.r2 = r2;
+        field
+    }
+
+    /// `a + b`
+    fn add(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let (sum, carry) = add(a, b);
+        if carry || !less(&sum, &self.modulus) {
+            sub(&sum, &self.modulus).0
+        } else {
+            sum
+        }
+    }
+
+    /// `a - b`
+    fn sub(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let (difference, borrow) = sub(a, b);
+        if borrow {
+            add(&difference, &self.modulus).0
+        } else {
+            difference
+        }
+    }
+
+    /// `a·b·R⁻¹`, the Montgomery product
+    fn mul(&self, a: &Limbs, b: &Limbs) -> Limbs {
+        let mut t = [0u64; LIMBS + 2];
+        for b_limb in b {
+            let mut carry = 0u64;
+            for (t_limb, a_limb) in t.iter_mut().zip(a) {
+                let sum = *t_limb as u128 + *a_limb as u128 * *b_limb as u128 + carry as u128;
+                *t_limb = sum as u64;
+                carry = (sum >> 64) as u64;
+            }
+            let sum = t[LIMBS] as u128 + carry as u128;
+            t[LIMBS] = sum as u64;
+            t[LIMBS + 1] = (sum >> 64) as u64;
+
+            // Add a multiple of the modulus that clears the lowest limb, then shift it out
+            let m = t[0].wrapping_mul(self.inverse);
+            let mut carry = ((t[0] as u128 + m as u128 * self.modulus[0] as u128) >> 64) as u64;
+            for index in 1..LIMBS {
+                let sum =
+                    t[index] as u128 + m as u128 * self.modulus[index] as u128 + carry as u128;
+                t[index - 1] = sum as u64;
+                carry = (sum >> 64) as u64;
+            }
+            let sum = t[LIMBS] as u128 + carry as u128;
+            t[LIMBS - 1] = sum as u64;
+            t[LIMBS] = t[LIMBS + 1] + (sum >> 64) as u64;
+        }
+        let mut result = [0u64; LIMBS];
+        result.copy_from_slice(&t[..LIMBS]);
+        if t[LIMBS] != 0 || !less(&result, &self.modulus) {
+            result = sub(&result, &self.modulus).0;
+        }
+        result
+    }
+
+    /// Convert into Montgomery form
+    fn to_montgomery(&self, a: &Limbs) -> Limbs {
+        self.mul(a, &self.r2)
+    }
+
+    /// Convert out of Montgomery form
+    fn to_normal(&self, a: &Limbs) -> Limbs {
+        let mut one = [0; LIMBS];
+        one[0] = 1;
+        self.mul(a, &one)
+    }
+
+    /// `a⁻¹` by Fermat's little theorem, `a` must not be zero
+    fn invert(&self, a: &Limbs) -> Limbs {
+        let mut two = [0; LIMBS];
+        two[0] = 2;
+        let exponent = sub(&self.modulus, &two).0;
+        let mut one = [0; LIMBS];
+        one[0] = 1;
+        let mut result = self.to_montgomery(&one);
+        for bit in (0..64 * LIMBS).rev() {
+            result = self.mul(&result, &result);
+            if (exponent[bit / 64] >> (bit % 64)) & 1 == 1 {
+                result = self.mul(&result, a);
+            }
+        }
+        result
+    }
+}
+
+/// The public point of a key, stepped by `G`
+#[derive(Debug, Clone)]
+pub(crate) struct PointWalk {
+    curve: EcCurve,
+    field: Field,
+    order: Limbs,
+    /// The generator, in Montgomery form
+    gx: Limbs,
+    gy: Limbs,
+    /// The current point, in Montgomery form
+    x: Limbs,
+    y: Limbs,
+    steps: u64,
+}
+
+impl PointWalk {
+    /// Start walking from the point (`x`, `y`), big-endian coordinates
+    pub(crate) fn new(curve: EcCurve, x: &[u8], y: &[u8]) -> Result<Self, PGPError> {
+        let parameters = curve.parameters();
+        let field = Field::new(from_hex(parameters.p));
+        let walk = Self {
+            curve,
+            order: from_hex(parameters.n),
+            gx: field.to_montgomery(&from_hex(parameters.gx)),
+            gy: field.to_montgomery(&from_hex(parameters.gy)),
+            x: field.to_montgomery(&from_be_bytes(x)),
+            y: field.to_montgomery(&from_be_bytes(y)),
+            steps: 0,
+            field,
+        };
+        let b = walk.field.to_montgomery(&from_hex(parameters.b));
+        if walk.is_on_curve(&b) {
+            Ok(walk)
+        } else {
+            Err(PGPError::FailedToModifyPublicPoint)
+        }
+    }
+
+    /// Check `y² = x³ - 3x + b`
+    fn is_on_curve(&self, b: &Limbs) -> bool {
+        let field = &self.field;
+        let x2 = field.mul(&self.x, &self.x);
+        let x3 = field.mul(&x2, &self.x);
+        let x3x = field.add(&field.add(&self.x, &self.x), &self.x);
+        let right = field.add(&field.sub(&x3, &x3x), b);
+        field.mul(&self.y, &self.y) == right
+    }
+
+    /// Get the curve
+    pub(crate) fn curve(&self) -> EcCurve {
+        self.curve
+    }
+
+    /// Number of times `G` was added
+    pub(crate) fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Add `G` to the point
+    pub(crate) fn step(&mut self) -> Result<(), PGPError> {
+        let field = &self.field;
+        // `Q = ±G` needs a doubling or gives the point at infinity, neither happens in practice
+        if self.x == self.gx {
+            return Err(PGPError::FailedToModifyPublicPoint);
+        }
+        let lambda = field.mul(
+            &field.sub(&self.gy, &self.y),
+            &field.invert(&field.sub(&self.gx, &self.x)),
+        );
+        let x = field.sub(&field.sub(&field.mul(&lambda, &lambda), &self.x), &self.gx);
+        let y = field.sub(&field.mul(&lambda, &field.sub(&self.x, &x)), &self.y);
+        self.x = x;
+        self.y = y;
+        self.steps += 1;
+        Ok(())
+    }
+
+    /// Get the x coordinate (big-endian, padded to the coordinate size)
+    pub(crate) fn x(&self) -> Vec<u8> {
+        to_be_bytes(&self.field.to_normal(&self.x), self.curve.size())
+    }
+
+    /// Get the y coordinate (big-endian, padded to the coordinate size)
+    pub(crate) fn y(&self) -> Vec<u8> {
+        to_be_bytes(&self.field.to_normal(&self.y), self.curve.size())
+    }
+
+    /// The secret scalar of the current point from the one of the starting point (big-endian)
+    pub(crate) fn secret(&self, scalar: &[u8]) -> Vec<u8> {
+        let mut steps = [0; LIMBS];
+        steps[0] = self.steps;
+        let (mut sum, carry) = add(&from_be_bytes(scalar), &steps);
+        if carry || !less(&sum, &self.order) {
+            sum = sub(&sum, &self.order).0;
+        }
+        to_be_bytes(&sum, self.curve.size())
+            .into_iter()
+            .skip_while(|byte| *byte == 0)
+            .collect()
+    }
+}
+
+/// `a + b` and the carry
+fn add(a: &Limbs, b: &Limbs) -> (Limbs, bool) {
+    let mut result = [0; LIMBS];
+    let mut carry = false;
+    for (limb, (x, y)) in result.iter_mut().zip(a.iter().zip(b)) {
+        let (sum, first) = x.overflowing_add(*y);
+        let (sum, second) = sum.overflowing_add(carry as u64);
+        *limb = sum;
+        carry = first || second;
+    }
+    (result, carry)
+}
+
+/// `a - b` and the borrow
+fn sub(a: &Limbs, b: &Limbs) -> (Limbs, bool) {
+    let mut result = [0; LIMBS];
+    let mut borrow = false;
+    for (limb, (x, y)) in result.iter_mut().zip(a.iter().zip(b)) {
+        let (difference, first) = x.overflowing_sub(*y);
+        let (difference, second) = difference.overflowing_sub(borrow as u64);
+        *limb = difference;
+        borrow = first || second;
+    }
+    (result, borrow)
+}
+
+/// `a < b`
+fn less(a: &Limbs, b: &Limbs) -> bool {
+    a.iter().rev().cmp(b.iter().rev()) == std::cmp::Ordering::Less
+}
+
+/// Parse a big-endian number of at most `8 * LIMBS` bytes
+fn from_be_bytes(bytes: &[u8]) -> Limbs {
+    let mut limbs = [0; LIMBS];
+    for (limb, chunk) in limbs.iter_mut().zip(bytes.rchunks(8)) {
+        *limb = chunk
+            .iter()
+            .fold(0, |limb, byte| (limb << 8) | *byte as u64);
+    }
+    limbs
+}
+
+/// Serialize the lowest `size` bytes as a big-endian number
+fn to_be_bytes(limbs: &Limbs, size: usize) -> Vec<u8> {
+    let bytes: Vec<u8> = limbs
+        .iter()
+        .rev()
+        .flat_map(|limb| limb.to_be_bytes())
+        .collect();
+    bytes[bytes.len() - size..].to_vec()
+}
+
+/// Parse a big-endian hex constant
+fn from_hex(hex: &str) -> Limbs {
+    let bytes: Vec<u8> = hex
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+        .collect();
+    from_be_bytes(&bytes)
+}
+
+#[cfg(test)]
+mod ec_test {
+    use super::{from_hex, to_be_bytes, EcCurve, Field, PointWalk, LIMBS};
+
+    /// `d·G` and `(d + 3)·G` for `d = 0xC0FFEE`
+    const VECTORS: [(EcCurve, [&str; 4]); 3] = [
+        (
+            EcCurve::NistP256,
+            [
+                "D360332FAD9BC83AFAFF4A740DE8A516BF1B8FB3FDE360FF1D03979C1F943EE2",
+                "E8A66007FD276B0271265C6DB092C4A0C5EB8C45FDC436502C8A095F5D5745F2",
+                "ED49CE5B494A7F579DFFE0837A7C42D267416AAB4E147A8E7584F4AEB1865BB8",
+                "B56FD3FC6145F50F0434E2065F2ECB89E49E232376C4A5516ABE40BDD14EBD1E",
+            ],
+        ),
+        (
+            EcCurve::NistP384,
+            [
+                "32EE5C42B2FE56347203B8EDEA84419F840701404D49C35DA9B795EE00090541\
+                 9452BE10623260B87CCC60AAFFF64239",
+                "DB3707273DDA8747305F8C8E717B60081DC3ECD95B091D85E7A1C5672C5CD675\
+                 336ECF789942C308E6C39226FCC0949C",
+                "624D3DA5F65AA14FEA9E97B33DF0B4B4E81F9CD06C4055EA3A357DBA72E1185B\
+                 4701A4200B61426813E50A349CA8362F",
+                "F578385494B8DA448E0460AC72D5D48AF4CC2E51313A2545325B8D75D743A5DD\
+                 C90F36DC152AC1B84E398AD9529B51F9",
+            ],
+        ),
+        (
+            EcCurve::NistP521,
+            [
+                "01B1FDD5C9C169A37B5F82CA15627B61CF270262C76709563E09413ACD76D328\
+                 C24D96869D22119A7C4FACDB82EECCEE3FEE68DB2E2A264A6D48720C39139662628A",
+                "01EDF8D1DBB9813D82972D3B8FD8B340BBB5640929BFCA1F262AC882FE465DB6\
+                 151323283C90A5788E9766E3279FFE4F2259867E87CC77010314D677110A6427F51F",
+                "005CBECE84862286B04B4BFD5F92E3F2F12383063CFB31B451909997417BD14D\
+                 F6B257D2CEBF7DC2396F5F0B9E95655EB63F995608420915AEA5FF216F6B473E0407",
+                "01C045ABDEBDF927DB740ECF65E56F68F59FEB5221AF9B92BE23C5B51930DDDB\
+                 0C8603F56082F32B04FC8AECCC49F914CA4B09CD3E4AC2A66F9EC21C563DB69F3BD1",
+            ],
+        ),
+    ];
+
+    /// Decode a coordinate of `curve`
+    fn coordinate(curve: EcCurve, hex: &str) -> Vec<u8> {
+        to_be_bytes(&from_hex(hex), curve.size())
+    }
+
+    #[test]
+    fn field() {
+        // 2^127 - 1 is prime
+        let field = Field::new(from_hex("7FFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF"));
+        let mut a = [0; LIMBS];
+        a[0] = 0x1234_5678_9ABC_DEF0;
+        let mut b = [0; LIMBS];
+        b[0] = 0xFEDC_BA98_7654_3210;
+        let modulus = (1u128 << 127) - 1;
+        let product =
+            field.to_normal(&field.mul(&field.to_montgomery(&a), &field.to_montgomery(&b)));
+        assert_eq!(
+            product[0] as u128 | (product[1] as u128) << 64,
+            (a[0] as u128 * b[0] as u128) % modulus
+        );
+        let inverse = field.to_normal(&field.invert(&field.to_montgomery(&a)));
+        let check =
+            field.to_normal(&field.mul(&field.to_montgomery(&inverse), &field.to_montgomery(&a)));
+        assert_eq!(check[0], 1);
+        assert!(check[1..].iter().all(|limb| *limb == 0));
+    }
+
+    #[test]
+    fn step() {
+        for (curve, [x, y, x3, y3]) in VECTORS {
+            let mut walk =
+                PointWalk::new(curve, &coordinate(curve, x), &coordinate(curve, y)).unwrap();
+            assert_eq!(walk.x(), coordinate(curve, x));
+            for _ in 0..3 {
+                walk.step().unwrap();
+            }
+            assert_eq!(walk.steps(), 3);
+            assert_eq!(walk.x(), coordinate(curve, x3), "{:?}", curve);
+            assert_eq!(walk.y(), coordinate(curve, y3), "{:?}", curve);
+            assert_eq!(walk.secret(&[0xC0, 0xFF, 0xEE]), vec![0xC0, 0xFF, 0xF1]);
+        }
+    }
+
+    #[test]
+    fn secret_wraps() {
+        let parameters = EcCurve::NistP256.parameters();
+        let mut walk = PointWalk::new(
+            EcCurve::NistP256,
+            &coordinate(EcCurve::NistP256, parameters.gx),
+            &coordinate(EcCurve::NistP256, parameters.gy),
+        )
+        .unwrap();
+        // `G` itself can't be stepped, the secret is computed all the same
+        assert!(walk.step().is_err());
+        walk.steps = 2;
+        let order_minus_one = coordinate(
+            EcCurve::NistP256,
+            "FFFFFFFF00000000FFFFFFFFFFFFFFFFBCE6FAADA7179E84F3B9CAC2FC632550",
+        );
+        assert_eq!(walk.secret(&order_minus_one), vec![1]);
+    }
+
+    #[test]
+    fn off_curve() {
+        let curve = EcCurve::NistP384;
+        let [x, y, _, _] = VECTORS[1].1;
+        let mut y = coordinate(curve, y);
+        y[47] ^= 1;
+        assert!(PointWalk::new(curve, &coordinate(curve, x), &y).is_err());
+    }
+}
@@ -0,0 +1,341 @@
+//! RSA public exponent variation
+//!
+//! The public exponent `e` is the last MPI of an RSA key packet. Trying a different `e` only
+//! changes the last SHA-1 block(s) of the fingerprint, every block before it is cached as a
+//! midstate. Exponents are kept at 4 bytes so that the packet length never changes, and below
+//! 2^31 since some implementations refuse anything larger.
+//!
+//! A new `e` needs a new private exponent `d`, with `φ = (p - 1)(q - 1)` it is recomputed as
+//! `d = (k·φ + 1) / e`, where `k = -φ⁻¹ mod e`. Only arithmetic between big and 32-bit numbers
+//! is needed besides one multiplication, so there is no bignum dependency.
+
+use super::{Digest, FingerprintBatch, PGPError};
+
+/// First exponent tried, the smallest one with a bit length of 31
+pub(crate) const EXPONENT_START: u32 = 0x4000_0001;
+
+/// Last exponent tried
+pub(crate) const EXPONENT_END: u32 = 0x7FFF_FFFF;
+
+/// Bound for the small prime factors of `φ` that candidates are checked against
+const SMALL_PRIME_BOUND: u32 = 1 << 16;
+
+/// Exponents of one RSA key, walked upwards
+#[derive(Debug, Clone)]
+pub(crate) struct ExponentWalk {
+    /// `φ` as little-endian 32-bit limbs
+    phi: Vec<u32>,
+    /// Odd primes below `SMALL_PRIME_BOUND` dividing `φ`
+    factors: Vec<u32>,
+    exponent: u32,
+    values: Vec<u32>,
+}
+
+impl ExponentWalk {
+    /// Start walking the exponents of the key with the primes `p` and `q` (big-endian)
+    pub(crate) fn new(p: &[u8], q: &[u8]) -> Result<Self, PGPError> {
+        let phi = multiply(&decrement(&from_be_bytes(p)), &decrement(&from_be_bytes(q)));
+        let factors = small_primes()
+            .into_iter()
+            .filter(|prime| remainder(&phi, *prime) == 0)
+            .collect();
+        let mut walk = Self {
+            phi,
+            factors,
+            exponent: EXPONENT_START,
+            values: Vec::new(),
+        };
+        if !walk.is_candidate(EXPONENT_START) {
+            walk.exponent = walk
+                .next_candidate(EXPONENT_START)
+                .ok_or(PGPError::FailedToModifyPublicExponent)?;
+        }
+        Ok(walk)
+    }
+
+    /// Get the current exponent
+    pub(crate) fn exponent(&self) -> u32 {
+        self.exponent
+    }
+
+    /// Check whether `e` is coprime to `φ`, so that every candidate can be exported
+    ///
+    /// The small factors rule out most exponents cheaply, the rest are tested exactly.
+    fn is_candidate(&self, e: u32) -> bool {
+        e & 1 == 1
+            && self.factors.iter().all(|factor| !e.is_multiple_of(*factor))
+            && inverse(remainder(&self.phi, e), e).is_some()
+    }
+
+    /// The candidate following `e`
+    fn next_candidate(&self, mut e: u32) -> Option<u32> {
+        loop {
+            e = e.checked_add(2).filter(|e| *e <= EXPONENT_END)?;
+            if self.is_candidate(e) {
+                return Some(e);
+            }
+        }
+    }
+
+    /// Fingerprint the current exponent and the candidates following it
+    ///
+    /// Returns the number of digests written.
+    pub(crate) fn fingerprints(&mut self, batch: &FingerprintBatch, out: &mut [Digest]) -> usize {
+        self.values.clear();
+        let mut e = Some(self.exponent);
+        while let Some(candidate) = e.filter(|_| self.values.len() < out.len()) {
+            self.values.push(candidate);
+            e = self.next_candidate(candidate);
+        }
+        batch.digests(&self.values, out);
+        self.values.len()
+    }
+
+    /// Move `count` candidates ahead
+    pub(crate) fn advance(&mut self, count: usize) -> Result<(), PGPError> {
+        let mut exponent = self.exponent;
+        for _ in 0..count {
+            exponent = self
+                .next_candidate(exponent)
+                .ok_or(PGPError::FailedToModifyPublicExponent)?;
+        }
+        self.exponent = exponent;
+        Ok(())
+    }
+
+    /// The private exponent for the current exponent (big-endian)
+    pub(crate) fn private_exponent(&self) -> Result<Vec<u8>, PGPError> {
+        private_exponent(&self.phi, self.exponent).ok_or(PGPError::FailedToModifyPublicExponent)
+    }
+}
+
+/// `d` with `e·d ≡ 1 (mod φ)`, if `e` is invertible
+fn private_exponent(phi: &[u32], e: u32) -> Option<Vec<u8>> {
+    let inverse = inverse(remainder(phi, e), e)?;
+    let k = (e - inverse) % e;
+    let (d, rest) = divide(&multiply_add(phi, k, 1), e);
+    debug_assert_eq!(rest, 0);
+    Some(to_be_bytes(&d))
+}
+
+/// Inverse of `a` modulo `m` with the extended Euclidean algorithm
+fn inverse(a: u32, m: u32) -> Option<u32> {
+    let (mut r0, mut r1) = (m as i64, a as i64);
+    let (mut t0, mut t1) = (0i64, 1i64);
+    while r1 != 0 {
+        let quotient = r0 / r1;
+        (r0, r1) = (r1, r0 - quotient * r1);
+        (t0, t1) = (t1, t0 - quotient * t1);
+    }
+    if r0 != 1 {
+        return None;
+    }
+    Some(t0.rem_euclid(m as i64) as u32)
+}
+
+/// Odd primes below `SMALL_PRIME_BOUND`
+fn small_primes() -> Vec<u32> {
+    let mut composite = vec![false; SMALL_PRIME_BOUND as usize];
+    let mut primes = Vec::new();
+    for number in 3..SMALL_PRIME_BOUND as usize {
+        if composite[number] {
+            continue;
+        }
+        primes.push(number as u32);
+        for multiple in (number * number..composite.len()).step_by(number) {
+            composite[multiple] = true;
+        }
+    }
+    primes
+}
+
+/// Parse a big-endian number into little-endian limbs
+fn from_be_bytes(bytes: &[u8]) -> Vec<u32> {
+    let mut limbs: Vec<u32> = bytes
+        .rchunks(4)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .fold(0, |limb, byte| (limb << 8) | *byte as u32)
+        })
+        .collect();
+    trim(&mut limbs);
+    limbs
+}
+
+/// Serialize little-endian limbs as a big-endian number without leading zeros
+fn to_be_bytes(limbs: &[u32]) -> Vec<u8> {
+    let bytes: Vec<u8> = limbs
+        .iter()
+        .rev()
+        .flat_map(|limb| limb.to_be_bytes())
+        .skip_while(|byte| *byte == 0)
+        .collect();
+    if bytes.is_empty() {
+        vec![0]
+    } else {
+        bytes
+    }
+}
+
+/// Drop the leading zero limbs
+fn trim(limbs: &mut Vec<u32>) {
+    while limbs.last() == Some(&0) {
+        limbs.pop();
+    }
+}
+
+/// `a - 1` for a positive `a`
+fn decrement(a: &[u32]) -> Vec<u32> {
+    let mut result = a.to_vec();
+    for limb in result.iter_mut() {
+        let (value, borrow) = limb.overflowing_sub(1);
+        *limb = value;
+        if !borrow {
+            break;
+        }
+    }
+    trim(&mut result);
+    result
+}
+
+/// `a·b`
+fn multiply(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut result = vec![0u32; a.len() + b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, y) in b.iter().enumerate() {
+            let sum = *x as u64 * *y as u64 + result[i + j] as u64 + carry;
+            result[i + j] = sum as u32;
+            carry = sum >> 32;
+        }
+        result[i + b.len()] = carry as u32;
+    }
+    trim(&mut result);
+    result
+}
+
+/// `a·k + c`
+fn multiply_add(a: &[u32], k: u32, c: u32) -> Vec<u32> {
+    let mut result = Vec::with_capacity(a.len() + 1);
+    let mut carry = c as u64;
+    for limb in a {
+        let sum = *limb as u64 * k as u64 + carry;
+        result.push(sum as u32);
+        carry = sum >> 32;
+    }
+    result.push(carry as u32);
+    trim(&mut result);
+    result
+}
+
+/// `a mod m`
+fn remainder(a: &[u32], m: u32) -> u32 {
+    a.iter()
+        .rev()
+        .fold(0u64, |rest, limb| ((rest << 32) | *limb as u64) % m as u64) as u32
+}
+
+/// `a / m` and `a mod m`
+fn divide(a: &[u32], m: u32) -> (Vec<u32>, u32) {
+    let mut quotient = vec![0u32; a.len()];
+    let mut rest = 0u64;
+    for (index, limb) in a.iter().enumerate().rev() {
+        let current = (rest << 32) | *limb as u64;
+        quotient[index] = (current / m as u64) as u32;
+        rest = current % m as u64;
+    }
+    trim(&mut quotient);
+    (quotient, rest as u32)
+}
+
+#[cfg(test)]
+mod exponent_test {
+    use super::{
+        divide, from_be_bytes, inverse, multiply, private_exponent, remainder, to_be_bytes,
+        ExponentWalk, EXPONENT_START,
+    };
+    use crate::pgp_backends::{Digest, FingerprintBatch, KeyVersion};
+
+    /// Read a big-endian number of at most 16 bytes
+    fn to_u128(bytes: &[u8]) -> u128 {
+        bytes
+            .iter()
+            .fold(0, |value, byte| (value << 8) | *byte as u128)
+    }
+
+    #[test]
+    fn arithmetic() {
+        let a = from_be_bytes(&0xFFFF_FFFF_FFFF_FFFFu64.to_be_bytes());
+        let b = from_be_bytes(&0x1234_5678_9ABC_DEF0u64.to_be_bytes());
+        let product = multiply(&a, &b);
+        assert_eq!(
+            to_u128(&to_be_bytes(&product)),
+            0xFFFF_FFFF_FFFF_FFFFu128 * 0x1234_5678_9ABC_DEF0u128
+        );
+        assert_eq!(
+            remainder(&product, 1000003),
+            (0xFFFF_FFFF_FFFF_FFFFu128 * 0x1234_5678_9ABC_DEF0u128 % 1000003) as u32
+        );
+        let (quotient, rest) = divide(&product, 0x1234_5678);
+        assert_eq!(
+            to_u128(&to_be_bytes(&quotient)),
+            0xFFFF_FFFF_FFFF_FFFFu128 * 0x1234_5678_9ABC_DEF0u128 / 0x1234_5678
+        );
+        assert_eq!(
+            rest,
+            (0xFFFF_FFFF_FFFF_FFFFu128 * 0x1234_5678_9ABC_DEF0u128 % 0x1234_5678) as u32
+        );
+        assert_eq!(to_be_bytes(&from_be_bytes(&[0, 0, 1, 2, 3])), vec![1, 2, 3]);
+        assert_eq!(inverse(3, 7), Some(5));
+        assert_eq!(inverse(6, 9), None);
+    }
+
+    #[test]
+    fn textbook_key() {
+        // p = 61, q = 53, φ = 3120
+        assert_eq!(private_exponent(&[3120], 17), Some(vec![0x0A, 0xC1]));
+        assert_eq!(private_exponent(&[3120], 15), None);
+    }
+
+    #[test]
+    fn walk() {
+        let p = 0x1_0000_000Fu64; // 2^32 + 15
+        let q = 0x7FFF_FFFFu64; // 2^31 - 1
+        let phi = (p as u128 - 1) * (q as u128 - 1);
+        let mut walk = ExponentWalk::new(&p.to_be_bytes(), &q.to_be_bytes()).unwrap();
+        assert!(walk.exponent() >= EXPONENT_START);
+
+        let batch = FingerprintBatch::new(KeyVersion::V4, &[0u8; 16], 12);
+        let mut out = [Digest::default(); 50];
+        assert_eq!(walk.fingerprints(&batch, &mut out), 50);
+        let values = walk.values.clone();
+        assert!(values.windows(2).all(|pair| pair[0] < pair[1]));
+        for (index, e) in values.iter().enumerate() {
+            let mut single = [Digest::default(); 1];
+            batch.digests(&[*e], &mut single);
+            assert_eq!(single[0], out[index]);
+            assert_eq!(walk.exponent(), *e);
+            // None of the first candidates shares a large factor with `φ`
+            let d = to_u128(&walk.private_exponent().unwrap());
+            assert_eq!(*e as u128 * d % phi, 1);
+            walk.advance(1).unwrap();
+        }
+        // Multiples of 3 and 7 are skipped, both divide `φ`
+        assert!(values.iter().all(|e| e % 3 != 0 && e % 7 != 0));
+    }
+
+    #[test]
+    fn large_factor() {
+        // φ has the factor 65537, too large for the small primes
+        let p = 2 * 65537 + 1u64;
+        let q = 0x7FFF_FFFFu64;
+        let multiple = 65537 * 16385;
+        let mut walk = ExponentWalk::new(&p.to_be_bytes(), &q.to_be_bytes()).unwrap();
+        while walk.exponent() < multiple + 100 {
+            assert_ne!(walk.exponent(), multiple);
+            assert!(walk.private_exponent().is_ok());
+            walk.advance(1).unwrap();
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! Fingerprints of the different key versions
+//!
+//! v4 keys (RFC 4880) are fingerprinted with SHA-1 over the public key packet behind `0x99` and a
+//! 2-byte length. v6 keys (RFC 9580) use SHA-256 over the packet behind `0x9B` and a 4-byte
+//! length, and their key material is preceded by its length in octets. v5 keys (LibrePGP, as
+//! written by GnuPG 2.3 and later) are laid out like v6 keys, but framed with `0x9A`.
+
+use std::fmt;
+use std::ops::Deref;
+use std::str::FromStr;
+
+use super::{digest_to_hex, BatchImplementation, PGPError, Sha1Batch, Sha256Batch};
+
+/// Length of the longest fingerprint in bytes
+pub const MAX_DIGEST_BYTES: usize = 32;
+
+/// Number of digests computed at once by the walks
+const CHUNK: usize = 64;
+
+/// Versions of OpenPGP keys
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KeyVersion {
+    /// RFC 4880 keys with SHA-1 fingerprints
+    #[default]
+    V4,
+    /// LibrePGP keys with SHA-256 fingerprints
+    V5,
+    /// RFC 9580 keys with SHA-256 fingerprints
+    V6,
+}
+
+/// A binary fingerprint of any key version
+#[derive(Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Digest {
+    bytes: [u8; MAX_DIGEST_BYTES],
+    length: usize,
+}
+
+/// Fingerprints of one key for many creation times (or exponents) at once
+#[derive(Debug, Clone)]
+pub enum FingerprintBatch {
+    Sha1(Sha1Batch),
+    Sha256(Sha256Batch),
+}
+
+impl KeyVersion {
+    /// All key versions
+    pub const ALL: [KeyVersion; 3] = [KeyVersion::V4, KeyVersion::V5, KeyVersion::V6];
+
+    /// Name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            KeyVersion::V4 => "v4",
+            KeyVersion::V5 => "v5",
+            KeyVersion::V6 => "v6",
+        }
+    }
+
+    /// Version number in the key packet
+    pub fn number(self) -> u8 {
+        match self {
+            KeyVersion::V4 => 4,
+            KeyVersion::V5 => 5,
+            KeyVersion::V6 => 6,
+        }
+    }
+
+    /// Length of the fingerprint in bytes
+    pub fn digest_bytes(self) -> usize {
+        match self {
+            KeyVersion::V4 => 20,
+            KeyVersion::V5 | KeyVersion::V6 => 32,
+        }
+    }
+
+    /// Length of the prefix the packet is hashed behind
+    pub(crate) fn frame_bytes(self) -> usize {
+        match self {
+            KeyVersion::V4 => 3,
+            KeyVersion::V5 | KeyVersion::V6 => 5,
+        }
+    }
+
+    /// Offset of the creation time in the packet returned by `key_packet`
+    pub fn timestamp_offset(self) -> usize {
+        self.frame_bytes() + 1
+    }
+
+    /// Frame a public key packet the way it is hashed for the fingerprint
+    ///
+    /// `material` is the serialized public key material of the algorithm.
+    pub fn key_packet(self, timestamp: u32, algorithm: u8, material: &[u8]) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(self.frame_bytes() + 10 + material.len());
+        match self {
+            KeyVersion::V4 => {
+                packet.push(0x99);
+                packet.extend_from_slice(&((6 + material.len()) as u16).to_be_bytes());
+            }
+            KeyVersion::V5 | KeyVersion::V6 => {
+                packet.push(if self == KeyVersion::V5 { 0x9A } else { 0x9B });
+                packet.extend_from_slice(&((10 + material.len()) as u32).to_be_bytes());
+            }
+        }
+        packet.push(self.number());
+        packet.extend_from_slice(&timestamp.to_be_bytes());
+        packet.push(algorithm);
+        if self != KeyVersion::V4 {
+            packet.extend_from_slice(&(material.len() as u32).to_be_bytes());
+        }
+        packet.extend_from_slice(material);
+        packet
+    }
+}
+
+impl FromStr for KeyVersion {
+    type Err = PGPError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_lowercase();
+        Self::ALL
+            .iter()
+            .find(|version| version.name() == name || version.name()[1..] == name)
+            .copied()
+            .ok_or_else(|| PGPError::KeyVersionNotSupported(String::from(s)))
+    }
+}
+
+impl fmt::Display for KeyVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Digest {
+    /// Copy a binary fingerprint
+    ///
+    /// Panics if it's longer than `MAX_DIGEST_BYTES`.
+    pub fn new(bytes: &[u8]) -> Self {
+        let mut digest = Self {
+            bytes: [0; MAX_DIGEST_BYTES],
+            length: bytes.len(),
+        };
+        digest.bytes[..bytes.len()].copy_from_slice(bytes);
+        digest
+    }
+
+    /// Get the fingerprint in uppercase hex
+    pub fn to_hex(&self) -> String {
+        digest_to_hex(self)
+    }
+}
+
+impl Deref for Digest {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.bytes[..self.length]
+    }
+}
+
+impl AsRef<[u8]> for Digest {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<[u8; 20]> for Digest {
+    fn from(bytes: [u8; 20]) -> Self {
+        Self::new(&bytes)
+    }
+}
+
+impl From<[u8; 32]> for Digest {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self::new(&bytes)
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Digest({})", self.to_hex())
+    }
+}
+
+impl FingerprintBatch {
+    /// Prepare the hasher of a key version for `packet`, the values are written to
+    /// `offset..offset + 4`
+    pub fn new(version: KeyVersion, packet: &[u8], offset: usize) -> Self {
+        match version {
+            KeyVersion::V4 => FingerprintBatch::Sha1(Sha1Batch::new(packet, offset)),
+            KeyVersion::V5 | KeyVersion::V6 => {
+                FingerprintBatch::Sha256(Sha256Batch::new(packet, offset))
+            }
+        }
+    }
+
+    /// Get the implementation in use
+    pub fn implementation(&self) -> BatchImplementation {
+        match self {
+            FingerprintBatch::Sha1(batch) => batch.implementation(),
+            FingerprintBatch::Sha256(batch) => batch.implementation(),
+        }
+    }
+
+    /// Hash the packet once for every value, `out` has to be at least as long as `values`
+    pub fn digests(&self, values: &[u32], out: &mut [Digest]) {
+        assert!(out.len() >= values.len(), "Output buffer too small");
+        for (values, out) in values.chunks(CHUNK).zip(out.chunks_mut(CHUNK)) {
+            match self {
+                FingerprintBatch::Sha1(batch) => {
+                    let mut raw = [[0u8; 20]; CHUNK];
+                    batch.digests(values, &mut raw);
+                    for (digest, raw) in out.iter_mut().zip(raw.iter().take(values.len())) {
+                        *digest = Digest::from(*raw);
+                    }
+                }
+                FingerprintBatch::Sha256(batch) => {
+                    let mut raw = [[0u8; 32]; CHUNK];
+                    batch.digests(values, &mut raw);
+                    for (digest, raw) in out.iter_mut().zip(raw.iter().take(values.len())) {
+                        *digest = Digest::from(*raw);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Hash the packet for `start`, `start - 1` and so on, stopping at zero
+    ///
+    /// Returns the number of digests written.
+    pub fn digests_descending(&self, start: u32, out: &mut [Digest]) -> usize {
+        let count = out.len().min(start as usize + 1);
+        let mut values = [0u32; CHUNK];
+        for (index, chunk) in out[..count].chunks_mut(CHUNK).enumerate() {
+            let first = start - (index * CHUNK) as u32;
+            for (offset, value) in values.iter_mut().take(chunk.len()).enumerate() {
+                *value = first - offset as u32;
+            }
+            self.digests(&values[..chunk.len()], chunk);
+        }
+        count
+    }
+
+    /// Hash the packet for `start`, `start + 1` and so on, stopping at `u32::MAX`
+    ///
+    /// Returns the number of digests written.
+    pub fn digests_ascending(&self, start: u32, out: &mut [Digest]) -> usize {
+        let count = (out.len() as u64).min(u32::MAX as u64 - start as u64 + 1) as usize;
+        let mut values = [0u32; CHUNK];
+        for (index, chunk) in out[..count].chunks_mut(CHUNK).enumerate() {
+            let first = start + (index * CHUNK) as u32;
+            for (offset, value) in values.iter_mut().take(chunk.len()).enumerate() {
+                *value = first + offset as u32;
+            }
+            self.digests(&values[..chunk.len()], chunk);
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod fingerprint_test {
+    use super::{Digest, FingerprintBatch, KeyVersion};
+    use crate::pgp_backends::Sha256;
+    use hex::encode_upper;
+
+    /// Ed25519 public key of the v6 sample certificate in RFC 9580, appendix A.3
+    const SAMPLE_KEY: &str = "f94da7bb48d60a61e567706a6587d0331999bb9d891a08242ead84543df895a3";
+
+    #[test]
+    fn rfc9580_sample() {
+        let material = hex::decode(SAMPLE_KEY).unwrap();
+        let packet = KeyVersion::V6.key_packet(0x63877FE3, 27, &material);
+        assert_eq!(
+            hex::encode(&packet[5..]),
+            format!("0663877fe31b00000020{}", SAMPLE_KEY)
+        );
+        assert_eq!(packet[..5], [0x9B, 0, 0, 0, 42]);
+        assert_eq!(
+            &packet[KeyVersion::V6.timestamp_offset()..][..4],
+            &[0x63, 0x87, 0x7F, 0xE3]
+        );
+        let digest = Digest::from(Sha256::digest(&packet));
+        assert_eq!(
+            digest.to_hex(),
+            "CB186C4F0609A697E4D52DFA6C722B0C1F1E27C18A56708F6525EC27BAD9ACC9"
+        );
+
+        let batch = FingerprintBatch::new(KeyVersion::V6, &packet, 6);
+        let mut out = [Digest::default(); 2];
+        batch.digests(&[0x63877FE3, 0x63877FE4], &mut out);
+        assert_eq!(out[0], digest);
+        assert_ne!(out[1], digest);
+    }
+
+    #[test]
+    fn librepgp_v5() {
+        // Computed independently from the LibrePGP layout with Python's hashlib, for the RFC 9580
+        // sample key as a legacy EdDSA key
+        let material = hex::decode(format!("092b06010401da470f01010740{}", SAMPLE_KEY)).unwrap();
+        let packet = KeyVersion::V5.key_packet(0x63877FE3, 22, &material);
+        assert_eq!(packet[..6], [0x9A, 0, 0, 0, 55, 5]);
+        assert_eq!(packet[10..15], [22, 0, 0, 0, 45]);
+        let digest = Digest::from(Sha256::digest(&packet));
+        assert_eq!(
+            digest.to_hex(),
+            "17BCF8366D9436F7AF37EADA156C6494656E5A9769CF2D5CB345F8B83436F652"
+        );
+
+        let offset = KeyVersion::V5.timestamp_offset();
+        assert_eq!(&packet[offset..][..4], &[0x63, 0x87, 0x7F, 0xE3]);
+        let mut out = [Digest::default(); 1];
+        FingerprintBatch::new(KeyVersion::V5, &packet, offset).digests(&[0x63877FE3], &mut out);
+        assert_eq!(out[0], digest);
+    }
+
+    #[test]
+    fn v4_packet() {
+        let packet = KeyVersion::V4.key_packet(0x01020304, 22, &[0xAA; 51]);
+        assert_eq!(packet[..9], [0x99, 0, 57, 4, 1, 2, 3, 4, 22]);
+        assert_eq!(packet.len(), 60);
+        assert_eq!(
+            &packet[KeyVersion::V4.timestamp_offset()..][..4],
+            &[1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn digest() {
+        let digest = Digest::from([0xAB; 20]);
+        assert_eq!(digest.len(), 20);
+        assert_eq!(&digest[..], &[0xAB; 20]);
+        assert_eq!(encode_upper(&*digest), digest.to_hex());
+        assert_eq!(Digest::new(&[0xAB; 32]).len(), 32);
+        assert_ne!(Digest::new(&[0; 20]), Digest::new(&[0; 32]));
+        assert!(Digest::default().is_empty());
+    }
+
+    #[test]
+    fn parse() {
+        for version in KeyVersion::ALL {
+            assert_eq!(version.name().parse::<KeyVersion>().unwrap(), version);
+        }
+        assert_eq!("6".parse::<KeyVersion>().unwrap(), KeyVersion::V6);
+        assert_eq!("5".parse::<KeyVersion>().unwrap(), KeyVersion::V5);
+        assert_eq!("V4".parse::<KeyVersion>().unwrap(), KeyVersion::V4);
+        assert!("v3".parse::<KeyVersion>().is_err());
+    }
+
+    #[test]
+    fn descending() {
+        let message = b"The quick brown fox jumps over the lazy dog";
+        for version in KeyVersion::ALL {
+            let hasher = FingerprintBatch::new(version, message, 4);
+            let mut out = vec![Digest::default(); 100];
+            assert_eq!(hasher.digests_descending(70, &mut out), 71);
+            let mut single = [Digest::default(); 1];
+            for (offset, digest) in out.iter().take(71).enumerate() {
+                hasher.digests(&[70 - offset as u32], &mut single);
+                assert_eq!(digest, &single[0]);
+                assert_eq!(digest.len(), version.digest_bytes());
+            }
+            assert_eq!(hasher.digests_descending(1000, &mut out), 100);
+            assert_eq!(hasher.digests_descending(0, &mut out), 1);
+            hasher.digests(&[0], &mut single);
+            assert_eq!(out[0], single[0]);
+        }
+    }
+
+    #[test]
+    fn ascending() {
+        let message = b"The quick brown fox jumps over the lazy dog";
+        for version in KeyVersion::ALL {
+            let hasher = FingerprintBatch::new(version, message, 4);
+            let mut out = vec![Digest::default(); 100];
+            assert_eq!(hasher.digests_ascending(u32::MAX - 70, &mut out), 71);
+            let mut single = [Digest::default(); 1];
+            for (offset, digest) in out.iter().take(71).enumerate() {
+                hasher.digests(&[u32::MAX - 70 + offset as u32], &mut single);
+                assert_eq!(digest, &single[0]);
+            }
+            assert_eq!(hasher.digests_ascending(1000, &mut out), 100);
+        }
+    }
+}
@@ -12,50 +12,56 @@ extern "C" {
     fn sha1_to_hex_neon_(binary: *const u8, hex: *mut u8);
 }
 
+use super::MAX_DIGEST_BYTES;
+
 static TABLE: &[u8; 16] = b"0123456789ABCDEF";
 
-/// SHA-1 binary to hex conversion with SSE4.1
-/// We have a fixed input and output length
+/// Binary to hex conversion with SSE4.1
+/// 16 bytes at a time, the rest goes through the fallback
 #[target_feature(enable = "sse4.1")]
 #[cfg(target_arch = "x86_64")]
-unsafe fn sha1_to_hex_sse41(binary: &[u8], hex: &mut [u8]) {
+unsafe fn digest_to_hex_sse41(binary: &[u8], hex: &mut [u8]) {
     let ascii_zero = _mm_set1_epi8(b'0' as i8);
     let nines = _mm_set1_epi8(9);
     let ascii_a = _mm_set1_epi8((b'A' - 9 - 1) as i8);
     let and4bits = _mm_set1_epi8(0xf);
 
-    let invec = _mm_loadu_si128(binary.as_ptr() as *const _);
+    let mut chunks = binary.chunks_exact(16);
+    for (chunk, hex) in (&mut chunks).zip(hex.chunks_mut(32)) {
+        let invec = _mm_loadu_si128(chunk.as_ptr() as *const _);
 
-    let masked1 = _mm_and_si128(invec, and4bits);
-    let masked2 = _mm_and_si128(_mm_srli_epi64(invec, 4), and4bits);
+        let masked1 = _mm_and_si128(invec, and4bits);
+        let masked2 = _mm_and_si128(_mm_srli_epi64(invec, 4), and4bits);
 
-    // return 0xff corresponding to the elements > 9, or 0x00 otherwise
-    let cmpmask1 = _mm_cmpgt_epi8(masked1, nines);
-    let cmpmask2 = _mm_cmpgt_epi8(masked2, nines);
+        // return 0xff corresponding to the elements > 9, or 0x00 otherwise
+        let cmpmask1 = _mm_cmpgt_epi8(masked1, nines);
+        let cmpmask2 = _mm_cmpgt_epi8(masked2, nines);
 
-    // add '0' or the offset depending on the masks
-    let masked1 = _mm_add_epi8(masked1, _mm_blendv_epi8(ascii_zero, ascii_a, cmpmask1));
-    let masked2 = _mm_add_epi8(masked2, _mm_blendv_epi8(ascii_zero, ascii_a, cmpmask2));
+        // add '0' or the offset depending on the masks
+        let masked1 = _mm_add_epi8(masked1, _mm_blendv_epi8(ascii_zero, ascii_a, cmpmask1));
+        let masked2 = _mm_add_epi8(masked2, _mm_blendv_epi8(ascii_zero, ascii_a, cmpmask2));
 
-    // interleave masked1 and masked2 bytes
-    let res1 = _mm_unpacklo_epi8(masked2, masked1);
-    let res2 = _mm_unpackhi_epi8(masked2, masked1);
+        // interleave masked1 and masked2 bytes
+        let res1 = _mm_unpacklo_epi8(masked2, masked1);
+        let res2 = _mm_unpackhi_epi8(masked2, masked1);
 
-    _mm_storeu_si128(hex.as_mut_ptr() as *mut _, res1);
-    _mm_storeu_si128(hex.as_mut_ptr().offset(16) as *mut _, res2);
+        _mm_storeu_si128(hex.as_mut_ptr() as *mut _, res1);
+        _mm_storeu_si128(hex.as_mut_ptr().offset(16) as *mut _, res2);
+    }
 
-    hex_fallback(&binary[16..], &mut hex[32..]);
+    let done = binary.len() - chunks.remainder().len();
+    hex_fallback(chunks.remainder(), &mut hex[done * 2..]);
 }
 
-/// SHA-1 binary to hex conversion with SSE4.1
-/// We have a fixed input and output length, and have to pad the input and output
+/// Binary to hex conversion with AVX2
+/// Up to 32 bytes at once, we have to pad the input and output
 #[target_feature(enable = "avx2")]
 #[cfg(target_arch = "x86_64")]
-unsafe fn sha1_to_hex_avx2(binary: &[u8], hex: &mut [u8]) {
+unsafe fn digest_to_hex_avx2(binary: &[u8], hex: &mut [u8]) {
     // Preparing padded input and output
     let mut padded_output: [u8; 64] = [0x0; 64];
     let mut padded_input: [u8; 32] = [0x0; 32];
-    std::ptr::copy_nonoverlapping(binary.as_ptr(), padded_input.as_mut_ptr(), 20);
+    std::ptr::copy_nonoverlapping(binary.as_ptr(), padded_input.as_mut_ptr(), binary.len());
 
     let ascii_zero = _mm256_set1_epi8(b'0' as i8);
     let nines = _mm256_set1_epi8(9);
@@ -86,14 +92,20 @@ unsafe fn sha1_to_hex_avx2(binary: &[u8], hex: &mut [u8]) {
     _mm256_storeu2_m128i(base3, base1, res1);
     _mm256_storeu2_m128i(base4, base2, res2);
 
-    std::ptr::copy_nonoverlapping(padded_output.as_ptr(), hex.as_mut_ptr(), 40);
+    std::ptr::copy_nonoverlapping(padded_output.as_ptr(), hex.as_mut_ptr(), binary.len() * 2);
 }
 
+/// Binary to hex conversion with NEON
+/// 16 bytes at a time, the rest goes through the fallback
 #[cfg(target_arch = "aarch64")]
-unsafe fn sha1_to_hex_neon(binary: &[u8], hex: &mut [u8]) {
-    sha1_to_hex_neon_(binary.as_ptr(), hex.as_mut_ptr());
+unsafe fn digest_to_hex_neon(binary: &[u8], hex: &mut [u8]) {
+    let mut chunks = binary.chunks_exact(16);
+    for (chunk, hex) in (&mut chunks).zip(hex.chunks_mut(32)) {
+        sha1_to_hex_neon_(chunk.as_ptr(), hex.as_mut_ptr());
+    }
 
-    hex_fallback(&binary[16..], &mut hex[32..]);
+    let done = binary.len() - chunks.remainder().len();
+    hex_fallback(chunks.remainder(), &mut hex[done * 2..]);
 }
 
 /// Software implementation of binary to hex
@@ -104,60 +116,148 @@ fn hex_fallback(binary: &[u8], hex: &mut [u8]) {
     }
 }
 
-/// SHA-1 binary to hex
-pub fn sha1_to_hex(binary: &[u8]) -> String {
-    let mut result: Vec<u8> = vec![0x0; 40];
+/// Implementations of the hex conversion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HexImplementation {
+    /// 32 bytes at once with AVX2
+    Avx2,
+    /// 16 bytes at once with SSE4.1
+    Sse41,
+    /// 16 bytes at once with NEON
+    Neon,
+    /// One byte at a time
+    Fallback,
+}
 
-    if cfg!(target_arch = "x86_64") {
+impl HexImplementation {
+    /// The preferred implementation of the current CPU
+    pub fn detect() -> Self {
         #[cfg(target_arch = "x86_64")]
-        if is_x86_feature_detected!("avx2") {
-            unsafe { sha1_to_hex_avx2(binary, &mut result) }
-        } else if is_x86_feature_detected!("sse4.1") {
-            unsafe { sha1_to_hex_sse41(binary, &mut result) }
+        {
+            if is_x86_feature_detected!("avx2") {
+                return HexImplementation::Avx2;
+            }
+            if is_x86_feature_detected!("sse4.1") {
+                return HexImplementation::Sse41;
+            }
+        }
+        if cfg!(target_arch = "aarch64") {
+            HexImplementation::Neon
         } else {
-            hex_fallback(binary, &mut result);
+            HexImplementation::Fallback
+        }
+    }
+
+    /// Every implementation supported by the current CPU, in order of preference
+    pub fn available() -> Vec<Self> {
+        let mut implementations = Vec::new();
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                implementations.push(HexImplementation::Avx2);
+            }
+            if is_x86_feature_detected!("sse4.1") {
+                implementations.push(HexImplementation::Sse41);
+            }
         }
-    } else if cfg!(target_arch = "aarch64") {
         #[cfg(target_arch = "aarch64")]
-        unsafe {
-            sha1_to_hex_neon(binary, &mut result)
+        implementations.push(HexImplementation::Neon);
+        implementations.push(HexImplementation::Fallback);
+        implementations
+    }
+
+    /// Get the name
+    pub fn name(self) -> &'static str {
+        match self {
+            HexImplementation::Avx2 => "avx2",
+            HexImplementation::Sse41 => "sse4.1",
+            HexImplementation::Neon => "neon",
+            HexImplementation::Fallback => "fallback",
+        }
+    }
+
+    /// Binary fingerprint to hex with this implementation
+    ///
+    /// Falls back to the software implementation if this one isn't supported by the CPU.
+    pub fn to_hex(self, binary: &[u8]) -> String {
+        let mut result: Vec<u8> = vec![0x0; binary.len() * 2];
+
+        match self {
+            #[cfg(target_arch = "x86_64")]
+            HexImplementation::Avx2
+                if binary.len() <= MAX_DIGEST_BYTES && is_x86_feature_detected!("avx2") =>
+            unsafe { digest_to_hex_avx2(binary, &mut result) },
+            #[cfg(target_arch = "x86_64")]
+            HexImplementation::Sse41 if is_x86_feature_detected!("sse4.1") => unsafe {
+                digest_to_hex_sse41(binary, &mut result)
+            },
+            #[cfg(target_arch = "aarch64")]
+            HexImplementation::Neon => unsafe { digest_to_hex_neon(binary, &mut result) },
+            _ => hex_fallback(binary, &mut result),
         }
-    } else {
-        hex_fallback(binary, &mut result);
+
+        unsafe { String::from_utf8_unchecked(result) }
     }
+}
 
-    unsafe { String::from_utf8_unchecked(result) }
+/// Binary fingerprint (SHA-1 or SHA-256) to hex
+pub fn digest_to_hex(binary: &[u8]) -> String {
+    HexImplementation::detect().to_hex(binary)
+}
+
+/// SHA-1 binary to hex
+pub fn sha1_to_hex(binary: &[u8]) -> String {
+    digest_to_hex(binary)
 }
 
 #[cfg(test)]
 mod hex_test {
-    use super::sha1_to_hex;
+    use super::{digest_to_hex, sha1_to_hex, HexImplementation};
     #[cfg(target_arch = "x86_64")]
-    use super::{sha1_to_hex_avx2, sha1_to_hex_sse41};
+    use super::{digest_to_hex_avx2, digest_to_hex_sse41};
     use hex::encode_upper;
 
+    /// A SHA-1 and a SHA-256 sized input
+    const DATA: [&[u8]; 2] = [b"0123456789ABCDEFGHIJ", b"0123456789ABCDEFGHIJKLMNOPQRSTUV"];
+
     #[test]
     #[cfg(target_arch = "x86_64")]
-    fn test_sha1_to_hex_sse41() {
+    fn test_digest_to_hex_sse41() {
         if is_x86_feature_detected!("sse4.1") {
-            let data: &[u8; 20] = b"0123456789ABCDEFGHIJ";
-            let mut result: Vec<u8> = vec![0x0; 40];
-            unsafe {
-                sha1_to_hex_sse41(data, &mut result);
+            for data in DATA {
+                let mut result: Vec<u8> = vec![0x0; data.len() * 2];
+                unsafe {
+                    digest_to_hex_sse41(data, &mut result);
+                }
+                let hex_string = unsafe { String::from_utf8_unchecked(result) };
+                assert_eq!(hex_string, encode_upper(data));
             }
-            let hex_string = unsafe { String::from_utf8_unchecked(result) };
-            assert_eq!(hex_string, encode_upper(data));
         }
     }
 
     #[test]
     #[cfg(target_arch = "x86_64")]
-    fn test_sha1_to_hex_avx2() {
+    fn test_digest_to_hex_avx2() {
         if is_x86_feature_detected!("avx2") {
-            let data: &[u8; 20] = b"0123456789ABCDEFGHIJ";
-            let mut result: Vec<u8> = vec![0x0; 40];
+            for data in DATA {
+                let mut result: Vec<u8> = vec![0x0; data.len() * 2];
+                unsafe {
+                    digest_to_hex_avx2(data, &mut result);
+                }
+                let hex_string = unsafe { String::from_utf8_unchecked(result) };
+                assert_eq!(hex_string, encode_upper(data));
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn test_digest_to_hex_neon() {
+        use super::digest_to_hex_neon;
+        for data in DATA {
+            let mut result: Vec<u8> = vec![0x0; data.len() * 2];
             unsafe {
-                sha1_to_hex_avx2(data, &mut result);
+                digest_to_hex_neon(data, &mut result);
             }
             let hex_string = unsafe { String::from_utf8_unchecked(result) };
             assert_eq!(hex_string, encode_upper(data));
@@ -165,21 +265,24 @@ mod hex_test {
     }
 
     #[test]
-    #[cfg(target_arch = "aarch64")]
-    fn test_sha1_to_hex_neon() {
-        use super::sha1_to_hex_neon;
-        let data: &[u8; 20] = b"0123456789ABCDEFGHIJ";
-        let mut result: Vec<u8> = vec![0x0; 40];
-        unsafe {
-            sha1_to_hex_neon(data, &mut result);
+    fn test_digest_to_hex() {
+        for data in DATA {
+            assert_eq!(digest_to_hex(data), encode_upper(data));
         }
-        let hex_string = unsafe { String::from_utf8_unchecked(result) };
-        assert_eq!(hex_string, encode_upper(data));
+        assert_eq!(sha1_to_hex(DATA[0]), encode_upper(DATA[0]));
     }
 
     #[test]
-    fn test_sha1_to_hex() {
-        let data: &[u8; 20] = b"0123456789ABCDEFGHIJ";
-        assert_eq!(sha1_to_hex(data), encode_upper(data));
+    fn test_hex_implementations() {
+        assert_eq!(
+            HexImplementation::detect(),
+            HexImplementation::available()[0]
+        );
+        for data in DATA {
+            for implementation in HexImplementation::available() {
+                assert_eq!(implementation.to_hex(data), encode_upper(data));
+            }
+            assert_eq!(HexImplementation::Fallback.to_hex(data), encode_upper(data));
+        }
     }
 }
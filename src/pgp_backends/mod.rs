@@ -1,7 +1,15 @@
 //! OpenPGP processing backends
 //!
 //! This module contains adapters or wrappers for different OpenPGP implementations.
+mod certificate;
+#[cfg(feature = "sequoia")]
+mod ec;
+mod exponent;
+mod fingerprint;
 mod hex;
+mod sha1_batch;
+mod sha256;
+mod window;
 
 #[cfg(feature = "rpgp")]
 mod rpgp_backend;
@@ -11,7 +19,11 @@ mod sequoia_backend;
 pub use anyhow::Error as UniversalError;
 use thiserror::Error;
 
-pub use self::hex::sha1_to_hex;
+pub use self::fingerprint::{Digest, FingerprintBatch, KeyVersion, MAX_DIGEST_BYTES};
+pub use self::hex::{digest_to_hex, sha1_to_hex, HexImplementation};
+pub use self::sha1_batch::{BatchImplementation, Sha1Batch};
+pub use self::sha256::{Sha256, Sha256Batch};
+pub use self::window::{parse_timestamp, unix_timestamp, CreationWindow, Walk};
 
 #[cfg(feature = "sequoia")]
 pub use sequoia_backend::SequoiaBackend;
@@ -47,6 +59,24 @@ pub enum PGPError {
     InvalidKeyGenerated,
     #[error("Failed to modify generation time")]
     FailedToModifyGenerationTime,
+    #[error("Failed to modify public exponent")]
+    FailedToModifyPublicExponent,
+    #[error("Failed to modify public point")]
+    FailedToModifyPublicPoint,
+    #[error("Variation not supported: {0}")]
+    VariationNotSupported(String),
+    #[error("Walk not supported: {0}")]
+    WalkNotSupported(String),
+    #[error("Invalid timestamp: {0}")]
+    InvalidTimestamp(String),
+    #[error("The creation time window is empty")]
+    EmptyCreationWindow,
+    #[error("No creation time left in the window")]
+    CreationWindowExhausted,
+    #[error("Time out of the range of OpenPGP timestamps (1970 to 2106)")]
+    TimestampOutOfRange,
+    #[error("Key version not supported: {0}")]
+    KeyVersionNotSupported(String),
 }
 
 /// Cipher suites for OpenPGP keys
@@ -86,6 +116,27 @@ pub enum Algorithms {
     Ecc(Curve),
 }
 
+/// What a shuffle changes in the primary key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Variation {
+    /// Step the creation time through its window
+    #[default]
+    CreationTime,
+    /// Step the public exponent upwards, RSA keys only
+    RsaExponent,
+}
+
+/// How keys are generated and shuffled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyOptions {
+    /// What a shuffle changes
+    pub variation: Variation,
+    /// Creation times the key may have
+    pub window: CreationWindow,
+    /// Packet version, which decides how the fingerprint is computed
+    pub version: KeyVersion,
+}
+
 /// UserID
 #[derive(Debug, Clone)]
 pub struct UserID {
@@ -101,12 +152,58 @@ pub struct ArmoredKey {
 
 /// Backend adaptor trait
 pub trait Backend {
+    /// Get the raw digest of the key (the binary fingerprint), SHA-1 or SHA-256 depending on the
+    /// key version
+    fn fingerprint_digest(&self) -> Digest;
+
     /// Get the fingerprint of the key
-    fn fingerprint(&self) -> String;
+    fn fingerprint(&self) -> String {
+        digest_to_hex(&self.fingerprint_digest())
+    }
+
+    /// Get the digests of the current key and of the keys following it
+    ///
+    /// `out[i]` receives the digest the key would have after `i` more shuffles, the key itself
+    /// isn't moved. Returns the number of digests written, which may be less than `out.len()`
+    /// but is at least one for a non-empty buffer. The default only fingerprints the current key.
+    fn fingerprints(&mut self, out: &mut [Digest]) -> usize {
+        match out.first_mut() {
+            Some(digest) => {
+                *digest = self.fingerprint_digest();
+                1
+            }
+            None => 0,
+        }
+    }
 
     /// Rehash the fingerprint
     fn shuffle(&mut self) -> Result<(), PGPError>;
 
+    /// Shuffle `count` times at once
+    fn advance(&mut self, count: usize) -> Result<(), PGPError> {
+        for _ in 0..count {
+            self.shuffle()?;
+        }
+        Ok(())
+    }
+
+    /// Replace the key with a new one derived from it, instead of generating one
+    ///
+    /// The key starts over from its original creation time. Returns `false` if the backend
+    /// can't derive keys of this kind, which is what the default does.
+    fn renew(&mut self) -> Result<bool, PGPError> {
+        Ok(false)
+    }
+
+    /// Restrict the key to slice `index` of `count` disjoint slices of its creation times
+    ///
+    /// The key starts over at the beginning of its slice, and renewing it starts over there too.
+    /// Fails if the slice is empty. Returns `false` if the backend can't slice keys of this kind,
+    /// which is what the default does.
+    fn slice(&mut self, _index: usize, _count: usize) -> Result<bool, PGPError> {
+        Ok(false)
+    }
+
     /// Get armored secret key and public key
     fn get_armored_results(self, uid: &UserID) -> Result<ArmoredKey, UniversalError>;
 }
@@ -187,6 +284,37 @@ impl CipherSuite {
     }
 }
 
+impl Variation {
+    /// All variations
+    pub const ALL: [Variation; 2] = [Variation::CreationTime, Variation::RsaExponent];
+
+    /// Name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Variation::CreationTime => "creation-time",
+            Variation::RsaExponent => "rsa-exponent",
+        }
+    }
+}
+
+impl FromStr for Variation {
+    type Err = PGPError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|variation| variation.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| PGPError::VariationNotSupported(String::from(s)))
+    }
+}
+
+impl std::fmt::Display for Variation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 impl UserID {
     /// Unwrap the UserID
     pub fn get_id(&self) -> Option<String> {
@@ -16,20 +16,29 @@ use pgp::packet::{
 };
 use pgp::ser::Serialize;
 use pgp::types::{
-    CompressionAlgorithm, KeyVersion, PublicParams, SecretKeyTrait, SecretParams, Version,
+    CompressionAlgorithm, KeyVersion as PacketKeyVersion, Mpi, PlainSecretParams, PublicParams,
+    SecretKeyTrait, SecretParams, Version,
 };
 use rand::rngs::StdRng;
-use rand::SeedableRng;
-use sha1::{Digest, Sha1};
+use rand::{RngCore, SeedableRng};
+use sha1::{Digest as _, Sha1};
 use smallvec::smallvec;
 
-use super::{sha1_to_hex, ArmoredKey, Backend, CipherSuite, PGPError, UniversalError, UserID};
+use super::certificate::{self, Certificate, KeyMaterial};
+use super::exponent::ExponentWalk;
+use super::window::TimestampWalk;
+use super::{
+    unix_timestamp, ArmoredKey, Backend, CipherSuite, Digest, FingerprintBatch, KeyOptions,
+    KeyVersion, PGPError, Sha256, UniversalError, UserID, Variation,
+};
+
+use std::time::SystemTime;
 
 /// Converter for transmuting to struct with private fields
 #[allow(dead_code)]
 struct PublicKeyPacketConverter {
     packet_version: Version,
-    version: KeyVersion,
+    version: PacketKeyVersion,
     algorithm: PublicKeyAlgorithm,
     created_at: DateTime<Utc>,
     expiration: Option<u16>,
@@ -51,14 +60,23 @@ struct SecretSubkeyPacketConverter {
 }
 
 /// VanityGPG backend powered by rPGP
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct RPGPBackend {
     public_params: PublicParams,
     secret_params: SecretParams,
     key_type: KeyType,
     cipher_suite: CipherSuite,
-    timestamp: u32,
+    creation: TimestampWalk,
     packet_cache: Vec<u8>,
+    batch: FingerprintBatch,
+    version: KeyVersion,
+    exponent: Option<ExponentWalk>,
+}
+
+/// Signs v5 and v6 certificates with the primary key
+struct CertificateSigner {
+    key: SecretKeyPacket,
+    rng: StdRng,
 }
 
 /// Generate key with the required `CipherSuite`
@@ -101,6 +119,99 @@ fn generate_key(
     }
 }
 
+/// Replace the public exponent of RSA parameters with the current exponent of `walk`
+///
+/// The private exponent is recomputed to match.
+fn with_exponent(
+    public_params: &PublicParams,
+    secret_params: &SecretParams,
+    walk: &ExponentWalk,
+) -> Result<(PublicParams, SecretParams), PGPError> {
+    match (public_params, secret_params) {
+        (
+            PublicParams::RSA { n, .. },
+            SecretParams::Plain(PlainSecretParams::RSA { p, q, u, .. }),
+        ) => Ok((
+            PublicParams::RSA {
+                n: n.clone(),
+                e: Mpi::from_raw(walk.exponent().to_be_bytes().to_vec()),
+            },
+            SecretParams::Plain(PlainSecretParams::RSA {
+                d: Mpi::from_raw(walk.private_exponent()?),
+                p: p.clone(),
+                q: q.clone(),
+                u: u.clone(),
+            }),
+        )),
+        _ => Err(PGPError::VariationNotSupported(
+            Variation::RsaExponent.to_string(),
+        )),
+    }
+}
+
+/// Get the key material of a key as stored in packets of `version`
+///
+/// Curve25519 keys change to the native Ed25519 and X25519 algorithms in v6 keys, everything else
+/// keeps its MPIs.
+fn key_material(
+    version: KeyVersion,
+    key_type: &KeyType,
+    public_params: &PublicParams,
+    secret_params: &SecretParams,
+) -> Result<KeyMaterial, PGPError> {
+    let secret_params = match secret_params {
+        SecretParams::Plain(secret_params) => secret_params,
+        SecretParams::Encrypted(_) => return Err(PGPError::MysteriousError),
+    };
+    let secret: Vec<&Mpi> = match (public_params, secret_params) {
+        (PublicParams::EdDSA { q, .. }, PlainSecretParams::EdDSA(seed))
+            if version == KeyVersion::V6 =>
+        {
+            return Ok(KeyMaterial::ed25519(&q.as_bytes()[1..], seed.as_bytes()));
+        }
+        (PublicParams::ECDH { p, .. }, PlainSecretParams::ECDH(scalar))
+            if version == KeyVersion::V6 =>
+        {
+            return Ok(KeyMaterial::x25519(&p.as_bytes()[1..], scalar.as_bytes()));
+        }
+        (_, PlainSecretParams::RSA { d, p, q, u }) => vec![d, p, q, u],
+        (_, PlainSecretParams::EdDSA(secret) | PlainSecretParams::ECDH(secret)) => vec![secret],
+        _ => return Err(PGPError::InvalidKeyGenerated),
+    };
+    let mut public = Vec::new();
+    public_params
+        .to_writer(&mut public)
+        .map_err(|_| PGPError::InvalidKeyGenerated)?;
+    Ok(KeyMaterial::new(
+        key_type.to_alg() as u8,
+        public,
+        secret
+            .iter()
+            .flat_map(|value| certificate::mpi(value.as_bytes()))
+            .collect(),
+    ))
+}
+
+impl certificate::Signer for CertificateSigner {
+    fn hash_algorithm(&self) -> u8 {
+        certificate::HASH_SHA512
+    }
+
+    fn hash(&self, data: &[u8]) -> Result<Vec<u8>, UniversalError> {
+        Ok(HashAlgorithm::SHA2_512.digest(data)?)
+    }
+
+    fn sign(&mut self, digest: &[u8]) -> Result<Vec<Vec<u8>>, UniversalError> {
+        Ok(self
+            .key
+            .create_signature(|| "".to_string(), HashAlgorithm::SHA2_512, digest)?)
+    }
+
+    fn random(&mut self, buffer: &mut [u8]) {
+        self.rng.fill_bytes(buffer);
+    }
+}
+
 impl Into<PublicKeyPacket> for PublicKeyPacketConverter {
     /// Transmuting to `PublicKeyPacket`
     fn into(self) -> PublicKeyPacket {
@@ -120,9 +231,9 @@ impl PublicKeyPacketConverter {
     fn new(algorithm: PublicKeyAlgorithm, public_params: PublicParams, created_at: u32) -> Self {
         Self {
             packet_version: Version::New,
-            version: KeyVersion::V4,
+            version: PacketKeyVersion::V4,
             algorithm,
-            created_at: Utc.timestamp(created_at as i64, 0),
+            created_at: Utc.timestamp(i64::from(created_at), 0),
             expiration: None,
             public_params,
         }
@@ -164,19 +275,87 @@ impl SecretSubkeyPacketConverter {
 }
 
 impl Backend for RPGPBackend {
-    fn fingerprint(&self) -> String {
-        let mut hasher = Sha1::new();
-        hasher.update(&self.packet_cache);
-        sha1_to_hex(&hasher.finalize().to_vec())
+    fn fingerprint_digest(&self) -> Digest {
+        match self.version {
+            KeyVersion::V4 => {
+                let mut hasher = Sha1::new();
+                hasher.update(&self.packet_cache);
+                Digest::new(&hasher.finalize())
+            }
+            KeyVersion::V5 | KeyVersion::V6 => Digest::from(Sha256::digest(&self.packet_cache)),
+        }
+    }
+
+    fn fingerprints(&mut self, out: &mut [Digest]) -> usize {
+        match &mut self.exponent {
+            Some(walk) => walk.fingerprints(&self.batch, out),
+            None => self.creation.fingerprints(&self.batch, out),
+        }
     }
 
     fn shuffle(&mut self) -> Result<(), PGPError> {
-        self.timestamp -= 1;
-        BigEndian::write_u32(&mut self.packet_cache[4..8], self.timestamp);
+        self.advance(1)
+    }
+
+    fn advance(&mut self, count: usize) -> Result<(), PGPError> {
+        if let Some(walk) = &mut self.exponent {
+            walk.advance(count)?;
+            let length = self.packet_cache.len();
+            BigEndian::write_u32(&mut self.packet_cache[length - 4..], walk.exponent());
+            return Ok(());
+        }
+        self.creation.advance(count)?;
+        self.write_timestamp();
         Ok(())
     }
 
-    fn get_armored_results(self, uid: &UserID) -> Result<ArmoredKey, UniversalError> {
+    fn slice(&mut self, index: usize, count: usize) -> Result<bool, PGPError> {
+        if self.exponent.is_some() {
+            return Ok(false);
+        }
+        self.creation.slice(index, count)?;
+        self.write_timestamp();
+        Ok(true)
+    }
+
+    fn get_armored_results(mut self, uid: &UserID) -> Result<ArmoredKey, UniversalError> {
+        if let Some(walk) = &self.exponent {
+            (self.public_params, self.secret_params) =
+                with_exponent(&self.public_params, &self.secret_params, walk)?;
+        }
+
+        if self.version != KeyVersion::V4 {
+            let (subkey_type, subkey_public_params, subkey_secret_params) =
+                generate_key(&self.cipher_suite, false)?;
+            let certificate = Certificate::new(
+                self.version,
+                self.creation.timestamp(),
+                key_material(
+                    self.version,
+                    &self.key_type,
+                    &self.public_params,
+                    &self.secret_params,
+                )?,
+                key_material(
+                    self.version,
+                    &subkey_type,
+                    &subkey_public_params,
+                    &subkey_secret_params,
+                )?,
+            );
+            let public_key_packet: PublicKeyPacket = PublicKeyPacketConverter::new(
+                self.key_type.to_alg(),
+                self.public_params,
+                self.creation.timestamp(),
+            )
+            .into();
+            let mut signer = CertificateSigner {
+                key: SecretKeyPacketConverter::new(public_key_packet, self.secret_params).into(),
+                rng: StdRng::from_entropy(),
+            };
+            return certificate.to_armored(&mut signer, uid);
+        }
+
         // Generate Subkey
         let mut subkey_flags = KeyFlags::default();
         subkey_flags.set_encrypt_storage(true);
@@ -186,7 +365,7 @@ impl Backend for RPGPBackend {
         let public_subkey_packet: PublicSubkeyPacket = PublicKeyPacketConverter::new(
             subkey_type.to_alg(),
             subkey_public_params,
-            self.timestamp,
+            self.creation.timestamp(),
         )
         .into();
         let secret_subkey_packet: SecretSubkeyPacket =
@@ -211,7 +390,7 @@ impl Backend for RPGPBackend {
         let primary_public_key_packet: PublicKeyPacket = PublicKeyPacketConverter::new(
             self.key_type.to_alg(),
             self.public_params,
-            self.timestamp,
+            self.creation.timestamp(),
         )
         .into();
         let primary_secret_key_packet: SecretKeyPacket =
@@ -239,32 +418,98 @@ impl Backend for RPGPBackend {
 impl RPGPBackend {
     /// Create new instance
     pub fn new<C: Into<CipherSuite>>(cipher_suite: C) -> Result<Self, PGPError> {
+        Self::with_options(cipher_suite, KeyOptions::default())
+    }
+
+    /// Create new instance which shuffles by changing `variation`
+    pub fn with_variation<C: Into<CipherSuite>>(
+        cipher_suite: C,
+        variation: Variation,
+    ) -> Result<Self, PGPError> {
+        Self::with_options(
+            cipher_suite,
+            KeyOptions {
+                variation,
+                ..KeyOptions::default()
+            },
+        )
+    }
+
+    /// Create new instance generated and shuffled according to `options`
+    pub fn with_options<C: Into<CipherSuite>>(
+        cipher_suite: C,
+        options: KeyOptions,
+    ) -> Result<Self, PGPError> {
+        let variation = options.variation;
         let valid_cipher_suite = cipher_suite.into();
-        if let Ok((key_type, public_params, secret_params)) =
+        if let Ok((key_type, mut public_params, mut secret_params)) =
             generate_key(&valid_cipher_suite, true)
         {
-            let timestamp = Utc::now().timestamp() as u32;
-            let mut packet_cache: Vec<u8> = vec![0x99, 0, 0, 4, 0, 0, 0, 0]; // Version 4
-            BigEndian::write_u32(&mut packet_cache[4..8], timestamp); // Timestamp
-            packet_cache.push(key_type.to_alg() as u8); // Algorithm identifier
-            public_params
-                .to_writer(&mut packet_cache)
-                .expect("Failed to write public_params to packet cache");
-            let packet_length = (packet_cache.len() as u16) - 3;
-            BigEndian::write_u16(&mut packet_cache[1..3], packet_length);
+            let exponent = match (variation, &secret_params) {
+                (Variation::CreationTime, _) => None,
+                (
+                    Variation::RsaExponent,
+                    SecretParams::Plain(PlainSecretParams::RSA { p, q, .. }),
+                ) => {
+                    let walk = ExponentWalk::new(p.as_bytes(), q.as_bytes())?;
+                    (public_params, secret_params) =
+                        with_exponent(&public_params, &secret_params, &walk)?;
+                    Some(walk)
+                }
+                (Variation::RsaExponent, _) => {
+                    return Err(PGPError::VariationNotSupported(variation.to_string()))
+                }
+            };
+            let window = options.window.until(unix_timestamp(SystemTime::now())?)?;
+            let creation = TimestampWalk::new(window);
+            let version = options.version;
+            let (algorithm, public_key_buffer) = match version {
+                KeyVersion::V4 => {
+                    let mut public_key_buffer = Vec::new();
+                    public_params
+                        .to_writer(&mut public_key_buffer)
+                        .expect("Failed to write public_params to packet cache");
+                    (key_type.to_alg() as u8, public_key_buffer)
+                }
+                _ => {
+                    let material =
+                        key_material(version, &key_type, &public_params, &secret_params)?;
+                    (material.algorithm(), material.public().to_vec())
+                }
+            };
+            let packet_cache =
+                version.key_packet(creation.timestamp(), algorithm, &public_key_buffer);
+
+            // The exponent is the last MPI, and exactly 4 bytes long
+            let batch = match exponent {
+                Some(_) => FingerprintBatch::new(version, &packet_cache, packet_cache.len() - 4),
+                None => FingerprintBatch::new(version, &packet_cache, version.timestamp_offset()),
+            };
             Ok(Self {
                 public_params,
                 secret_params,
                 key_type,
                 cipher_suite: valid_cipher_suite,
-                timestamp,
+                creation,
                 packet_cache,
+                batch,
+                version,
+                exponent,
             })
         } else {
             Err(PGPError::KeyGenerationFailed)
         }
     }
 
+    /// Write the current creation time into the packet cache
+    fn write_timestamp(&mut self) {
+        let offset = self.version.timestamp_offset();
+        BigEndian::write_u32(
+            &mut self.packet_cache[offset..offset + 4],
+            self.creation.timestamp(),
+        );
+    }
+
     #[allow(dead_code)]
     /// Get public params
     pub(crate) fn get_public_params(self) -> PublicParams {
@@ -272,21 +517,26 @@ impl RPGPBackend {
     }
 
     #[allow(dead_code)]
-    /// Get `u32` timestamp
-    // Boo! You've just found something that will go wrong after the year 2038
+    /// Get the current creation time, an unsigned timestamp valid until 2106
     pub(crate) fn get_timestamp(&self) -> u32 {
-        self.timestamp
+        self.creation.timestamp()
     }
 }
 
 #[cfg(test)]
 mod rpgp_backend_test {
     use super::{
-        Backend, CipherSuite, PublicKeyAlgorithm, PublicKeyPacket, PublicKeyPacketConverter,
-        RPGPBackend, UserID,
+        with_exponent, Backend, CipherSuite, PublicKeyAlgorithm, PublicKeyPacket,
+        PublicKeyPacketConverter, PublicParams, RPGPBackend, UserID, Variation,
+    };
+    use crate::pgp_backends::certificate::parse;
+    use crate::pgp_backends::{
+        BatchImplementation, CreationWindow, Digest, KeyOptions, KeyVersion, PGPError, Sha1Batch,
+        Sha256, Walk,
     };
     use hex::encode_upper;
     use pgp::composed::{Deserializable, SignedSecretKey};
+    use pgp::crypto::hash::HashAlgorithm;
     use pgp::types::KeyTrait;
     use std::io::Cursor;
 
@@ -314,6 +564,19 @@ mod rpgp_backend_test {
         assert_eq!(fingerprint_custom, fingerprint_rpgp);
     }
 
+    #[test]
+    fn ed25519_fingerprint_digest() {
+        let backend = RPGPBackend::new(CipherSuite::Curve25519).unwrap();
+        let digest = backend.fingerprint_digest();
+        let timestamp = backend.get_timestamp();
+        let public_params = backend.get_public_params();
+        let public_key_packet: PublicKeyPacket =
+            PublicKeyPacketConverter::new(PublicKeyAlgorithm::EdDSA, public_params, timestamp)
+                .into();
+
+        assert_eq!(digest.to_vec(), public_key_packet.fingerprint());
+    }
+
     #[test]
     fn ed25519_shuffle() {
         let mut backend = RPGPBackend::new(CipherSuite::Curve25519).unwrap();
@@ -344,6 +607,112 @@ mod rpgp_backend_test {
         assert_eq!(fingerprint_custom_after, fingerprint_rpgp_after);
     }
 
+    #[test]
+    fn ed25519_batch_known_answers() {
+        let backend = RPGPBackend::new(CipherSuite::Curve25519).unwrap();
+        let timestamp = backend.get_timestamp();
+        let values = [timestamp, timestamp - 1, timestamp - 86400];
+        let packet_cache = backend.packet_cache.clone();
+        let public_params = backend.get_public_params();
+        for implementation in BatchImplementation::available() {
+            let mut digests = [[0u8; 20]; 3];
+            Sha1Batch::new(&packet_cache, 4)
+                .with_implementation(implementation)
+                .digests(&values, &mut digests);
+            for (value, digest) in values.iter().zip(digests.iter()) {
+                let public_key_packet: PublicKeyPacket = PublicKeyPacketConverter::new(
+                    PublicKeyAlgorithm::EdDSA,
+                    public_params.clone(),
+                    *value,
+                )
+                .into();
+                assert_eq!(
+                    digest.to_vec(),
+                    public_key_packet.fingerprint(),
+                    "{}",
+                    implementation.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ed25519_fingerprints() {
+        let mut backend = RPGPBackend::new(CipherSuite::Curve25519).unwrap();
+        let mut skipped = backend.clone();
+        let mut batch = [Digest::default(); 21];
+        assert_eq!(backend.fingerprints(&mut batch), 21);
+        for digest in batch.iter().take(20) {
+            assert_eq!(digest, &backend.fingerprint_digest());
+            backend.shuffle().unwrap();
+        }
+        skipped.advance(20).unwrap();
+        assert_eq!(skipped.get_timestamp(), backend.get_timestamp());
+        assert_eq!(skipped.fingerprint_digest(), batch[20]);
+    }
+
+    #[test]
+    fn ed25519_window() {
+        // 2020-01-01T00:00:00Z to 2020-01-01T00:00:40Z
+        let window = CreationWindow::new(1577836800, 1577836840, Walk::Forward).unwrap();
+        let options = KeyOptions {
+            window,
+            ..KeyOptions::default()
+        };
+        let mut backend = RPGPBackend::with_options(CipherSuite::Curve25519, options).unwrap();
+        assert_eq!(backend.get_timestamp(), 1577836800);
+        let mut batch = [Digest::default(); 64];
+        assert_eq!(backend.fingerprints(&mut batch), 41);
+        backend.advance(40).unwrap();
+        assert_eq!(backend.get_timestamp(), 1577836840);
+        assert_eq!(backend.fingerprint_digest(), batch[40]);
+        assert!(matches!(
+            backend.shuffle(),
+            Err(PGPError::CreationWindowExhausted)
+        ));
+        assert_eq!(backend.get_timestamp(), 1577836840);
+
+        // A random walk patches the packet with every creation time of the window once
+        let window = CreationWindow::new(1577836800, 1577836899, Walk::Random).unwrap();
+        let options = KeyOptions {
+            window,
+            ..KeyOptions::default()
+        };
+        let mut backend = RPGPBackend::with_options(CipherSuite::Curve25519, options).unwrap();
+        let mut batch = [Digest::default(); 128];
+        assert_eq!(backend.fingerprints(&mut batch), 100);
+        let mut timestamps = Vec::new();
+        for digest in batch.iter().take(100) {
+            assert_eq!(digest, &backend.fingerprint_digest());
+            timestamps.push(backend.get_timestamp());
+            let _ = backend.shuffle();
+        }
+        timestamps.sort_unstable();
+        assert_eq!(timestamps, (1577836800..=1577836899).collect::<Vec<u32>>());
+
+        // Nothing is dated in the future
+        let future = CreationWindow::new(0, u32::MAX, Walk::Forward).unwrap();
+        let mut backend = RPGPBackend::with_options(
+            CipherSuite::Curve25519,
+            KeyOptions {
+                window: future,
+                ..KeyOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(backend.get_timestamp(), 0);
+        assert!(backend.advance(u32::MAX as usize).is_err());
+        let backward = CreationWindow::new(4102444800, u32::MAX, Walk::Backward).unwrap();
+        assert!(RPGPBackend::with_options(
+            CipherSuite::Curve25519,
+            KeyOptions {
+                window: backward,
+                ..KeyOptions::default()
+            },
+        )
+        .is_err());
+    }
+
     #[test]
     fn ed25519_export() {
         let mut backend = RPGPBackend::new(CipherSuite::Curve25519).unwrap();
@@ -416,6 +785,50 @@ mod rpgp_backend_test {
         assert_eq!(fingerprint_custom_after, fingerprint_rpgp_after);
     }
 
+    #[test]
+    fn rsa2048_batch_known_answers() {
+        let backend = RPGPBackend::new(CipherSuite::RSA2048).unwrap();
+        let timestamp = backend.get_timestamp();
+        let values = [timestamp, timestamp - 1, timestamp - 86400];
+        let packet_cache = backend.packet_cache.clone();
+        let public_params = backend.get_public_params();
+        for implementation in BatchImplementation::available() {
+            let mut digests = [[0u8; 20]; 3];
+            Sha1Batch::new(&packet_cache, 4)
+                .with_implementation(implementation)
+                .digests(&values, &mut digests);
+            for (value, digest) in values.iter().zip(digests.iter()) {
+                let public_key_packet: PublicKeyPacket = PublicKeyPacketConverter::new(
+                    PublicKeyAlgorithm::RSA,
+                    public_params.clone(),
+                    *value,
+                )
+                .into();
+                assert_eq!(
+                    digest.to_vec(),
+                    public_key_packet.fingerprint(),
+                    "{}",
+                    implementation.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rsa2048_fingerprints() {
+        let mut backend = RPGPBackend::new(CipherSuite::RSA2048).unwrap();
+        let mut skipped = backend.clone();
+        let mut batch = [Digest::default(); 21];
+        assert_eq!(backend.fingerprints(&mut batch), 21);
+        for digest in batch.iter().take(20) {
+            assert_eq!(digest, &backend.fingerprint_digest());
+            backend.shuffle().unwrap();
+        }
+        skipped.advance(20).unwrap();
+        assert_eq!(skipped.get_timestamp(), backend.get_timestamp());
+        assert_eq!(skipped.fingerprint_digest(), batch[20]);
+    }
+
     #[test]
     fn rsa2048_export() {
         let mut backend = RPGPBackend::new(CipherSuite::RSA2048).unwrap();
@@ -438,4 +851,178 @@ mod rpgp_backend_test {
         );
         key.verify().unwrap();
     }
+
+    #[test]
+    fn ed25519_exponent_not_supported() {
+        assert!(
+            RPGPBackend::with_variation(CipherSuite::Curve25519, Variation::RsaExponent).is_err()
+        );
+    }
+
+    #[test]
+    fn rsa2048_exponent_fingerprints() {
+        let mut backend =
+            RPGPBackend::with_variation(CipherSuite::RSA2048, Variation::RsaExponent).unwrap();
+        let timestamp = backend.get_timestamp();
+        let mut batch = [Digest::default(); 9];
+        assert_eq!(backend.fingerprints(&mut batch), 9);
+        for digest in batch.iter() {
+            let walk = backend.exponent.as_ref().unwrap();
+            let (public_params, _) =
+                with_exponent(&backend.public_params, &backend.secret_params, walk).unwrap();
+            let public_key_packet: PublicKeyPacket =
+                PublicKeyPacketConverter::new(PublicKeyAlgorithm::RSA, public_params, timestamp)
+                    .into();
+            assert_eq!(digest.to_vec(), public_key_packet.fingerprint());
+            assert_eq!(digest, &backend.fingerprint_digest());
+            backend.shuffle().unwrap();
+        }
+        assert_eq!(backend.get_timestamp(), timestamp);
+    }
+
+    #[test]
+    fn rsa2048_exponent_export() {
+        let mut backend =
+            RPGPBackend::with_variation(CipherSuite::RSA2048, Variation::RsaExponent).unwrap();
+        backend.advance(3).unwrap();
+        let exponent = backend.exponent.as_ref().unwrap().exponent();
+        let fingerprint_before = backend.fingerprint();
+        let uid = UserID::from("Tiansuo Li <114514@example.com>".to_string());
+        let results = backend.get_armored_results(&uid).unwrap();
+
+        let cursor = Cursor::new(results.get_private_key());
+        let key = SignedSecretKey::from_armor_single(cursor).unwrap().0;
+        assert_eq!(fingerprint_before, encode_upper(key.fingerprint()));
+        match key.primary_key.public_key().public_params() {
+            PublicParams::RSA { e, .. } => assert_eq!(e.as_bytes(), &exponent.to_be_bytes()[..]),
+            _ => unreachable!(),
+        }
+        key.verify().unwrap();
+    }
+
+    /// Options for v6 keys
+    fn v6_options() -> KeyOptions {
+        KeyOptions {
+            version: KeyVersion::V6,
+            ..KeyOptions::default()
+        }
+    }
+
+    #[test]
+    fn ed25519_v6_fingerprints() {
+        let mut backend = RPGPBackend::with_options(CipherSuite::Curve25519, v6_options()).unwrap();
+        let packet = &backend.packet_cache;
+        assert_eq!(&packet[..6], &[0x9B, 0, 0, 0, 42, 6]);
+        assert_eq!(&packet[10..15], &[27, 0, 0, 0, 32]);
+        match backend.clone().get_public_params() {
+            PublicParams::EdDSA { q, .. } => assert_eq!(&packet[15..], &q.as_bytes()[1..]),
+            _ => unreachable!(),
+        }
+        assert_eq!(backend.fingerprint().len(), 64);
+
+        let mut skipped = backend.clone();
+        let mut batch = [Digest::default(); 21];
+        assert_eq!(backend.fingerprints(&mut batch), 21);
+        for digest in batch.iter().take(20) {
+            assert_eq!(digest.len(), 32);
+            assert_eq!(digest, &backend.fingerprint_digest());
+            backend.shuffle().unwrap();
+        }
+        skipped.advance(20).unwrap();
+        assert_eq!(skipped.fingerprint_digest(), batch[20]);
+    }
+
+    #[test]
+    fn ed25519_v6_export() {
+        let mut backend = RPGPBackend::with_options(CipherSuite::Curve25519, v6_options()).unwrap();
+        backend.shuffle().unwrap();
+        let digest = backend.fingerprint_digest();
+        let uid = UserID::from("Tiansuo Li <114514@example.com>".to_string());
+        let results = backend.get_armored_results(&uid).unwrap();
+        let public = parse::packets(results.get_public_key());
+        let secret = parse::packets(results.get_private_key());
+        let tags =
+            |packets: &[(u8, Vec<u8>)]| packets.iter().map(|(tag, _)| *tag).collect::<Vec<u8>>();
+        assert_eq!(tags(&public), [6, 2, 13, 2, 14, 2]);
+        assert_eq!(tags(&secret), [5, 2, 13, 2, 7, 2]);
+        assert_eq!(public[2].1, b"Tiansuo Li <114514@example.com>");
+        assert_eq!(Sha256::digest(&parse::hashed_key(&public[0].1)), digest[..]);
+        assert_eq!(Sha256::digest(&parse::hashed_key(&secret[0].1)), digest[..]);
+
+        // The stored secrets belong to the native public keys
+        let mut derived = [0u8; 32];
+        let primary = &secret[0].1;
+        nettle::ed25519::public_key(&mut derived, &primary[43..75]).unwrap();
+        assert_eq!(&derived[..], &primary[10..42]);
+        let subkey = &secret[4].1;
+        assert_eq!(subkey[5], 25);
+        nettle::curve25519::mul_g(&mut derived, &subkey[43..75]).unwrap();
+        assert_eq!(&derived[..], &subkey[10..42]);
+
+        // Every signature verifies against the primary key
+        for index in [1, 3, 5] {
+            let signature = parse::signature(&public, index);
+            let hash = HashAlgorithm::SHA2_512.digest(&signature.data).unwrap();
+            assert_eq!(signature.left, hash[..2]);
+            assert!(nettle::ed25519::verify(&primary[10..42], &hash, &signature.material).unwrap());
+        }
+    }
+
+    #[test]
+    fn rsa2048_v6_export() {
+        let mut backend = RPGPBackend::with_options(CipherSuite::RSA2048, v6_options()).unwrap();
+        assert_eq!(backend.packet_cache[10], 1);
+        backend.shuffle().unwrap();
+        let digest = backend.fingerprint_digest();
+        let uid = UserID::from("Tiansuo Li <114514@example.com>".to_string());
+        let results = backend.get_armored_results(&uid).unwrap();
+        let public = parse::packets(results.get_public_key());
+        assert_eq!(Sha256::digest(&parse::hashed_key(&public[0].1)), digest[..]);
+        for index in [1, 3, 5] {
+            let signature = parse::signature(&public, index);
+            let hash = HashAlgorithm::SHA2_512.digest(&signature.data).unwrap();
+            assert_eq!(signature.hash_algorithm, 10);
+            assert_eq!(signature.left, hash[..2]);
+        }
+    }
+
+    #[test]
+    fn ed25519_v5_export() {
+        let options = KeyOptions {
+            version: KeyVersion::V5,
+            ..KeyOptions::default()
+        };
+        let mut backend = RPGPBackend::with_options(CipherSuite::Curve25519, options).unwrap();
+        assert_eq!(backend.packet_cache[..6], [0x9A, 0, 0, 0, 55, 5]);
+        assert_eq!(backend.packet_cache[10], 22);
+        backend.shuffle().unwrap();
+        let digest = backend.fingerprint_digest();
+        let uid = UserID::from("Tiansuo Li <114514@example.com>".to_string());
+        let results = backend.get_armored_results(&uid).unwrap();
+        let public = parse::packets(results.get_public_key());
+        let secret = parse::packets(results.get_private_key());
+        assert_eq!(Sha256::digest(&parse::hashed_key(&public[0].1)), digest[..]);
+        assert_eq!(public[2].1, b"Tiansuo Li <114514@example.com>");
+        assert_eq!(secret[4].1[5], 18);
+
+        // Legacy EdDSA: the point behind its 0x40 prefix, the seed as an MPI
+        let point = &public[0].1[23..55];
+        let mut seed = [0u8; 32];
+        let value = &parse::mpis(&parse::secret_fields(&secret[0].1))[0];
+        seed[32 - value.len()..].copy_from_slice(value);
+        let mut derived = [0u8; 32];
+        nettle::ed25519::public_key(&mut derived, &seed).unwrap();
+        assert_eq!(&derived[..], point);
+
+        for index in [1, 3, 5] {
+            let signature = parse::signature(&public, index);
+            let hash = HashAlgorithm::SHA2_512.digest(&signature.data).unwrap();
+            assert_eq!(signature.left, hash[..2]);
+            let mut material = [0u8; 64];
+            for (half, value) in parse::mpis(&signature.material).iter().enumerate() {
+                material[32 * half + 32 - value.len()..32 * (half + 1)].copy_from_slice(value);
+            }
+            assert!(nettle::ed25519::verify(point, &hash, &material).unwrap());
+        }
+    }
 }
@@ -4,21 +4,29 @@
 
 use byteorder::{BigEndian, ByteOrder};
 use nettle::hash::insecure_do_not_use::Sha1;
-use nettle::hash::Hash;
+use nettle::hash::{Hash, Sha512};
+use nettle::random::{Random, Yarrow};
 use sequoia_openpgp::armor::{Kind, Writer};
-use sequoia_openpgp::packet::key::{Key4, PrimaryRole, SecretParts};
+use sequoia_openpgp::crypto::mpi::{self, MPI};
+use sequoia_openpgp::crypto::{KeyPair, Signer as _};
+use sequoia_openpgp::packet::key::{Key4, PrimaryRole, SecretKeyMaterial, SecretParts};
 use sequoia_openpgp::packet::signature::SignatureBuilder;
 use sequoia_openpgp::packet::Key;
 use sequoia_openpgp::packet::UserID as SequoiaUserID;
 use sequoia_openpgp::serialize::{MarshalInto, SerializeInto};
 use sequoia_openpgp::types::{
-    Curve as SequoiaCurve, Features, HashAlgorithm, KeyFlags, SignatureType, SymmetricAlgorithm,
+    Curve as SequoiaCurve, Features, HashAlgorithm, KeyFlags, PublicKeyAlgorithm, SignatureType,
+    SymmetricAlgorithm,
 };
 use sequoia_openpgp::{Cert, Packet};
 
+use super::certificate::{self, Certificate, KeyMaterial};
+use super::ec::{EcCurve, PointWalk};
+use super::exponent::ExponentWalk;
+use super::window::TimestampWalk;
 use super::{
-    sha1_to_hex, Algorithms, ArmoredKey, Backend, CipherSuite, Curve, PGPError, Rsa,
-    UniversalError, UserID,
+    unix_timestamp, Algorithms, ArmoredKey, Backend, CipherSuite, Curve, Digest, FingerprintBatch,
+    KeyOptions, KeyVersion, PGPError, Rsa, Sha256, UniversalError, UserID, Variation,
 };
 
 use std::io::Write;
@@ -26,12 +34,22 @@ use std::time::Duration;
 use std::time::UNIX_EPOCH;
 
 /// The `Sequoia-OpenPGP` backend wrapper
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SequoiaBackend {
     primary_key: Key4<SecretParts, PrimaryRole>,
     cipher_suite: CipherSuite,
-    timestamp: u32,
+    creation: TimestampWalk,
     packet_cache: Vec<u8>,
+    batch: FingerprintBatch,
+    version: KeyVersion,
+    exponent: Option<ExponentWalk>,
+    point: Option<PointWalk>,
+}
+
+/// Signs v5 and v6 certificates with the primary key
+struct CertificateSigner {
+    keypair: KeyPair,
+    rng: Yarrow,
 }
 
 /// Generate key with the required `CipherSuite`
@@ -60,24 +78,261 @@ fn generate_key(
     }
 }
 
+/// Get the primes `p`, `q` and the coefficient `u` of an RSA key
+fn rsa_primes(
+    key: &Key4<SecretParts, PrimaryRole>,
+) -> Result<(Vec<u8>, Vec<u8>, Vec<u8>), PGPError> {
+    match key.secret() {
+        SecretKeyMaterial::Unencrypted(secret) => secret.map(|material| match material {
+            mpi::SecretKeyMaterial::RSA { p, q, u, .. } => {
+                Ok((p.value().to_vec(), q.value().to_vec(), u.value().to_vec()))
+            }
+            _ => Err(PGPError::VariationNotSupported(
+                Variation::RsaExponent.to_string(),
+            )),
+        }),
+        SecretKeyMaterial::Encrypted(_) => Err(PGPError::MysteriousError),
+    }
+}
+
+/// Rebuild an RSA key with the current exponent of `walk` and the matching private exponent
+fn with_exponent(
+    key: &Key4<SecretParts, PrimaryRole>,
+    walk: &ExponentWalk,
+) -> Result<Key4<SecretParts, PrimaryRole>, PGPError> {
+    let n = match key.mpis() {
+        mpi::PublicKey::RSA { n, .. } => n.clone(),
+        _ => {
+            return Err(PGPError::VariationNotSupported(
+                Variation::RsaExponent.to_string(),
+            ))
+        }
+    };
+    let (p, q, u) = rsa_primes(key)?;
+    let secret = mpi::SecretKeyMaterial::RSA {
+        d: walk.private_exponent()?.into(),
+        p: p.into(),
+        q: q.into(),
+        u: u.into(),
+    };
+    Key4::with_secret(
+        key.creation_time(),
+        PublicKeyAlgorithm::RSAEncryptSign,
+        mpi::PublicKey::RSA {
+            e: MPI::new(&walk.exponent().to_be_bytes()),
+            n,
+        },
+        secret.into(),
+    )
+    .map_err(|_| PGPError::FailedToModifyPublicExponent)
+}
+
+/// Start stepping the public point of a key, `None` for keys whose point can't be stepped
+fn point_walk(key: &Key4<SecretParts, PrimaryRole>) -> Result<Option<PointWalk>, PGPError> {
+    let (curve, q) = match key.mpis() {
+        mpi::PublicKey::ECDSA { curve, q } => (curve, q),
+        _ => return Ok(None),
+    };
+    let ec_curve = match curve {
+        SequoiaCurve::NistP256 => EcCurve::NistP256,
+        SequoiaCurve::NistP384 => EcCurve::NistP384,
+        SequoiaCurve::NistP521 => EcCurve::NistP521,
+        _ => return Ok(None),
+    };
+    let (x, y) = q
+        .decode_point(curve)
+        .map_err(|_| PGPError::FailedToModifyPublicPoint)?;
+    PointWalk::new(ec_curve, x, y).map(Some)
+}
+
+/// Rebuild an ECDSA key with the current point of `walk` and the matching secret scalar
+fn with_point(
+    key: &Key4<SecretParts, PrimaryRole>,
+    walk: &PointWalk,
+) -> Result<Key4<SecretParts, PrimaryRole>, PGPError> {
+    let curve = match key.mpis() {
+        mpi::PublicKey::ECDSA { curve, .. } => curve.clone(),
+        _ => return Err(PGPError::FailedToModifyPublicPoint),
+    };
+    let scalar = match key.secret() {
+        SecretKeyMaterial::Unencrypted(secret) => secret.map(|material| match material {
+            mpi::SecretKeyMaterial::ECDSA { scalar } => Ok(scalar.value().to_vec()),
+            _ => Err(PGPError::FailedToModifyPublicPoint),
+        }),
+        SecretKeyMaterial::Encrypted(_) => Err(PGPError::MysteriousError),
+    }?;
+    let secret = mpi::SecretKeyMaterial::ECDSA {
+        scalar: walk.secret(&scalar).into(),
+    };
+    Key4::with_secret(
+        key.creation_time(),
+        PublicKeyAlgorithm::ECDSA,
+        mpi::PublicKey::ECDSA {
+            curve,
+            q: MPI::new_point(&walk.x(), &walk.y(), walk.curve().bits()),
+        },
+        secret.into(),
+    )
+    .map_err(|_| PGPError::FailedToModifyPublicPoint)
+}
+
+/// Get the creation time of a key as a `u32` timestamp
+fn creation_timestamp(key: &Key4<SecretParts, PrimaryRole>) -> Result<u32, PGPError> {
+    unix_timestamp(key.creation_time())
+}
+
+/// Get the key material of a key as stored in packets of `version`
+///
+/// Curve25519 keys change to the native Ed25519 and X25519 algorithms in v6 keys, everything else
+/// keeps its MPIs.
+fn key_material(
+    version: KeyVersion,
+    key: &Key4<SecretParts, PrimaryRole>,
+) -> Result<KeyMaterial, PGPError> {
+    let secret = match key.secret() {
+        SecretKeyMaterial::Unencrypted(secret) => secret.map(|material| material.clone()),
+        SecretKeyMaterial::Encrypted(_) => return Err(PGPError::MysteriousError),
+    };
+    match (key.mpis(), &secret) {
+        (mpi::PublicKey::EdDSA { q, .. }, mpi::SecretKeyMaterial::EdDSA { scalar })
+            if version == KeyVersion::V6 =>
+        {
+            Ok(KeyMaterial::ed25519(&q.value()[1..], scalar.value()))
+        }
+        (
+            mpi::PublicKey::ECDH {
+                curve: SequoiaCurve::Cv25519,
+                q,
+                ..
+            },
+            mpi::SecretKeyMaterial::ECDH { scalar },
+        ) if version == KeyVersion::V6 => Ok(KeyMaterial::x25519(&q.value()[1..], scalar.value())),
+        (public, secret) => Ok(KeyMaterial::new(
+            key.pk_algo().into(),
+            MarshalInto::to_vec(public).map_err(|_| PGPError::InvalidKeyGenerated)?,
+            MarshalInto::to_vec(secret).map_err(|_| PGPError::InvalidKeyGenerated)?,
+        )),
+    }
+}
+
+impl certificate::Signer for CertificateSigner {
+    fn hash_algorithm(&self) -> u8 {
+        certificate::HASH_SHA512
+    }
+
+    fn hash(&self, data: &[u8]) -> Result<Vec<u8>, UniversalError> {
+        let mut hasher = Sha512::default();
+        hasher.update(data);
+        let mut digest = vec![0; hasher.digest_size()];
+        hasher.digest(&mut digest);
+        Ok(digest)
+    }
+
+    fn sign(&mut self, digest: &[u8]) -> Result<Vec<Vec<u8>>, UniversalError> {
+        match self.keypair.sign(HashAlgorithm::SHA512, digest)? {
+            mpi::Signature::RSA { s } => Ok(vec![s.value().to_vec()]),
+            mpi::Signature::EdDSA { r, s } | mpi::Signature::ECDSA { r, s } => {
+                Ok(vec![r.value().to_vec(), s.value().to_vec()])
+            }
+            _ => Err(PGPError::InvalidKeyGenerated.into()),
+        }
+    }
+
+    fn random(&mut self, buffer: &mut [u8]) {
+        self.rng.random(buffer);
+    }
+}
+
 impl Backend for SequoiaBackend {
-    fn fingerprint(&self) -> String {
-        let mut hasher = Sha1::default();
-        hasher.update(&self.packet_cache);
-        let mut digest_buffer: [u8; 20] = [0; 20];
-        hasher.digest(&mut digest_buffer);
-        // hex_string(&digest_buffer).unwrap()
-        sha1_to_hex(&digest_buffer)
+    fn fingerprint_digest(&self) -> Digest {
+        match self.version {
+            KeyVersion::V4 => {
+                let mut hasher = Sha1::default();
+                hasher.update(&self.packet_cache);
+                let mut digest_buffer: [u8; 20] = [0; 20];
+                hasher.digest(&mut digest_buffer);
+                Digest::from(digest_buffer)
+            }
+            KeyVersion::V5 | KeyVersion::V6 => Digest::from(Sha256::digest(&self.packet_cache)),
+        }
+    }
+
+    fn fingerprints(&mut self, out: &mut [Digest]) -> usize {
+        match &mut self.exponent {
+            Some(walk) => walk.fingerprints(&self.batch, out),
+            None => self.creation.fingerprints(&self.batch, out),
+        }
     }
 
     fn shuffle(&mut self) -> Result<(), PGPError> {
-        self.timestamp -= 1;
-        BigEndian::write_u32(&mut self.packet_cache[4..8], self.timestamp);
+        self.advance(1)
+    }
+
+    fn advance(&mut self, count: usize) -> Result<(), PGPError> {
+        if let Some(walk) = &mut self.exponent {
+            walk.advance(count)?;
+            let length = self.packet_cache.len();
+            BigEndian::write_u32(&mut self.packet_cache[length - 4..], walk.exponent());
+            return Ok(());
+        }
+        self.creation.advance(count)?;
+        self.write_timestamp();
         Ok(())
     }
 
+    fn renew(&mut self) -> Result<bool, PGPError> {
+        let walk = match &mut self.point {
+            Some(walk) => walk,
+            None => return Ok(false),
+        };
+        walk.step()?;
+
+        // The point is the last MPI, `0x04 || x || y`
+        let size = walk.curve().size();
+        let length = self.packet_cache.len();
+        self.packet_cache[length - 2 * size..length - size].copy_from_slice(&walk.x());
+        self.packet_cache[length - size..].copy_from_slice(&walk.y());
+        self.creation.restart();
+        self.write_timestamp();
+        self.batch = FingerprintBatch::new(
+            self.version,
+            &self.packet_cache,
+            self.version.timestamp_offset(),
+        );
+        Ok(true)
+    }
+
+    fn slice(&mut self, index: usize, count: usize) -> Result<bool, PGPError> {
+        if self.exponent.is_some() {
+            return Ok(false);
+        }
+        self.creation.slice(index, count)?;
+        self.write_timestamp();
+        Ok(true)
+    }
+
     fn get_armored_results(mut self, uid: &UserID) -> Result<ArmoredKey, UniversalError> {
-        let creation_time = UNIX_EPOCH + Duration::from_secs(self.timestamp as u64);
+        let creation_time = UNIX_EPOCH + Duration::from_secs(u64::from(self.creation.timestamp()));
+        if let Some(walk) = &self.exponent {
+            self.primary_key = with_exponent(&self.primary_key, walk)?;
+        }
+        if let Some(walk) = self.point.as_ref().filter(|walk| walk.steps() > 0) {
+            self.primary_key = with_point(&self.primary_key, walk)?;
+        }
+        if self.version != KeyVersion::V4 {
+            let subkey = generate_key(self.cipher_suite.get_encryption_key_algorithm(), false)?;
+            let certificate = Certificate::new(
+                self.version,
+                self.creation.timestamp(),
+                key_material(self.version, &self.primary_key)?,
+                key_material(self.version, &subkey)?,
+            );
+            let mut signer = CertificateSigner {
+                keypair: self.primary_key.into_keypair()?,
+                rng: Yarrow::default(),
+            };
+            return certificate.to_armored(&mut signer, uid);
+        }
         self.primary_key.set_creation_time(creation_time)?;
         let mut packets = Vec::<Packet>::new();
         let mut signer = self.primary_key.clone().into_keypair()?;
@@ -151,51 +406,115 @@ impl Backend for SequoiaBackend {
 impl SequoiaBackend {
     /// Create new instance
     pub fn new<C: Into<CipherSuite>>(cipher_suite: C) -> Result<Self, PGPError> {
+        Self::with_options(cipher_suite, KeyOptions::default())
+    }
+
+    /// Create new instance which shuffles by changing `variation`
+    pub fn with_variation<C: Into<CipherSuite>>(
+        cipher_suite: C,
+        variation: Variation,
+    ) -> Result<Self, PGPError> {
+        Self::with_options(
+            cipher_suite,
+            KeyOptions {
+                variation,
+                ..KeyOptions::default()
+            },
+        )
+    }
+
+    /// Create new instance generated and shuffled according to `options`
+    pub fn with_options<C: Into<CipherSuite>>(
+        cipher_suite: C,
+        options: KeyOptions,
+    ) -> Result<Self, PGPError> {
         let ciphers = cipher_suite.into();
-        let primary_key = generate_key(ciphers.get_signing_key_algorithm(), true)?;
+        let mut primary_key = generate_key(ciphers.get_signing_key_algorithm(), true)?;
+        let window = options.window.until(creation_timestamp(&primary_key)?)?;
+        let exponent = match options.variation {
+            Variation::CreationTime => None,
+            Variation::RsaExponent => {
+                let (p, q, _) = rsa_primes(&primary_key)?;
+                let walk = ExponentWalk::new(&p, &q)?;
+                primary_key = with_exponent(&primary_key, &walk)?;
+                Some(walk)
+            }
+        };
 
         // Build packet cache
-        let mut packet_cache: Vec<u8> = vec![0x99, 0, 0, 4, 0, 0, 0, 0];
-        let packet_length = 6 + primary_key.mpis().serialized_len() as u16;
-        BigEndian::write_u16(&mut packet_cache[1..3], packet_length); // Packet length
-        let timestamp = primary_key
-            .creation_time()
-            .duration_since(UNIX_EPOCH)
-            .expect("Failed to get timestamp")
-            .as_secs() as u32;
-        BigEndian::write_u32(&mut packet_cache[4..8], timestamp); // Timestamp
-        packet_cache.push(primary_key.pk_algo().into()); // Algorithm identifier
-        let mut public_key_buffer =
-            MarshalInto::to_vec(primary_key.mpis()).expect("Failed to serialize public key");
-        packet_cache.append(&mut public_key_buffer); // Public key
+        let version = options.version;
+        let (algorithm, public_key_buffer) = match version {
+            KeyVersion::V4 => (
+                primary_key.pk_algo().into(),
+                MarshalInto::to_vec(primary_key.mpis()).expect("Failed to serialize public key"),
+            ),
+            _ => {
+                let material = key_material(version, &primary_key)?;
+                (material.algorithm(), material.public().to_vec())
+            }
+        };
+        let creation = TimestampWalk::new(window);
+        let packet_cache = version.key_packet(creation.timestamp(), algorithm, &public_key_buffer);
 
+        // The exponent is the last MPI, and exactly 4 bytes long
+        let batch = match exponent {
+            Some(_) => FingerprintBatch::new(version, &packet_cache, packet_cache.len() - 4),
+            None => FingerprintBatch::new(version, &packet_cache, version.timestamp_offset()),
+        };
+        let point = match exponent {
+            Some(_) => None,
+            None => point_walk(&primary_key)?,
+        };
         Ok(Self {
             primary_key,
             cipher_suite: ciphers,
-            timestamp,
+            creation,
             packet_cache,
+            batch,
+            version,
+            exponent,
+            point,
         })
     }
 
+    /// Write the current creation time into the packet cache
+    fn write_timestamp(&mut self) {
+        let offset = self.version.timestamp_offset();
+        BigEndian::write_u32(
+            &mut self.packet_cache[offset..offset + 4],
+            self.creation.timestamp(),
+        );
+    }
+
     /// Get primary key
     #[allow(dead_code)]
     pub(crate) fn get_primary_key(self) -> Key4<SecretParts, PrimaryRole> {
         self.primary_key
     }
 
-    /// Get `u32` timestamp
-    // Boo! You've just found something that will go wrong after the year 2038
+    /// Get the current creation time, an unsigned timestamp valid until 2106
     #[allow(dead_code)]
     pub(crate) fn get_timestamp(&self) -> u32 {
-        self.timestamp
+        self.creation.timestamp()
     }
 }
 
 #[cfg(test)]
 mod sequoia_backend_test {
-    use super::{Backend, Cert, CipherSuite, Key, SequoiaBackend, UserID};
+    use super::{
+        with_exponent, with_point, Backend, Cert, CipherSuite, Key, SequoiaBackend, UserID,
+        Variation,
+    };
+    use crate::pgp_backends::certificate::parse;
+    use crate::pgp_backends::{
+        BatchImplementation, CreationWindow, Digest, KeyOptions, KeyVersion, PGPError, Sha1Batch,
+        Sha256, Walk,
+    };
     use anyhow::Error;
+    use nettle::hash::{Hash, Sha512};
     use sequoia_openpgp::armor::{Reader, ReaderMode};
+    use sequoia_openpgp::crypto::mpi;
+    use sequoia_openpgp::packet::key::SecretKeyMaterial;
     use sequoia_openpgp::packet::Signature;
     use sequoia_openpgp::parse::Parse;
     use sequoia_openpgp::types::PublicKeyAlgorithm;
@@ -217,6 +536,14 @@ mod sequoia_backend_test {
         assert_eq!(fingerprint_custom, fingerprint_sequoia);
     }
 
+    #[test]
+    fn ed25519_fingerprint_digest() {
+        let backend = SequoiaBackend::new(CipherSuite::Curve25519).unwrap();
+        let digest = backend.fingerprint_digest();
+        let fingerprint_sequoia = backend.get_primary_key().fingerprint();
+        assert_eq!(&digest[..], fingerprint_sequoia.as_bytes());
+    }
+
     #[test]
     fn ed25519_shuffle() {
         let mut backend = SequoiaBackend::new(CipherSuite::Curve25519).unwrap();
@@ -241,6 +568,109 @@ mod sequoia_backend_test {
         assert_eq!(fingerprint_custom_after, fingerprint_sequoia_after);
     }
 
+    #[test]
+    fn ed25519_batch_known_answers() {
+        let backend = SequoiaBackend::new(CipherSuite::Curve25519).unwrap();
+        let timestamp = backend.get_timestamp();
+        let values = [timestamp, timestamp - 1, timestamp - 86400];
+        let mut primary_key = backend.clone().get_primary_key();
+        for implementation in BatchImplementation::available() {
+            let mut digests = [[0u8; 20]; 3];
+            Sha1Batch::new(&backend.packet_cache, 4)
+                .with_implementation(implementation)
+                .digests(&values, &mut digests);
+            for (value, digest) in values.iter().zip(digests.iter()) {
+                primary_key
+                    .set_creation_time(UNIX_EPOCH + Duration::from_secs(*value as u64))
+                    .unwrap();
+                let fingerprint_sequoia = primary_key.fingerprint();
+                assert_eq!(
+                    &digest[..],
+                    fingerprint_sequoia.as_bytes(),
+                    "{}",
+                    implementation.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn ed25519_fingerprints() {
+        let mut backend = SequoiaBackend::new(CipherSuite::Curve25519).unwrap();
+        let mut skipped = backend.clone();
+        let mut batch = [Digest::default(); 21];
+        assert_eq!(backend.fingerprints(&mut batch), 21);
+        for digest in batch.iter().take(20) {
+            assert_eq!(digest, &backend.fingerprint_digest());
+            backend.shuffle().unwrap();
+        }
+        skipped.advance(20).unwrap();
+        assert_eq!(skipped.get_timestamp(), backend.get_timestamp());
+        assert_eq!(skipped.fingerprint_digest(), batch[20]);
+    }
+
+    #[test]
+    fn ed25519_window() {
+        // 2020-01-01T00:00:00Z to 2020-01-01T00:00:40Z
+        let window = CreationWindow::new(1577836800, 1577836840, Walk::Forward).unwrap();
+        let options = KeyOptions {
+            window,
+            ..KeyOptions::default()
+        };
+        let mut backend = SequoiaBackend::with_options(CipherSuite::Curve25519, options).unwrap();
+        assert_eq!(backend.get_timestamp(), 1577836800);
+        let mut batch = [Digest::default(); 64];
+        assert_eq!(backend.fingerprints(&mut batch), 41);
+        backend.advance(40).unwrap();
+        assert_eq!(backend.get_timestamp(), 1577836840);
+        assert_eq!(backend.fingerprint_digest(), batch[40]);
+        assert!(matches!(
+            backend.shuffle(),
+            Err(PGPError::CreationWindowExhausted)
+        ));
+        assert_eq!(backend.get_timestamp(), 1577836840);
+
+        // A random walk patches the packet with every creation time of the window once
+        let window = CreationWindow::new(1577836800, 1577836899, Walk::Random).unwrap();
+        let options = KeyOptions {
+            window,
+            ..KeyOptions::default()
+        };
+        let mut backend = SequoiaBackend::with_options(CipherSuite::Curve25519, options).unwrap();
+        let mut batch = [Digest::default(); 128];
+        assert_eq!(backend.fingerprints(&mut batch), 100);
+        let mut timestamps = Vec::new();
+        for digest in batch.iter().take(100) {
+            assert_eq!(digest, &backend.fingerprint_digest());
+            timestamps.push(backend.get_timestamp());
+            let _ = backend.shuffle();
+        }
+        timestamps.sort_unstable();
+        assert_eq!(timestamps, (1577836800..=1577836899).collect::<Vec<u32>>());
+
+        // Nothing is dated in the future
+        let future = CreationWindow::new(0, u32::MAX, Walk::Forward).unwrap();
+        let mut backend = SequoiaBackend::with_options(
+            CipherSuite::Curve25519,
+            KeyOptions {
+                window: future,
+                ..KeyOptions::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(backend.get_timestamp(), 0);
+        assert!(backend.advance(u32::MAX as usize).is_err());
+        let backward = CreationWindow::new(4102444800, u32::MAX, Walk::Backward).unwrap();
+        assert!(SequoiaBackend::with_options(
+            CipherSuite::Curve25519,
+            KeyOptions {
+                window: backward,
+                ..KeyOptions::default()
+            },
+        )
+        .is_err());
+    }
+
     #[test]
     fn ed25519_export() {
         let mut backend = SequoiaBackend::new(CipherSuite::Curve25519).unwrap();
@@ -315,6 +745,47 @@ mod sequoia_backend_test {
         assert_eq!(fingerprint_custom_after, fingerprint_sequoia_after);
     }
 
+    #[test]
+    fn rsa4096_batch_known_answers() {
+        let backend = SequoiaBackend::new(CipherSuite::RSA4096).unwrap();
+        let timestamp = backend.get_timestamp();
+        let values = [timestamp, timestamp - 1, timestamp - 86400];
+        let mut primary_key = backend.clone().get_primary_key();
+        for implementation in BatchImplementation::available() {
+            let mut digests = [[0u8; 20]; 3];
+            Sha1Batch::new(&backend.packet_cache, 4)
+                .with_implementation(implementation)
+                .digests(&values, &mut digests);
+            for (value, digest) in values.iter().zip(digests.iter()) {
+                primary_key
+                    .set_creation_time(UNIX_EPOCH + Duration::from_secs(*value as u64))
+                    .unwrap();
+                let fingerprint_sequoia = primary_key.fingerprint();
+                assert_eq!(
+                    &digest[..],
+                    fingerprint_sequoia.as_bytes(),
+                    "{}",
+                    implementation.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rsa4096_fingerprints() {
+        let mut backend = SequoiaBackend::new(CipherSuite::RSA4096).unwrap();
+        let mut skipped = backend.clone();
+        let mut batch = [Digest::default(); 21];
+        assert_eq!(backend.fingerprints(&mut batch), 21);
+        for digest in batch.iter().take(20) {
+            assert_eq!(digest, &backend.fingerprint_digest());
+            backend.shuffle().unwrap();
+        }
+        skipped.advance(20).unwrap();
+        assert_eq!(skipped.get_timestamp(), backend.get_timestamp());
+        assert_eq!(skipped.fingerprint_digest(), batch[20]);
+    }
+
     #[test]
     fn rsa4096_export() -> Result<(), Error> {
         let mut backend = SequoiaBackend::new(CipherSuite::RSA4096).unwrap();
@@ -350,4 +821,301 @@ mod sequoia_backend_test {
         assert_eq!(pk.pk_algo(), PublicKeyAlgorithm::RSAEncryptSign);
         Ok(())
     }
+
+    #[test]
+    fn ed25519_exponent_not_supported() {
+        assert!(
+            SequoiaBackend::with_variation(CipherSuite::Curve25519, Variation::RsaExponent)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rsa2048_exponent_fingerprints() {
+        let mut backend =
+            SequoiaBackend::with_variation(CipherSuite::RSA2048, Variation::RsaExponent).unwrap();
+        let primary_key = backend.clone().get_primary_key();
+        let timestamp = backend.get_timestamp();
+        let mut batch = [Digest::default(); 9];
+        assert_eq!(backend.fingerprints(&mut batch), 9);
+        for digest in batch.iter() {
+            let walk = backend.exponent.as_ref().unwrap();
+            let rebuilt = with_exponent(&primary_key, walk).unwrap();
+            assert_eq!(&digest[..], rebuilt.fingerprint().as_bytes());
+            assert_eq!(digest, &backend.fingerprint_digest());
+            backend.shuffle().unwrap();
+        }
+        assert_eq!(backend.get_timestamp(), timestamp);
+    }
+
+    #[test]
+    fn rsa2048_exponent_export() {
+        let mut backend =
+            SequoiaBackend::with_variation(CipherSuite::RSA2048, Variation::RsaExponent).unwrap();
+        backend.advance(3).unwrap();
+        let exponent = backend.exponent.as_ref().unwrap().exponent();
+        let fingerprint_before = backend.fingerprint();
+        let uid = UserID::from("Tiansuo Li <114514@example.com>".to_string());
+        let results = backend.get_armored_results(&uid).unwrap();
+
+        let mut cursor = Cursor::new(results.get_private_key());
+        let mut reader = Reader::new(&mut cursor, ReaderMode::VeryTolerant);
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).unwrap();
+        let cert = Cert::from_bytes(&content).unwrap();
+        assert_eq!(fingerprint_before, cert.fingerprint().to_hex());
+        assert!(cert.is_tsk());
+        assert!(cert
+            .bad_signatures()
+            .collect::<Vec<&Signature>>()
+            .is_empty());
+        match cert.primary_key().key().mpis() {
+            mpi::PublicKey::RSA { e, .. } => assert_eq!(e.value(), &exponent.to_be_bytes()[..]),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn nistp256_renew() {
+        let mut backend = SequoiaBackend::new(CipherSuite::NistP256).unwrap();
+        let timestamp = backend.get_timestamp();
+        let primary_key = backend.clone().get_primary_key();
+        backend.advance(100).unwrap();
+        assert!(backend.renew().unwrap());
+        assert!(backend.renew().unwrap());
+        assert_eq!(backend.get_timestamp(), timestamp);
+        let walk = backend.point.as_ref().unwrap();
+        assert_eq!(walk.steps(), 2);
+        let stepped = with_point(&primary_key, walk).unwrap();
+        assert_eq!(backend.fingerprint(), stepped.fingerprint().to_hex());
+
+        // Shuffling and batches go on from the new point
+        let mut batch = [Digest::default(); 5];
+        assert_eq!(backend.fingerprints(&mut batch), 5);
+        backend.advance(4).unwrap();
+        assert_eq!(backend.fingerprint_digest(), batch[4]);
+    }
+
+    #[test]
+    fn ed25519_renew_not_supported() {
+        let mut backend = SequoiaBackend::new(CipherSuite::Curve25519).unwrap();
+        assert!(!backend.renew().unwrap());
+    }
+
+    #[test]
+    fn ed25519_secret_is_a_seed() {
+        // The public key is derived from a hash of the stored secret, so a stepped scalar has
+        // nothing to be stored as
+        let key = SequoiaBackend::new(CipherSuite::Curve25519)
+            .unwrap()
+            .get_primary_key();
+        let value = match key.secret() {
+            SecretKeyMaterial::Unencrypted(secret) => secret.map(|material| match material {
+                mpi::SecretKeyMaterial::EdDSA { scalar } => scalar.value().to_vec(),
+                _ => unreachable!(),
+            }),
+            _ => unreachable!(),
+        };
+        let mut seed = [0u8; 32];
+        seed[32 - value.len()..].copy_from_slice(&value);
+        let mut public = [0u8; 32];
+        nettle::ed25519::public_key(&mut public, &seed).unwrap();
+        match key.mpis() {
+            mpi::PublicKey::EdDSA { q, .. } => assert_eq!(&q.value()[1..], &public[..]),
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn nist_renew_export() {
+        for cipher_suite in [
+            CipherSuite::NistP256,
+            CipherSuite::NistP384,
+            CipherSuite::NistP521,
+        ] {
+            let mut backend = SequoiaBackend::new(cipher_suite).unwrap();
+            for _ in 0..3 {
+                assert!(backend.renew().unwrap());
+            }
+            backend.shuffle().unwrap();
+            let fingerprint_before = backend.fingerprint();
+            let uid = UserID::from("Tiansuo Li <114514@example.com>".to_string());
+            let results = backend.get_armored_results(&uid).unwrap();
+
+            // Signatures made with the recomputed scalar verify against the stepped point
+            let mut cursor = Cursor::new(results.get_private_key());
+            let mut reader = Reader::new(&mut cursor, ReaderMode::VeryTolerant);
+            let mut content = Vec::new();
+            reader.read_to_end(&mut content).unwrap();
+            let cert = Cert::from_bytes(&content).unwrap();
+            assert_eq!(fingerprint_before, cert.fingerprint().to_hex());
+            assert!(cert.is_tsk());
+            assert!(cert
+                .bad_signatures()
+                .collect::<Vec<&Signature>>()
+                .is_empty());
+            assert_eq!(cert.userids().count(), 1);
+            assert_eq!(
+                cert.primary_key().key().pk_algo(),
+                PublicKeyAlgorithm::ECDSA
+            );
+        }
+    }
+
+    /// Options for v6 keys
+    fn v6_options() -> KeyOptions {
+        KeyOptions {
+            version: KeyVersion::V6,
+            ..KeyOptions::default()
+        }
+    }
+
+    /// SHA-512 of signed data
+    fn sha512(data: &[u8]) -> [u8; 64] {
+        let mut hasher = Sha512::default();
+        hasher.update(data);
+        let mut digest = [0u8; 64];
+        hasher.digest(&mut digest);
+        digest
+    }
+
+    #[test]
+    fn ed25519_v6_fingerprints() {
+        let mut backend =
+            SequoiaBackend::with_options(CipherSuite::Curve25519, v6_options()).unwrap();
+        let key = backend.clone().get_primary_key();
+        let packet = &backend.packet_cache;
+        assert_eq!(&packet[..6], &[0x9B, 0, 0, 0, 42, 6]);
+        assert_eq!(&packet[10..15], &[27, 0, 0, 0, 32]);
+        match key.mpis() {
+            mpi::PublicKey::EdDSA { q, .. } => assert_eq!(&packet[15..], &q.value()[1..]),
+            _ => unreachable!(),
+        }
+        assert_eq!(backend.fingerprint().len(), 64);
+
+        let mut skipped = backend.clone();
+        let mut batch = [Digest::default(); 21];
+        assert_eq!(backend.fingerprints(&mut batch), 21);
+        for digest in batch.iter().take(20) {
+            assert_eq!(digest.len(), 32);
+            assert_eq!(digest, &backend.fingerprint_digest());
+            backend.shuffle().unwrap();
+        }
+        skipped.advance(20).unwrap();
+        assert_eq!(skipped.fingerprint_digest(), batch[20]);
+    }
+
+    #[test]
+    fn ed25519_v6_export() {
+        let mut backend =
+            SequoiaBackend::with_options(CipherSuite::Curve25519, v6_options()).unwrap();
+        backend.shuffle().unwrap();
+        let digest = backend.fingerprint_digest();
+        let uid = UserID::from("Tiansuo Li <114514@example.com>".to_string());
+        let results = backend.get_armored_results(&uid).unwrap();
+        let public = parse::packets(results.get_public_key());
+        let secret = parse::packets(results.get_private_key());
+        let tags =
+            |packets: &[(u8, Vec<u8>)]| packets.iter().map(|(tag, _)| *tag).collect::<Vec<u8>>();
+        assert_eq!(tags(&public), [6, 2, 13, 2, 14, 2]);
+        assert_eq!(tags(&secret), [5, 2, 13, 2, 7, 2]);
+        assert_eq!(public[2].1, b"Tiansuo Li <114514@example.com>");
+        assert_eq!(Sha256::digest(&parse::hashed_key(&public[0].1)), digest[..]);
+        assert_eq!(Sha256::digest(&parse::hashed_key(&secret[0].1)), digest[..]);
+
+        // The stored secrets belong to the native public keys
+        let mut derived = [0u8; 32];
+        let primary = &secret[0].1;
+        nettle::ed25519::public_key(&mut derived, &primary[43..75]).unwrap();
+        assert_eq!(&derived[..], &primary[10..42]);
+        let subkey = &secret[4].1;
+        assert_eq!(subkey[5], 25);
+        nettle::curve25519::mul_g(&mut derived, &subkey[43..75]).unwrap();
+        assert_eq!(&derived[..], &subkey[10..42]);
+
+        // Every signature verifies against the primary key
+        for index in [1, 3, 5] {
+            let signature = parse::signature(&public, index);
+            let hash = sha512(&signature.data);
+            assert_eq!(signature.left, hash[..2]);
+            assert!(nettle::ed25519::verify(&primary[10..42], &hash, &signature.material).unwrap());
+        }
+    }
+
+    #[test]
+    fn nist_v6_renew_export() {
+        let mut backend =
+            SequoiaBackend::with_options(CipherSuite::NistP256, v6_options()).unwrap();
+        assert_eq!(backend.packet_cache[10], 19);
+        assert!(backend.renew().unwrap());
+        backend.shuffle().unwrap();
+        let digest = backend.fingerprint_digest();
+        let uid = UserID::from("Tiansuo Li <114514@example.com>".to_string());
+        let results = backend.get_armored_results(&uid).unwrap();
+        let public = parse::packets(results.get_public_key());
+        assert_eq!(Sha256::digest(&parse::hashed_key(&public[0].1)), digest[..]);
+        for index in [1, 3, 5] {
+            let signature = parse::signature(&public, index);
+            assert_eq!(signature.hash_algorithm, 10);
+            assert_eq!(signature.left, sha512(&signature.data)[..2]);
+        }
+    }
+
+    #[test]
+    fn rsa2048_v6_exponent_fingerprints() {
+        let options = KeyOptions {
+            variation: Variation::RsaExponent,
+            ..v6_options()
+        };
+        let mut backend = SequoiaBackend::with_options(CipherSuite::RSA2048, options).unwrap();
+        let mut skipped = backend.clone();
+        let mut batch = [Digest::default(); 9];
+        assert_eq!(backend.fingerprints(&mut batch), 9);
+        for digest in batch.iter().take(8) {
+            assert_eq!(digest, &backend.fingerprint_digest());
+            backend.shuffle().unwrap();
+        }
+        skipped.advance(8).unwrap();
+        assert_eq!(skipped.fingerprint_digest(), batch[8]);
+    }
+
+    #[test]
+    fn ed25519_v5_export() {
+        let options = KeyOptions {
+            version: KeyVersion::V5,
+            ..KeyOptions::default()
+        };
+        let mut backend = SequoiaBackend::with_options(CipherSuite::Curve25519, options).unwrap();
+        assert_eq!(backend.packet_cache[..6], [0x9A, 0, 0, 0, 55, 5]);
+        assert_eq!(backend.packet_cache[10], 22);
+        backend.shuffle().unwrap();
+        let digest = backend.fingerprint_digest();
+        let uid = UserID::from("Tiansuo Li <114514@example.com>".to_string());
+        let results = backend.get_armored_results(&uid).unwrap();
+        let public = parse::packets(results.get_public_key());
+        let secret = parse::packets(results.get_private_key());
+        assert_eq!(Sha256::digest(&parse::hashed_key(&public[0].1)), digest[..]);
+        assert_eq!(public[2].1, b"Tiansuo Li <114514@example.com>");
+        assert_eq!(secret[4].1[5], 18);
+
+        // Legacy EdDSA: the point behind its 0x40 prefix, the seed as an MPI
+        let point = &public[0].1[23..55];
+        let mut seed = [0u8; 32];
+        let value = &parse::mpis(&parse::secret_fields(&secret[0].1))[0];
+        seed[32 - value.len()..].copy_from_slice(value);
+        let mut derived = [0u8; 32];
+        nettle::ed25519::public_key(&mut derived, &seed).unwrap();
+        assert_eq!(&derived[..], point);
+
+        for index in [1, 3, 5] {
+            let signature = parse::signature(&public, index);
+            let hash = sha512(&signature.data);
+            assert_eq!(signature.left, hash[..2]);
+            let mut material = [0u8; 64];
+            for (half, value) in parse::mpis(&signature.material).iter().enumerate() {
+                material[32 * half + 32 - value.len()..32 * (half + 1)].copy_from_slice(value);
+            }
+            assert!(nettle::ed25519::verify(point, &hash, &material).unwrap());
+        }
+    }
 }
@@ -0,0 +1,964 @@
+//! Multi-buffer SHA-1
+//!
+//! Hashes the same message many times at once, each copy with a different big-endian `u32`
+//! written at a fixed offset. This is exactly what shuffling the creation time (or the RSA
+//! exponent) of a key does to the fingerprinted packet, so one SIMD lane is used per value.
+//! The blocks before the value are hashed once into a cached midstate, and the message schedules
+//! of the blocks after it are only expanded once since they are identical across lanes.
+//!
+//! CPUs with SHA extensions (SHA-NI on x86_64, the SHA1 instructions of ARMv8) hash one message
+//! at a time instead, which still beats the vector lanes.
+
+#[cfg(target_arch = "aarch64")]
+use std::arch::aarch64::*;
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::time::{Duration, Instant};
+
+/// Initial hash values
+const H: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+/// Round constants, one for every 20 rounds
+const K: [u32; 4] = [0x5A827999, 0x6ED9EBA1, 0x8F1BBCDC, 0xCA62C1D6];
+
+/// Most lanes processed at once by any implementation
+const MAX_LANES: usize = 8;
+
+/// Number of digests per implementation and round when calibrating
+const CALIBRATION_VALUES: u32 = 64;
+
+/// Most shared blocks hashed when calibrating
+const CALIBRATION_BLOCKS: usize = 16;
+
+/// Number of times every implementation is timed when calibrating
+const CALIBRATION_ROUNDS: usize = 3;
+
+/// Messages hashed at once with SHA-NI
+#[cfg(target_arch = "x86_64")]
+const SHA_NI_STREAMS: usize = 4;
+
+/// Messages hashed at once with the ARMv8 SHA1 instructions
+#[cfg(target_arch = "aarch64")]
+const ARMV8_STREAMS: usize = 2;
+
+/// Implementations of the batch hasher
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchImplementation {
+    /// Intel SHA extensions
+    ShaNi,
+    /// ARMv8 cryptography extensions
+    Armv8,
+    /// 8 lanes with AVX2
+    Avx2,
+    /// 4 lanes with SSE2
+    Sse2,
+    /// 4 lanes with NEON
+    Neon,
+    /// One lane at a time
+    Scalar,
+}
+
+/// SHA-1 of one message template for many values at once
+#[derive(Debug, Clone)]
+pub struct Sha1Batch {
+    midstate: [u32; 5],
+    head: [u8; 128],
+    head_blocks: usize,
+    offset: usize,
+    tail: Vec<[u32; 80]>,
+    tail_blocks: Vec<[u8; 64]>,
+    implementation: BatchImplementation,
+}
+
+impl BatchImplementation {
+    /// The preferred implementation of the current CPU
+    pub fn detect() -> Self {
+        Self::available()[0]
+    }
+
+    /// Every implementation supported by the current CPU, in order of preference
+    pub fn available() -> Vec<Self> {
+        let mut implementations = Vec::new();
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("sha")
+                && is_x86_feature_detected!("ssse3")
+                && is_x86_feature_detected!("sse4.1")
+            {
+                implementations.push(BatchImplementation::ShaNi);
+            }
+            if is_x86_feature_detected!("avx2") {
+                implementations.push(BatchImplementation::Avx2);
+            }
+            implementations.push(BatchImplementation::Sse2);
+        }
+        #[cfg(target_arch = "aarch64")]
+        {
+            if std::arch::is_aarch64_feature_detected!("sha2") {
+                implementations.push(BatchImplementation::Armv8);
+            }
+            implementations.push(BatchImplementation::Neon);
+        }
+        implementations.push(BatchImplementation::Scalar);
+        implementations
+    }
+
+    /// Number of messages hashed in parallel
+    pub fn lanes(self) -> usize {
+        match self {
+            BatchImplementation::Avx2 => 8,
+            BatchImplementation::Sse2 | BatchImplementation::Neon => 4,
+            BatchImplementation::ShaNi
+            | BatchImplementation::Armv8
+            | BatchImplementation::Scalar => 1,
+        }
+    }
+
+    /// Get the name
+    pub fn name(self) -> &'static str {
+        match self {
+            BatchImplementation::ShaNi => "sha-ni",
+            BatchImplementation::Armv8 => "armv8",
+            BatchImplementation::Avx2 => "avx2",
+            BatchImplementation::Sse2 => "sse2",
+            BatchImplementation::Neon => "neon",
+            BatchImplementation::Scalar => "scalar",
+        }
+    }
+}
+
+impl Sha1Batch {
+    /// Prepare the hasher for `message`, the values are written to `offset..offset + 4`
+    ///
+    /// Whether SHA extensions or vector lanes are faster depends on the CPU and on the number of
+    /// shared blocks, so the implementations supported by the CPU are timed on the message.
+    /// Panics if those bytes aren't part of the message.
+    pub fn new(message: &[u8], offset: usize) -> Self {
+        assert!(offset + 4 <= message.len(), "Offset out of range");
+        let mut padded = message.to_vec();
+        padded.push(0x80);
+        while padded.len() % 64 != 56 {
+            padded.push(0);
+        }
+        padded.extend_from_slice(&((message.len() as u64) * 8).to_be_bytes());
+
+        // The value may straddle two blocks
+        let first = offset / 64;
+        let end = (offset + 3) / 64 + 1;
+        let mut midstate = [
+            Scalar(H[0]),
+            Scalar(H[1]),
+            Scalar(H[2]),
+            Scalar(H[3]),
+            Scalar(H[4]),
+        ];
+        for block in padded[..first * 64].chunks(64) {
+            unsafe { compress(&mut midstate, &Shared(&expand(block))) };
+        }
+        let mut head = [0u8; 128];
+        head[..(end - first) * 64].copy_from_slice(&padded[first * 64..end * 64]);
+        let tail_blocks: Vec<[u8; 64]> = padded[end * 64..]
+            .chunks(64)
+            .map(|chunk| {
+                let mut block = [0u8; 64];
+                block.copy_from_slice(chunk);
+                block
+            })
+            .collect();
+        let tail = tail_blocks.iter().map(|block| expand(block)).collect();
+        let mut hasher = Self {
+            midstate: [
+                midstate[0].0,
+                midstate[1].0,
+                midstate[2].0,
+                midstate[3].0,
+                midstate[4].0,
+            ],
+            head,
+            head_blocks: end - first,
+            offset: offset - first * 64,
+            tail,
+            tail_blocks,
+            implementation: BatchImplementation::detect(),
+        };
+        hasher.calibrate();
+        hasher
+    }
+
+    /// Switch to the fastest implementation for the message
+    fn calibrate(&mut self) {
+        let candidates: Vec<BatchImplementation> = BatchImplementation::available()
+            .into_iter()
+            .filter(|implementation| *implementation != BatchImplementation::Scalar)
+            .collect();
+        if candidates.len() < 2 {
+            return;
+        }
+        // Long messages are timed on a prefix of their blocks
+        let shared = self.tail.len().min(CALIBRATION_BLOCKS);
+        let mut probe = Self {
+            midstate: self.midstate,
+            head: self.head,
+            head_blocks: self.head_blocks,
+            offset: self.offset,
+            tail: self.tail[..shared].to_vec(),
+            tail_blocks: self.tail_blocks[..shared].to_vec(),
+            implementation: self.implementation,
+        };
+        let values: Vec<u32> = (0..CALIBRATION_VALUES).collect();
+        let mut out = vec![[0u8; 20]; values.len()];
+        let mut fastest = (Duration::MAX, self.implementation);
+        for _ in 0..CALIBRATION_ROUNDS {
+            for candidate in candidates.iter() {
+                probe.implementation = *candidate;
+                let start = Instant::now();
+                probe.digests(&values, &mut out);
+                let elapsed = start.elapsed();
+                if elapsed < fastest.0 {
+                    fastest = (elapsed, *candidate);
+                }
+            }
+        }
+        self.implementation = fastest.1;
+    }
+
+    /// Use a specific implementation instead of the detected one
+    ///
+    /// Panics if it isn't supported by the current CPU.
+    pub fn with_implementation(mut self, implementation: BatchImplementation) -> Self {
+        assert!(
+            BatchImplementation::available().contains(&implementation),
+            "Unsupported implementation: {}",
+            implementation.name()
+        );
+        self.implementation = implementation;
+        self
+    }
+
+    /// Get the implementation in use
+    pub fn implementation(&self) -> BatchImplementation {
+        self.implementation
+    }
+
+    /// Hash the message once for every value, `out` has to be at least as long as `values`
+    pub fn digests(&self, values: &[u32], out: &mut [[u8; 20]]) {
+        assert!(out.len() >= values.len(), "Output buffer too small");
+        match self.implementation {
+            #[cfg(target_arch = "x86_64")]
+            BatchImplementation::ShaNi => unsafe { digests_sha_ni(self, values, out) },
+            #[cfg(target_arch = "aarch64")]
+            BatchImplementation::Armv8 => unsafe { digests_armv8(self, values, out) },
+            #[cfg(target_arch = "x86_64")]
+            BatchImplementation::Avx2 => unsafe { digests_avx2(self, values, out) },
+            #[cfg(target_arch = "x86_64")]
+            BatchImplementation::Sse2 => unsafe { digests_sse2(self, values, out) },
+            #[cfg(target_arch = "aarch64")]
+            BatchImplementation::Neon => unsafe { digests_neon(self, values, out) },
+            _ => unsafe { self.digests_with::<Scalar>(values, out) },
+        }
+    }
+
+    /// Hash the message for every value, `N` at a time with an interleaved compression function
+    #[inline(always)]
+    unsafe fn digests_interleaved<const N: usize>(
+        &self,
+        values: &[u32],
+        out: &mut [[u8; 20]],
+        compress: unsafe fn(&mut [[u32; 5]; N], [&[u8; 64]; N]),
+    ) {
+        for (values, out) in values.chunks(N).zip(out.chunks_mut(N)) {
+            // Unused streams repeat the last value
+            let mut heads = [[[0u8; 64]; 2]; N];
+            for (lane, head) in heads.iter_mut().enumerate() {
+                let mut bytes = self.head;
+                bytes[self.offset..self.offset + 4]
+                    .copy_from_slice(&values[lane.min(values.len() - 1)].to_be_bytes());
+                head[0].copy_from_slice(&bytes[..64]);
+                head[1].copy_from_slice(&bytes[64..]);
+            }
+            let mut states = [self.midstate; N];
+            for index in 0..self.head_blocks {
+                let mut blocks = [&heads[0][index]; N];
+                for (block, head) in blocks.iter_mut().zip(heads.iter()) {
+                    *block = &head[index];
+                }
+                compress(&mut states, blocks);
+            }
+            for block in &self.tail_blocks {
+                compress(&mut states, [block; N]);
+            }
+            for (state, digest) in states.iter().zip(out.iter_mut().take(values.len())) {
+                for (word, bytes) in state.iter().zip(digest.chunks_mut(4)) {
+                    bytes.copy_from_slice(&word.to_be_bytes());
+                }
+            }
+        }
+    }
+
+    /// Hash the message for every value, `V::WIDTH` at a time
+    #[inline(always)]
+    unsafe fn digests_with<V: Lanes>(&self, values: &[u32], out: &mut [[u8; 20]]) {
+        for (values, out) in values.chunks(V::WIDTH).zip(out.chunks_mut(V::WIDTH)) {
+            // Transpose the blocks holding the value, unused lanes repeat the last value
+            let mut words = [[0u32; MAX_LANES]; 32];
+            for lane in 0..V::WIDTH {
+                let mut bytes = self.head;
+                bytes[self.offset..self.offset + 4]
+                    .copy_from_slice(&values[lane.min(values.len() - 1)].to_be_bytes());
+                for (word, bytes) in words.iter_mut().zip(bytes.chunks(4)) {
+                    word[lane] = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                }
+            }
+
+            let mut state = [
+                V::splat(self.midstate[0]),
+                V::splat(self.midstate[1]),
+                V::splat(self.midstate[2]),
+                V::splat(self.midstate[3]),
+                V::splat(self.midstate[4]),
+            ];
+            for block in words.chunks(16).take(self.head_blocks) {
+                let mut schedule = [V::splat(0); 80];
+                for (word, lanes) in schedule.iter_mut().zip(block.iter()) {
+                    *word = V::load(lanes);
+                }
+                for t in 16..80 {
+                    schedule[t] = schedule[t - 3]
+                        .xor(schedule[t - 8])
+                        .xor(schedule[t - 14])
+                        .xor(schedule[t - 16])
+                        .rotl1();
+                }
+                for (t, word) in schedule.iter_mut().enumerate() {
+                    *word = word.add(V::splat(K[t / 20]));
+                }
+                compress(&mut state, &schedule);
+            }
+            for shared in &self.tail {
+                compress(&mut state, &Shared(shared));
+            }
+
+            let mut lanes = [[0u32; MAX_LANES]; 5];
+            for (word, lane) in state.iter().zip(lanes.iter_mut()) {
+                word.store(lane);
+            }
+            for (lane, digest) in out.iter_mut().take(values.len()).enumerate() {
+                for (word, bytes) in lanes.iter().zip(digest.chunks_mut(4)) {
+                    bytes.copy_from_slice(&word[lane].to_be_bytes());
+                }
+            }
+        }
+    }
+}
+
+/// Hash with SHA-NI
+#[target_feature(enable = "sha,ssse3,sse4.1")]
+#[cfg(target_arch = "x86_64")]
+unsafe fn digests_sha_ni(hasher: &Sha1Batch, values: &[u32], out: &mut [[u8; 20]]) {
+    hasher.digests_interleaved(values, out, compress_sha_ni::<SHA_NI_STREAMS>)
+}
+
+/// Hash with the ARMv8 SHA1 instructions
+#[target_feature(enable = "sha2")]
+#[cfg(target_arch = "aarch64")]
+unsafe fn digests_armv8(hasher: &Sha1Batch, values: &[u32], out: &mut [[u8; 20]]) {
+    hasher.digests_interleaved(values, out, compress_armv8::<ARMV8_STREAMS>)
+}
+
+/// Compress one block for each of `N` independent streams with SHA-NI
+///
+/// Four rounds per instruction, the message schedule is extended four words at a time. The
+/// streams are interleaved to hide the latency of the round instructions.
+#[target_feature(enable = "sha,ssse3,sse4.1")]
+#[cfg(target_arch = "x86_64")]
+unsafe fn compress_sha_ni<const N: usize>(states: &mut [[u32; 5]; N], blocks: [&[u8; 64]; N]) {
+    let mask = _mm_set_epi64x(0x0001_0203_0405_0607, 0x0809_0A0B_0C0D_0E0F);
+    let mut saved_abcd = [_mm_setzero_si128(); N];
+    let mut saved_e = [_mm_setzero_si128(); N];
+    let mut words = [[_mm_setzero_si128(); 4]; N];
+    for lane in 0..N {
+        let state = &states[lane];
+        saved_abcd[lane] = _mm_set_epi32(
+            state[0] as i32,
+            state[1] as i32,
+            state[2] as i32,
+            state[3] as i32,
+        );
+        saved_e[lane] = _mm_set_epi32(state[4] as i32, 0, 0, 0);
+        let pointer = blocks[lane].as_ptr() as *const __m128i;
+        for (index, word) in words[lane].iter_mut().enumerate() {
+            *word = _mm_shuffle_epi8(_mm_loadu_si128(pointer.add(index)), mask);
+        }
+    }
+
+    let mut abcd = saved_abcd;
+    let mut previous = abcd;
+    for group in 0..20 {
+        for lane in 0..N {
+            let words = &mut words[lane];
+            if group >= 4 {
+                words[group % 4] = _mm_sha1msg2_epu32(
+                    _mm_xor_si128(
+                        _mm_sha1msg1_epu32(words[group % 4], words[(group + 1) % 4]),
+                        words[(group + 2) % 4],
+                    ),
+                    words[(group + 3) % 4],
+                );
+            }
+            let e = if group == 0 {
+                _mm_add_epi32(saved_e[lane], words[0])
+            } else {
+                _mm_sha1nexte_epu32(previous[lane], words[group % 4])
+            };
+            previous[lane] = abcd[lane];
+            abcd[lane] = match group / 5 {
+                0 => _mm_sha1rnds4_epu32::<0>(abcd[lane], e),
+                1 => _mm_sha1rnds4_epu32::<1>(abcd[lane], e),
+                2 => _mm_sha1rnds4_epu32::<2>(abcd[lane], e),
+                _ => _mm_sha1rnds4_epu32::<3>(abcd[lane], e),
+            };
+        }
+    }
+
+    for lane in 0..N {
+        let abcd = _mm_add_epi32(abcd[lane], saved_abcd[lane]);
+        let e = _mm_sha1nexte_epu32(previous[lane], saved_e[lane]);
+        states[lane] = [
+            _mm_extract_epi32::<3>(abcd) as u32,
+            _mm_extract_epi32::<2>(abcd) as u32,
+            _mm_extract_epi32::<1>(abcd) as u32,
+            _mm_extract_epi32::<0>(abcd) as u32,
+            _mm_extract_epi32::<3>(e) as u32,
+        ];
+    }
+}
+
+/// Compress one block for each of `N` independent streams with the ARMv8 SHA1 instructions
+///
+/// Four rounds per instruction, the message schedule is extended four words at a time. The
+/// streams are interleaved to hide the latency of the round instructions.
+#[target_feature(enable = "sha2")]
+#[cfg(target_arch = "aarch64")]
+unsafe fn compress_armv8<const N: usize>(states: &mut [[u32; 5]; N], blocks: [&[u8; 64]; N]) {
+    let mut saved_abcd = [vdupq_n_u32(0); N];
+    let mut saved_e = [0u32; N];
+    let mut words = [[vdupq_n_u32(0); 4]; N];
+    for lane in 0..N {
+        saved_abcd[lane] = vld1q_u32(states[lane].as_ptr());
+        saved_e[lane] = states[lane][4];
+        let pointer = blocks[lane].as_ptr();
+        for (index, word) in words[lane].iter_mut().enumerate() {
+            *word = vreinterpretq_u32_u8(vrev32q_u8(vld1q_u8(pointer.add(16 * index))));
+        }
+    }
+
+    let mut abcd = saved_abcd;
+    let mut e = saved_e;
+    for group in 0..20 {
+        for lane in 0..N {
+            let words = &mut words[lane];
+            if group >= 4 {
+                words[group % 4] = vsha1su1q_u32(
+                    vsha1su0q_u32(
+                        words[group % 4],
+                        words[(group + 1) % 4],
+                        words[(group + 2) % 4],
+                    ),
+                    words[(group + 3) % 4],
+                );
+            }
+            let wk = vaddq_u32(words[group % 4], vdupq_n_u32(K[group / 5]));
+            let next_e = vsha1h_u32(vgetq_lane_u32::<0>(abcd[lane]));
+            abcd[lane] = match group / 5 {
+                0 => vsha1cq_u32(abcd[lane], e[lane], wk),
+                2 => vsha1mq_u32(abcd[lane], e[lane], wk),
+                _ => vsha1pq_u32(abcd[lane], e[lane], wk),
+            };
+            e[lane] = next_e;
+        }
+    }
+
+    for lane in 0..N {
+        vst1q_u32(
+            states[lane].as_mut_ptr(),
+            vaddq_u32(abcd[lane], saved_abcd[lane]),
+        );
+        states[lane][4] = e[lane].wrapping_add(saved_e[lane]);
+    }
+}
+
+/// Hash with AVX2
+#[target_feature(enable = "avx2")]
+#[cfg(target_arch = "x86_64")]
+unsafe fn digests_avx2(hasher: &Sha1Batch, values: &[u32], out: &mut [[u8; 20]]) {
+    hasher.digests_with::<Avx2>(values, out)
+}
+
+/// Hash with SSE2
+#[target_feature(enable = "sse2")]
+#[cfg(target_arch = "x86_64")]
+unsafe fn digests_sse2(hasher: &Sha1Batch, values: &[u32], out: &mut [[u8; 20]]) {
+    hasher.digests_with::<Sse2>(values, out)
+}
+
+/// Hash with NEON
+#[target_feature(enable = "neon")]
+#[cfg(target_arch = "aarch64")]
+unsafe fn digests_neon(hasher: &Sha1Batch, values: &[u32], out: &mut [[u8; 20]]) {
+    hasher.digests_with::<Neon>(values, out)
+}
+
+/// A vector of `u32` lanes
+trait Lanes: Copy {
+    const WIDTH: usize;
+    unsafe fn splat(value: u32) -> Self;
+    unsafe fn load(lanes: &[u32; MAX_LANES]) -> Self;
+    unsafe fn store(self, lanes: &mut [u32; MAX_LANES]);
+    unsafe fn add(self, other: Self) -> Self;
+    unsafe fn xor(self, other: Self) -> Self;
+    unsafe fn and(self, other: Self) -> Self;
+    unsafe fn or(self, other: Self) -> Self;
+    unsafe fn rotl1(self) -> Self;
+    unsafe fn rotl5(self) -> Self;
+    unsafe fn rotl30(self) -> Self;
+}
+
+/// Expanded message schedule with the round constants added
+trait Schedule<V> {
+    unsafe fn word(&self, t: usize) -> V;
+}
+
+/// Schedule of a block shared by all lanes
+struct Shared<'a>(&'a [u32; 80]);
+
+impl<V: Lanes> Schedule<V> for [V; 80] {
+    #[inline(always)]
+    unsafe fn word(&self, t: usize) -> V {
+        self[t]
+    }
+}
+
+impl<V: Lanes> Schedule<V> for Shared<'_> {
+    #[inline(always)]
+    unsafe fn word(&self, t: usize) -> V {
+        V::splat(self.0[t])
+    }
+}
+
+/// Expand the message schedule of a block and add the round constants
+fn expand(block: &[u8]) -> [u32; 80] {
+    let mut schedule = [0u32; 80];
+    for (word, bytes) in schedule.iter_mut().zip(block.chunks(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    for t in 16..80 {
+        schedule[t] = (schedule[t - 3] ^ schedule[t - 8] ^ schedule[t - 14] ^ schedule[t - 16])
+            .rotate_left(1);
+    }
+    for (t, word) in schedule.iter_mut().enumerate() {
+        *word = word.wrapping_add(K[t / 20]);
+    }
+    schedule
+}
+
+/// One round, `F` selects the round function
+#[inline(always)]
+unsafe fn round<V: Lanes, const F: usize>(state: &mut [V; 5], word: V) {
+    let [a, b, c, d, e] = *state;
+    let f = match F {
+        0 => d.xor(b.and(c.xor(d))),
+        2 => b.and(c).or(d.and(b.or(c))),
+        _ => b.xor(c).xor(d),
+    };
+    *state = [a.rotl5().add(f).add(e).add(word), a, b.rotl30(), c, d];
+}
+
+/// Process one block
+#[inline(always)]
+unsafe fn compress<V: Lanes, S: Schedule<V>>(state: &mut [V; 5], schedule: &S) {
+    let mut working = *state;
+    for t in 0..20 {
+        round::<V, 0>(&mut working, schedule.word(t));
+    }
+    for t in 20..40 {
+        round::<V, 1>(&mut working, schedule.word(t));
+    }
+    for t in 40..60 {
+        round::<V, 2>(&mut working, schedule.word(t));
+    }
+    for t in 60..80 {
+        round::<V, 3>(&mut working, schedule.word(t));
+    }
+    for (word, working) in state.iter_mut().zip(working.iter()) {
+        *word = word.add(*working);
+    }
+}
+
+/// A single lane
+#[derive(Clone, Copy)]
+struct Scalar(u32);
+
+impl Lanes for Scalar {
+    const WIDTH: usize = 1;
+
+    #[inline(always)]
+    unsafe fn splat(value: u32) -> Self {
+        Scalar(value)
+    }
+
+    #[inline(always)]
+    unsafe fn load(lanes: &[u32; MAX_LANES]) -> Self {
+        Scalar(lanes[0])
+    }
+
+    #[inline(always)]
+    unsafe fn store(self, lanes: &mut [u32; MAX_LANES]) {
+        lanes[0] = self.0;
+    }
+
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        Scalar(self.0.wrapping_add(other.0))
+    }
+
+    #[inline(always)]
+    unsafe fn xor(self, other: Self) -> Self {
+        Scalar(self.0 ^ other.0)
+    }
+
+    #[inline(always)]
+    unsafe fn and(self, other: Self) -> Self {
+        Scalar(self.0 & other.0)
+    }
+
+    #[inline(always)]
+    unsafe fn or(self, other: Self) -> Self {
+        Scalar(self.0 | other.0)
+    }
+
+    #[inline(always)]
+    unsafe fn rotl1(self) -> Self {
+        Scalar(self.0.rotate_left(1))
+    }
+
+    #[inline(always)]
+    unsafe fn rotl5(self) -> Self {
+        Scalar(self.0.rotate_left(5))
+    }
+
+    #[inline(always)]
+    unsafe fn rotl30(self) -> Self {
+        Scalar(self.0.rotate_left(30))
+    }
+}
+
+/// 8 lanes in an AVX2 register
+#[derive(Clone, Copy)]
+#[cfg(target_arch = "x86_64")]
+struct Avx2(__m256i);
+
+#[cfg(target_arch = "x86_64")]
+impl Lanes for Avx2 {
+    const WIDTH: usize = 8;
+
+    #[inline(always)]
+    unsafe fn splat(value: u32) -> Self {
+        Avx2(_mm256_set1_epi32(value as i32))
+    }
+
+    #[inline(always)]
+    unsafe fn load(lanes: &[u32; MAX_LANES]) -> Self {
+        Avx2(_mm256_loadu_si256(lanes.as_ptr() as *const _))
+    }
+
+    #[inline(always)]
+    unsafe fn store(self, lanes: &mut [u32; MAX_LANES]) {
+        _mm256_storeu_si256(lanes.as_mut_ptr() as *mut _, self.0)
+    }
+
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        Avx2(_mm256_add_epi32(self.0, other.0))
+    }
+
+    #[inline(always)]
+    unsafe fn xor(self, other: Self) -> Self {
+        Avx2(_mm256_xor_si256(self.0, other.0))
+    }
+
+    #[inline(always)]
+    unsafe fn and(self, other: Self) -> Self {
+        Avx2(_mm256_and_si256(self.0, other.0))
+    }
+
+    #[inline(always)]
+    unsafe fn or(self, other: Self) -> Self {
+        Avx2(_mm256_or_si256(self.0, other.0))
+    }
+
+    #[inline(always)]
+    unsafe fn rotl1(self) -> Self {
+        Avx2(_mm256_or_si256(
+            _mm256_slli_epi32::<1>(self.0),
+            _mm256_srli_epi32::<31>(self.0),
+        ))
+    }
+
+    #[inline(always)]
+    unsafe fn rotl5(self) -> Self {
+        Avx2(_mm256_or_si256(
+            _mm256_slli_epi32::<5>(self.0),
+            _mm256_srli_epi32::<27>(self.0),
+        ))
+    }
+
+    #[inline(always)]
+    unsafe fn rotl30(self) -> Self {
+        Avx2(_mm256_or_si256(
+            _mm256_slli_epi32::<30>(self.0),
+            _mm256_srli_epi32::<2>(self.0),
+        ))
+    }
+}
+
+/// 4 lanes in an SSE2 register
+#[derive(Clone, Copy)]
+#[cfg(target_arch = "x86_64")]
+struct Sse2(__m128i);
+
+#[cfg(target_arch = "x86_64")]
+impl Lanes for Sse2 {
+    const WIDTH: usize = 4;
+
+    #[inline(always)]
+    unsafe fn splat(value: u32) -> Self {
+        Sse2(_mm_set1_epi32(value as i32))
+    }
+
+    #[inline(always)]
+    unsafe fn load(lanes: &[u32; MAX_LANES]) -> Self {
+        Sse2(_mm_loadu_si128(lanes.as_ptr() as *const _))
+    }
+
+    #[inline(always)]
+    unsafe fn store(self, lanes: &mut [u32; MAX_LANES]) {
+        _mm_storeu_si128(lanes.as_mut_ptr() as *mut _, self.0)
+    }
+
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        Sse2(_mm_add_epi32(self.0, other.0))
+    }
+
+    #[inline(always)]
+    unsafe fn xor(self, other: Self) -> Self {
+        Sse2(_mm_xor_si128(self.0, other.0))
+    }
+
+    #[inline(always)]
+    unsafe fn and(self, other: Self) -> Self {
+        Sse2(_mm_and_si128(self.0, other.0))
+    }
+
+    #[inline(always)]
+    unsafe fn or(self, other: Self) -> Self {
+        Sse2(_mm_or_si128(self.0, other.0))
+    }
+
+    #[inline(always)]
+    unsafe fn rotl1(self) -> Self {
+        Sse2(_mm_or_si128(
+            _mm_slli_epi32::<1>(self.0),
+            _mm_srli_epi32::<31>(self.0),
+        ))
+    }
+
+    #[inline(always)]
+    unsafe fn rotl5(self) -> Self {
+        Sse2(_mm_or_si128(
+            _mm_slli_epi32::<5>(self.0),
+            _mm_srli_epi32::<27>(self.0),
+        ))
+    }
+
+    #[inline(always)]
+    unsafe fn rotl30(self) -> Self {
+        Sse2(_mm_or_si128(
+            _mm_slli_epi32::<30>(self.0),
+            _mm_srli_epi32::<2>(self.0),
+        ))
+    }
+}
+
+/// 4 lanes in a NEON register
+#[derive(Clone, Copy)]
+#[cfg(target_arch = "aarch64")]
+struct Neon(uint32x4_t);
+
+#[cfg(target_arch = "aarch64")]
+impl Lanes for Neon {
+    const WIDTH: usize = 4;
+
+    #[inline(always)]
+    unsafe fn splat(value: u32) -> Self {
+        Neon(vdupq_n_u32(value))
+    }
+
+    #[inline(always)]
+    unsafe fn load(lanes: &[u32; MAX_LANES]) -> Self {
+        Neon(vld1q_u32(lanes.as_ptr()))
+    }
+
+    #[inline(always)]
+    unsafe fn store(self, lanes: &mut [u32; MAX_LANES]) {
+        vst1q_u32(lanes.as_mut_ptr(), self.0)
+    }
+
+    #[inline(always)]
+    unsafe fn add(self, other: Self) -> Self {
+        Neon(vaddq_u32(self.0, other.0))
+    }
+
+    #[inline(always)]
+    unsafe fn xor(self, other: Self) -> Self {
+        Neon(veorq_u32(self.0, other.0))
+    }
+
+    #[inline(always)]
+    unsafe fn and(self, other: Self) -> Self {
+        Neon(vandq_u32(self.0, other.0))
+    }
+
+    #[inline(always)]
+    unsafe fn or(self, other: Self) -> Self {
+        Neon(vorrq_u32(self.0, other.0))
+    }
+
+    #[inline(always)]
+    unsafe fn rotl1(self) -> Self {
+        Neon(vsriq_n_u32::<31>(vshlq_n_u32::<1>(self.0), self.0))
+    }
+
+    #[inline(always)]
+    unsafe fn rotl5(self) -> Self {
+        Neon(vsriq_n_u32::<27>(vshlq_n_u32::<5>(self.0), self.0))
+    }
+
+    #[inline(always)]
+    unsafe fn rotl30(self) -> Self {
+        Neon(vsriq_n_u32::<2>(vshlq_n_u32::<30>(self.0), self.0))
+    }
+}
+
+#[cfg(test)]
+mod sha1_batch_test {
+    use super::{BatchImplementation, Sha1Batch};
+    use hex::encode_upper;
+
+    /// Value stored at `offset`
+    fn word_at(message: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes([
+            message[offset],
+            message[offset + 1],
+            message[offset + 2],
+            message[offset + 3],
+        ])
+    }
+
+    #[test]
+    fn known_answers() {
+        let vectors: [(&[u8], usize, &str); 6] = [
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                0,
+                "84983E441C3BD26EBAAE4AA1F95129E5E54670F1",
+            ),
+            (
+                b"The quick brown fox jumps over the lazy dog",
+                39,
+                "2FD4E1C67A2D28FCED849EE1BB76E7391B93EB12",
+            ),
+            (
+                b"The quick brown fox jumps over the lazy cog",
+                39,
+                "DE9F2C7FD25E1B3AFAD3E85A0BD17D9B100DB4B3",
+            ),
+            (
+                b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu",
+                60,
+                "A49B2446A02C645BF419F995B67091253A04A259",
+            ),
+            (
+                b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu",
+                62,
+                "A49B2446A02C645BF419F995B67091253A04A259",
+            ),
+            (
+                b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu",
+                108,
+                "A49B2446A02C645BF419F995B67091253A04A259",
+            ),
+        ];
+        for implementation in BatchImplementation::available() {
+            for (message, offset, expected) in vectors.iter() {
+                let hasher = Sha1Batch::new(message, *offset).with_implementation(implementation);
+                let mut out = [[0u8; 20]; 3];
+                hasher.digests(&[word_at(message, *offset); 3], &mut out);
+                for digest in out.iter() {
+                    assert_eq!(&encode_upper(digest), expected, "{}", implementation.name());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn long_message() {
+        let message = vec![b'a'; 1000000];
+        for offset in [0, 500030, 999996] {
+            for implementation in BatchImplementation::available() {
+                let hasher = Sha1Batch::new(&message, offset).with_implementation(implementation);
+                let mut out = [[0u8; 20]; 1];
+                hasher.digests(&[word_at(&message, offset)], &mut out);
+                assert_eq!(
+                    encode_upper(out[0]),
+                    "34AA973CD4C4DAA4F61EEB2BDBAD27316534016F"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn implementations_agree() {
+        let values: Vec<u32> = (0..37u32)
+            .map(|index| index.wrapping_mul(0x9E3779B9))
+            .collect();
+        for length in [8, 55, 56, 63, 64, 65, 119, 120, 200, 531] {
+            let message: Vec<u8> = (0..length).map(|index| (index * 7) as u8).collect();
+            for offset in [4, 60, 62, 126, length - 4] {
+                if offset + 4 > length {
+                    continue;
+                }
+                let reference = Sha1Batch::new(&message, offset)
+                    .with_implementation(BatchImplementation::Scalar);
+                let mut expected = vec![[0u8; 20]; values.len()];
+                reference.digests(&values, &mut expected);
+
+                // Without a midstate
+                let mut changed = message.clone();
+                changed[offset..offset + 4].copy_from_slice(&values[5].to_be_bytes());
+                let mut single = [[0u8; 20]; 1];
+                Sha1Batch::new(&changed, 0).digests(&[word_at(&changed, 0)], &mut single);
+                assert_eq!(single[0], expected[5], "{} {}", length, offset);
+
+                for implementation in BatchImplementation::available() {
+                    let hasher =
+                        Sha1Batch::new(&message, offset).with_implementation(implementation);
+                    let mut out = vec![[0u8; 20]; values.len()];
+                    hasher.digests(&values, &mut out);
+                    let name = implementation.name();
+                    assert_eq!(out, expected, "{} {} {}", name, length, offset);
+                }
+            }
+        }
+    }
+}
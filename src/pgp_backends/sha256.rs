@@ -0,0 +1,448 @@
+//! SHA-256 for v6 fingerprints and signatures
+//!
+//! `Sha256Batch` is the SHA-256 counterpart of `Sha1Batch`: the blocks before the value are
+//! hashed once into a midstate and the schedules of the blocks after it are only expanded once.
+//! SHA-256 has no cheap vector lanes, so messages are hashed one at a time, with SHA-NI where the
+//! CPU has it.
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+
+use super::BatchImplementation;
+
+/// Initial hash values
+const H: [u32; 8] = [
+    0x6A09E667, 0xBB67AE85, 0x3C6EF372, 0xA54FF53A, 0x510E527F, 0x9B05688C, 0x1F83D9AB, 0x5BE0CD19,
+];
+
+/// Round constants
+const K: [u32; 64] = [
+    0x428A2F98, 0x71374491, 0xB5C0FBCF, 0xE9B5DBA5, 0x3956C25B, 0x59F111F1, 0x923F82A4, 0xAB1C5ED5,
+    0xD807AA98, 0x12835B01, 0x243185BE, 0x550C7DC3, 0x72BE5D74, 0x80DEB1FE, 0x9BDC06A7, 0xC19BF174,
+    0xE49B69C1, 0xEFBE4786, 0x0FC19DC6, 0x240CA1CC, 0x2DE92C6F, 0x4A7484AA, 0x5CB0A9DC, 0x76F988DA,
+    0x983E5152, 0xA831C66D, 0xB00327C8, 0xBF597FC7, 0xC6E00BF3, 0xD5A79147, 0x06CA6351, 0x14292967,
+    0x27B70A85, 0x2E1B2138, 0x4D2C6DFC, 0x53380D13, 0x650A7354, 0x766A0ABB, 0x81C2C92E, 0x92722C85,
+    0xA2BFE8A1, 0xA81A664B, 0xC24B8B70, 0xC76C51A3, 0xD192E819, 0xD6990624, 0xF40E3585, 0x106AA070,
+    0x19A4C116, 0x1E376C08, 0x2748774C, 0x34B0BCB5, 0x391C0CB3, 0x4ED8AA4A, 0x5B9CCA4F, 0x682E6FF3,
+    0x748F82EE, 0x78A5636F, 0x84C87814, 0x8CC70208, 0x90BEFFFA, 0xA4506CEB, 0xBEF9A3F7, 0xC67178F2,
+];
+
+/// Streaming SHA-256
+#[derive(Debug, Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    buffer: [u8; 64],
+    buffered: usize,
+    length: u64,
+}
+
+/// SHA-256 of one message template for many values at once
+#[derive(Debug, Clone)]
+pub struct Sha256Batch {
+    midstate: [u32; 8],
+    head: [u8; 128],
+    head_blocks: usize,
+    offset: usize,
+    tail: Vec<[u32; 64]>,
+    tail_blocks: Vec<[u8; 64]>,
+    implementation: BatchImplementation,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha256 {
+    /// Start a new hash
+    pub fn new() -> Self {
+        Self {
+            state: H,
+            buffer: [0; 64],
+            buffered: 0,
+            length: 0,
+        }
+    }
+
+    /// Hash a whole message
+    pub fn digest(message: &[u8]) -> [u8; 32] {
+        let mut hasher = Self::new();
+        hasher.update(message);
+        hasher.finalize()
+    }
+
+    /// Feed more of the message
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if self.buffered > 0 {
+            let taken = data.len().min(64 - self.buffered);
+            self.buffer[self.buffered..self.buffered + taken].copy_from_slice(&data[..taken]);
+            self.buffered += taken;
+            data = &data[taken..];
+            if self.buffered < 64 {
+                return;
+            }
+            compress(&mut self.state, &expand(&self.buffer));
+            self.buffered = 0;
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            compress(&mut self.state, &expand(block));
+        }
+        let rest = blocks.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffered = rest.len();
+    }
+
+    /// Pad the message and get the digest
+    pub fn finalize(mut self) -> [u8; 32] {
+        let bits = self.length * 8;
+        self.update(&[0x80]);
+        while self.buffered != 56 {
+            self.update(&[0]);
+        }
+        self.update(&bits.to_be_bytes());
+        let mut digest = [0u8; 32];
+        for (word, bytes) in self.state.iter().zip(digest.chunks_mut(4)) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+}
+
+impl Sha256Batch {
+    /// Every implementation supported by the current CPU, in order of preference
+    pub fn available() -> Vec<BatchImplementation> {
+        let mut implementations = Vec::new();
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("sha")
+                && is_x86_feature_detected!("ssse3")
+                && is_x86_feature_detected!("sse4.1")
+            {
+                implementations.push(BatchImplementation::ShaNi);
+            }
+        }
+        implementations.push(BatchImplementation::Scalar);
+        implementations
+    }
+
+    /// Prepare the hasher for `message`, the values are written to `offset..offset + 4`
+    ///
+    /// Panics if those bytes aren't part of the message.
+    pub fn new(message: &[u8], offset: usize) -> Self {
+        assert!(offset + 4 <= message.len(), "Offset out of range");
+        let mut padded = message.to_vec();
+        padded.push(0x80);
+        while padded.len() % 64 != 56 {
+            padded.push(0);
+        }
+        padded.extend_from_slice(&((message.len() as u64) * 8).to_be_bytes());
+
+        // The value may straddle two blocks
+        let first = offset / 64;
+        let end = (offset + 3) / 64 + 1;
+        let mut midstate = H;
+        for block in padded[..first * 64].chunks(64) {
+            compress(&mut midstate, &expand(block));
+        }
+        let mut head = [0u8; 128];
+        head[..(end - first) * 64].copy_from_slice(&padded[first * 64..end * 64]);
+        let tail_blocks: Vec<[u8; 64]> = padded[end * 64..]
+            .chunks(64)
+            .map(|chunk| {
+                let mut block = [0u8; 64];
+                block.copy_from_slice(chunk);
+                block
+            })
+            .collect();
+        Self {
+            midstate,
+            head,
+            head_blocks: end - first,
+            offset: offset - first * 64,
+            tail: tail_blocks.iter().map(|block| expand(block)).collect(),
+            tail_blocks,
+            implementation: Self::available()[0],
+        }
+    }
+
+    /// Use a specific implementation instead of the detected one
+    ///
+    /// Panics if it isn't supported by the current CPU or by this hasher.
+    pub fn with_implementation(mut self, implementation: BatchImplementation) -> Self {
+        assert!(
+            Self::available().contains(&implementation),
+            "Unsupported implementation: {}",
+            implementation.name()
+        );
+        self.implementation = implementation;
+        self
+    }
+
+    /// Get the implementation in use
+    pub fn implementation(&self) -> BatchImplementation {
+        self.implementation
+    }
+
+    /// Hash the message once for every value, `out` has to be at least as long as `values`
+    pub fn digests(&self, values: &[u32], out: &mut [[u8; 32]]) {
+        assert!(out.len() >= values.len(), "Output buffer too small");
+        match self.implementation {
+            #[cfg(target_arch = "x86_64")]
+            BatchImplementation::ShaNi => unsafe { digests_sha_ni(self, values, out) },
+            _ => self.digests_scalar(values, out),
+        }
+    }
+
+    /// The first blocks of the message with `value` written into them
+    #[inline(always)]
+    fn head(&self, value: u32) -> [u8; 128] {
+        let mut head = self.head;
+        head[self.offset..self.offset + 4].copy_from_slice(&value.to_be_bytes());
+        head
+    }
+
+    /// Hash one message at a time
+    fn digests_scalar(&self, values: &[u32], out: &mut [[u8; 32]]) {
+        for (value, digest) in values.iter().zip(out.iter_mut()) {
+            let head = self.head(*value);
+            let mut state = self.midstate;
+            for block in head.chunks(64).take(self.head_blocks) {
+                compress(&mut state, &expand(block));
+            }
+            for schedule in &self.tail {
+                compress(&mut state, schedule);
+            }
+            for (word, bytes) in state.iter().zip(digest.chunks_mut(4)) {
+                bytes.copy_from_slice(&word.to_be_bytes());
+            }
+        }
+    }
+}
+
+/// Hash with SHA-NI
+#[target_feature(enable = "sha,ssse3,sse4.1")]
+#[cfg(target_arch = "x86_64")]
+unsafe fn digests_sha_ni(hasher: &Sha256Batch, values: &[u32], out: &mut [[u8; 32]]) {
+    for (value, digest) in values.iter().zip(out.iter_mut()) {
+        let head = hasher.head(*value);
+        let mut state = hasher.midstate;
+        for block in head.chunks(64).take(hasher.head_blocks) {
+            compress_sha_ni(&mut state, block);
+        }
+        for block in &hasher.tail_blocks {
+            compress_sha_ni(&mut state, block);
+        }
+        for (word, bytes) in state.iter().zip(digest.chunks_mut(4)) {
+            bytes.copy_from_slice(&word.to_be_bytes());
+        }
+    }
+}
+
+/// Compress one block with SHA-NI
+#[inline(always)]
+#[cfg(target_arch = "x86_64")]
+unsafe fn compress_sha_ni(state: &mut [u32; 8], block: &[u8]) {
+    let byte_swap = _mm_set_epi64x(0x0C0D0E0F08090A0Bu64 as i64, 0x0405060700010203u64 as i64);
+
+    // The instructions want the state as ABEF and CDGH
+    let dcba = _mm_loadu_si128(state.as_ptr() as *const __m128i);
+    let hgfe = _mm_loadu_si128(state.as_ptr().add(4) as *const __m128i);
+    let cdab = _mm_shuffle_epi32(dcba, 0xB1);
+    let efgh = _mm_shuffle_epi32(hgfe, 0x1B);
+    let mut abef = _mm_alignr_epi8(cdab, efgh, 8);
+    let mut cdgh = _mm_blend_epi16(efgh, cdab, 0xF0);
+    let (abef_saved, cdgh_saved) = (abef, cdgh);
+
+    let mut words = [_mm_setzero_si128(); 16];
+    for (index, word) in words.iter_mut().take(4).enumerate() {
+        let bytes = _mm_loadu_si128(block.as_ptr().add(index * 16) as *const __m128i);
+        *word = _mm_shuffle_epi8(bytes, byte_swap);
+    }
+    for index in 0..16 {
+        if index >= 4 {
+            let shifted = _mm_alignr_epi8(words[index - 1], words[index - 2], 4);
+            let partial = _mm_add_epi32(
+                _mm_sha256msg1_epu32(words[index - 4], words[index - 3]),
+                shifted,
+            );
+            words[index] = _mm_sha256msg2_epu32(partial, words[index - 1]);
+        }
+        let constants = _mm_loadu_si128(K.as_ptr().add(index * 4) as *const __m128i);
+        let scheduled = _mm_add_epi32(words[index], constants);
+        cdgh = _mm_sha256rnds2_epu32(cdgh, abef, scheduled);
+        abef = _mm_sha256rnds2_epu32(abef, cdgh, _mm_shuffle_epi32(scheduled, 0x0E));
+    }
+    abef = _mm_add_epi32(abef, abef_saved);
+    cdgh = _mm_add_epi32(cdgh, cdgh_saved);
+
+    let feba = _mm_shuffle_epi32(abef, 0x1B);
+    let dchg = _mm_shuffle_epi32(cdgh, 0xB1);
+    let dcba = _mm_blend_epi16(feba, dchg, 0xF0);
+    let hgef = _mm_alignr_epi8(dchg, feba, 8);
+    _mm_storeu_si128(state.as_mut_ptr() as *mut __m128i, dcba);
+    _mm_storeu_si128(state.as_mut_ptr().add(4) as *mut __m128i, hgef);
+}
+
+/// Expand the message schedule of a block and add the round constants
+fn expand(block: &[u8]) -> [u32; 64] {
+    let mut schedule = [0u32; 64];
+    for (word, bytes) in schedule.iter_mut().zip(block.chunks(4)) {
+        *word = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    for t in 16..64 {
+        let s0 = schedule[t - 15].rotate_right(7)
+            ^ schedule[t - 15].rotate_right(18)
+            ^ (schedule[t - 15] >> 3);
+        let s1 = schedule[t - 2].rotate_right(17)
+            ^ schedule[t - 2].rotate_right(19)
+            ^ (schedule[t - 2] >> 10);
+        schedule[t] = schedule[t - 16]
+            .wrapping_add(s0)
+            .wrapping_add(schedule[t - 7])
+            .wrapping_add(s1);
+    }
+    for (word, constant) in schedule.iter_mut().zip(K.iter()) {
+        *word = word.wrapping_add(*constant);
+    }
+    schedule
+}
+
+/// Process one block
+#[inline(always)]
+fn compress(state: &mut [u32; 8], schedule: &[u32; 64]) {
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = *state;
+    for word in schedule {
+        let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let choice = (e & f) ^ (!e & g);
+        let t1 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(*word);
+        let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let majority = (a & b) ^ (a & c) ^ (b & c);
+        let t2 = s0.wrapping_add(majority);
+        h = g;
+        g = f;
+        f = e;
+        e = d.wrapping_add(t1);
+        d = c;
+        c = b;
+        b = a;
+        a = t1.wrapping_add(t2);
+    }
+    for (word, working) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+        *word = word.wrapping_add(working);
+    }
+}
+
+#[cfg(test)]
+mod sha256_test {
+    use super::{Sha256, Sha256Batch};
+    use hex::encode_upper;
+
+    /// Value stored at `offset`
+    fn word_at(message: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes([
+            message[offset],
+            message[offset + 1],
+            message[offset + 2],
+            message[offset + 3],
+        ])
+    }
+
+    #[test]
+    fn known_answers() {
+        let vectors: [(&[u8], &str); 4] = [
+            (
+                b"",
+                "E3B0C44298FC1C149AFBF4C8996FB92427AE41E4649B934CA495991B7852B855",
+            ),
+            (
+                b"abc",
+                "BA7816BF8F01CFEA414140DE5DAE2223B00361A396177A9CB410FF61F20015AD",
+            ),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "248D6A61D20638B8E5C026930C3E6039A33CE45964FF2167F6ECEDD419DB06C1",
+            ),
+            (
+                b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu",
+                "CF5B16A778AF8380036CE59E7B0492370B249B11E8F07A51AFAC45037AFEE9D1",
+            ),
+        ];
+        for (message, expected) in vectors.iter() {
+            assert_eq!(&encode_upper(Sha256::digest(message)), expected);
+
+            // Fed in uneven pieces
+            let mut hasher = Sha256::new();
+            for piece in message.chunks(7) {
+                hasher.update(piece);
+            }
+            assert_eq!(&encode_upper(hasher.finalize()), expected);
+
+            for implementation in Sha256Batch::available() {
+                for offset in [0, message.len().saturating_sub(4)] {
+                    if offset + 4 > message.len() {
+                        continue;
+                    }
+                    let hasher =
+                        Sha256Batch::new(message, offset).with_implementation(implementation);
+                    let mut out = [[0u8; 32]; 2];
+                    hasher.digests(&[word_at(message, offset); 2], &mut out);
+                    for digest in out.iter() {
+                        assert_eq!(&encode_upper(digest), expected, "{}", implementation.name());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn long_message() {
+        let message = vec![b'a'; 1000000];
+        assert_eq!(
+            encode_upper(Sha256::digest(&message)),
+            "CDC76E5C9914FB9281A1C7E284D73E67F1809A48A497200E046D39CCC7112CD0"
+        );
+        for offset in [0, 500030, 999996] {
+            for implementation in Sha256Batch::available() {
+                let hasher = Sha256Batch::new(&message, offset).with_implementation(implementation);
+                let mut out = [[0u8; 32]; 1];
+                hasher.digests(&[word_at(&message, offset)], &mut out);
+                assert_eq!(
+                    encode_upper(out[0]),
+                    "CDC76E5C9914FB9281A1C7E284D73E67F1809A48A497200E046D39CCC7112CD0"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn implementations_agree() {
+        let values: Vec<u32> = (0..37u32)
+            .map(|index| index.wrapping_mul(0x9E3779B9))
+            .collect();
+        for length in [8, 55, 56, 63, 64, 65, 119, 120, 200, 531] {
+            let message: Vec<u8> = (0..length).map(|index| (index * 7) as u8).collect();
+            for offset in [4, 60, 62, 126, length - 4] {
+                if offset + 4 > length {
+                    continue;
+                }
+                let mut expected = vec![[0u8; 32]; values.len()];
+                for (value, digest) in values.iter().zip(expected.iter_mut()) {
+                    let mut changed = message.clone();
+                    changed[offset..offset + 4].copy_from_slice(&value.to_be_bytes());
+                    *digest = Sha256::digest(&changed);
+                }
+                for implementation in Sha256Batch::available() {
+                    let hasher =
+                        Sha256Batch::new(&message, offset).with_implementation(implementation);
+                    let mut out = vec![[0u8; 32]; values.len()];
+                    hasher.digests(&values, &mut out);
+                    let name = implementation.name();
+                    assert_eq!(out, expected, "{} {} {}", name, length, offset);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,620 @@
+//! Creation time windows
+//!
+//! A key is shuffled through the creation times of a window, either backwards from the newest one,
+//! forwards from the oldest one or in a pseudo-random order. The newest creation time is capped at
+//! the moment the key was generated, so that no key claims to be created in the future. Once every
+//! creation time of the window was visited the key can't be shuffled any further and a new one has
+//! to be generated.
+//!
+//! The random order is a keyed permutation of the window, a Feistel network walked until it lands
+//! inside the window, so creation times never repeat and nothing has to be remembered about the
+//! ones already visited.
+//!
+//! Version 4 creation times are unsigned 32-bit timestamps, which last until 2106.
+
+use super::{Digest, FingerprintBatch, PGPError};
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Number of Feistel rounds of the random walk
+const FEISTEL_ROUNDS: usize = 4;
+
+/// Order creation times are walked in
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Walk {
+    /// From the newest creation time to the oldest
+    #[default]
+    Backward,
+    /// From the oldest creation time to the newest
+    Forward,
+    /// Every creation time once, in a pseudo-random order
+    Random,
+}
+
+/// Creation times a key may have, both ends included
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CreationWindow {
+    not_before: u32,
+    not_after: u32,
+    walk: Walk,
+}
+
+/// Pseudo-random permutation of `0..size`
+#[derive(Debug, Clone)]
+struct Permutation {
+    size: u64,
+    /// Bits of each half of the Feistel network, which covers `size` at most four times over
+    half_bits: u32,
+    keys: [u64; FEISTEL_ROUNDS],
+}
+
+/// Creation times of one key, visited in the order of its window
+#[derive(Debug, Clone)]
+pub(crate) struct TimestampWalk {
+    window: CreationWindow,
+    /// Number of creation times visited before the current one
+    position: u32,
+    /// First position of the slice of the window being walked
+    start: u32,
+    /// End of the slice, exclusive
+    end: u64,
+    /// Order of a random walk
+    permutation: Option<Permutation>,
+}
+
+impl Walk {
+    /// All walks
+    pub const ALL: [Walk; 3] = [Walk::Backward, Walk::Forward, Walk::Random];
+
+    /// Name used on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Walk::Backward => "backward",
+            Walk::Forward => "forward",
+            Walk::Random => "random",
+        }
+    }
+}
+
+impl FromStr for Walk {
+    type Err = PGPError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .iter()
+            .find(|walk| walk.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| PGPError::WalkNotSupported(String::from(s)))
+    }
+}
+
+impl std::fmt::Display for Walk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl Default for CreationWindow {
+    fn default() -> Self {
+        Self {
+            not_before: 0,
+            not_after: u32::MAX,
+            walk: Walk::default(),
+        }
+    }
+}
+
+impl CreationWindow {
+    /// Create a window from `not_before` to `not_after` (Unix timestamps), walked with `walk`
+    pub fn new(not_before: u32, not_after: u32, walk: Walk) -> Result<Self, PGPError> {
+        if not_before > not_after {
+            return Err(PGPError::EmptyCreationWindow);
+        }
+        Ok(Self {
+            not_before,
+            not_after,
+            walk,
+        })
+    }
+
+    /// Get the oldest creation time
+    pub fn not_before(&self) -> u32 {
+        self.not_before
+    }
+
+    /// Get the newest creation time
+    pub fn not_after(&self) -> u32 {
+        self.not_after
+    }
+
+    /// Get the order of the walk
+    pub fn walk(&self) -> Walk {
+        self.walk
+    }
+
+    /// Get the number of creation times in the window
+    pub fn size(&self) -> u64 {
+        u64::from(self.not_after - self.not_before) + 1
+    }
+
+    /// The window of a key generated at `created`, which must not be dated later
+    pub fn until(&self, created: u32) -> Result<Self, PGPError> {
+        Self::new(self.not_before, self.not_after.min(created), self.walk)
+    }
+}
+
+impl Permutation {
+    /// Create the permutation of `0..size` selected by `seed`
+    fn new(size: u64, seed: u64) -> Self {
+        let bits = 64 - (size - 1).leading_zeros();
+        let mut state = seed;
+        let mut keys = [0u64; FEISTEL_ROUNDS];
+        for key in keys.iter_mut() {
+            state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            *key = mix(state);
+        }
+        Self {
+            size,
+            half_bits: bits.div_ceil(2).max(1),
+            keys,
+        }
+    }
+
+    /// Map `value`, which must be below `size`
+    fn apply(&self, value: u32) -> u32 {
+        // Values outside the range are mapped again, they are on the same cycle as `value`
+        let mut value = u64::from(value);
+        loop {
+            value = self.feistel(value);
+            if value < self.size {
+                return value as u32;
+            }
+        }
+    }
+
+    /// One pass through the Feistel network
+    fn feistel(&self, value: u64) -> u64 {
+        let mask = (1u64 << self.half_bits) - 1;
+        let (mut left, mut right) = (value >> self.half_bits, value & mask);
+        for key in &self.keys {
+            (left, right) = (right, left ^ (mix(right ^ key) & mask));
+        }
+        (left << self.half_bits) | right
+    }
+}
+
+impl TimestampWalk {
+    /// Start walking `window`, a random walk gets an order of its own
+    pub(crate) fn new(window: CreationWindow) -> Self {
+        let permutation = match window.walk {
+            Walk::Random => {
+                let seed = RandomState::new().build_hasher().finish();
+                Some(Permutation::new(window.size(), seed))
+            }
+            Walk::Backward | Walk::Forward => None,
+        };
+        Self {
+            window,
+            position: 0,
+            start: 0,
+            end: window.size(),
+            permutation,
+        }
+    }
+
+    /// Restrict the walk to slice `index` of `count` disjoint slices, and start over in it
+    pub(crate) fn slice(&mut self, index: usize, count: usize) -> Result<(), PGPError> {
+        if index >= count {
+            return Err(PGPError::EmptyCreationWindow);
+        }
+        let size = u128::from(self.window.size());
+        let bound = |index: usize| (size * index as u128 / count as u128) as u64;
+        let (start, end) = (bound(index), bound(index + 1));
+        if start == end {
+            return Err(PGPError::EmptyCreationWindow);
+        }
+        // Below the size of the window
+        self.start = start as u32;
+        self.end = end;
+        self.position = self.start;
+        Ok(())
+    }
+
+    /// Get the current creation time
+    pub(crate) fn timestamp(&self) -> u32 {
+        self.at(self.position)
+    }
+
+    /// The creation time at `position`, which must be in the window
+    fn at(&self, position: u32) -> u32 {
+        match (&self.permutation, self.window.walk) {
+            (Some(permutation), _) => self.window.not_before + permutation.apply(position),
+            (None, Walk::Backward) => self.window.not_after - position,
+            (None, _) => self.window.not_before + position,
+        }
+    }
+
+    /// Number of creation times after the current one
+    fn left(&self) -> u64 {
+        self.end - 1 - u64::from(self.position)
+    }
+
+    /// Move `count` creation times on
+    pub(crate) fn advance(&mut self, count: usize) -> Result<(), PGPError> {
+        match u64::try_from(count) {
+            Ok(count) if count <= self.left() => {
+                // Below the end of the slice, which is at most the size of the window
+                self.position += count as u32;
+                Ok(())
+            }
+            _ => Err(PGPError::CreationWindowExhausted),
+        }
+    }
+
+    /// Go back to the first creation time of the slice
+    pub(crate) fn restart(&mut self) {
+        self.position = self.start;
+    }
+
+    /// Fingerprint the current creation time and the ones following it
+    ///
+    /// Returns the number of digests written.
+    pub(crate) fn fingerprints(&self, batch: &FingerprintBatch, out: &mut [Digest]) -> usize {
+        let count = usize::try_from(self.left() + 1).map_or(out.len(), |left| left.min(out.len()));
+        let out = &mut out[..count];
+        match (&self.permutation, self.window.walk) {
+            (Some(_), _) => {
+                let mut values = [0u32; 64];
+                for (index, chunk) in out.chunks_mut(values.len()).enumerate() {
+                    let first = self.position + (index * values.len()) as u32;
+                    for (offset, value) in values.iter_mut().take(chunk.len()).enumerate() {
+                        *value = self.at(first + offset as u32);
+                    }
+                    batch.digests(&values[..chunk.len()], chunk);
+                }
+                count
+            }
+            (None, Walk::Backward) => batch.digests_descending(self.timestamp(), out),
+            (None, _) => batch.digests_ascending(self.timestamp(), out),
+        }
+    }
+}
+
+/// Mix the bits of `value`, the finalizer of SplitMix64
+fn mix(mut value: u64) -> u64 {
+    value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    value ^ (value >> 31)
+}
+
+/// Convert a point in time to an OpenPGP timestamp
+pub fn unix_timestamp(time: SystemTime) -> Result<u32, PGPError> {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| PGPError::TimestampOutOfRange)?
+        .as_secs();
+    u32::try_from(seconds).map_err(|_| PGPError::TimestampOutOfRange)
+}
+
+/// Parse a point in time as a Unix timestamp
+///
+/// Accepts Unix timestamps and UTC dates like `2024-03-01`, `2024-03-01T12:30` or
+/// `2024-03-01T12:30:15Z`. The time must fit in an unsigned 32-bit timestamp.
+pub fn parse_timestamp(s: &str) -> Result<u32, PGPError> {
+    let invalid = || PGPError::InvalidTimestamp(String::from(s));
+    let s = s.trim();
+    if !s.is_empty() && s.bytes().all(|byte| byte.is_ascii_digit()) {
+        return s.parse().map_err(|_| invalid());
+    }
+    let (date, time) = s
+        .trim_end_matches('Z')
+        .split_once(['T', ' '])
+        .unwrap_or((s, "00:00"));
+    let number = |part: Option<&str>| -> Result<i64, PGPError> {
+        part.filter(|part| !part.is_empty() && part.bytes().all(|byte| byte.is_ascii_digit()))
+            .and_then(|part| part.parse().ok())
+            .ok_or_else(invalid)
+    };
+    let mut date = date.splitn(3, '-');
+    let (year, month, day) = (
+        number(date.next())?,
+        number(date.next())?,
+        number(date.next())?,
+    );
+    let mut time = time.splitn(3, ':');
+    let (hour, minute) = (number(time.next())?, number(time.next())?);
+    let second = match time.next() {
+        Some(second) => number(Some(second))?,
+        None => 0,
+    };
+    if !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 59
+    {
+        return Err(invalid());
+    }
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second;
+    u32::try_from(seconds).map_err(|_| invalid())
+}
+
+/// Number of days in a month of the proleptic Gregorian calendar
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days between 1970-01-01 and a date, after Howard Hinnant's `days_from_civil`
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod window_test {
+    use super::{
+        parse_timestamp, unix_timestamp, CreationWindow, Permutation, TimestampWalk, Walk,
+    };
+    use crate::pgp_backends::{Digest, FingerprintBatch, KeyVersion, PGPError};
+    use std::collections::HashSet;
+    use std::str::FromStr;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    /// Walk `window` up to `timestamp`
+    fn walk_to(window: CreationWindow, timestamp: u32) -> TimestampWalk {
+        let mut walk = TimestampWalk::new(window);
+        let count = match window.walk() {
+            Walk::Backward => window.not_after() - timestamp,
+            _ => timestamp - window.not_before(),
+        };
+        walk.advance(count as usize).unwrap();
+        assert_eq!(walk.timestamp(), timestamp);
+        walk
+    }
+
+    #[test]
+    fn walks() {
+        let window = CreationWindow::new(100, 200, Walk::Backward).unwrap();
+        assert_eq!(window.size(), 101);
+        assert_eq!(
+            TimestampWalk::new(window.until(150).unwrap()).timestamp(),
+            150
+        );
+        assert_eq!(
+            TimestampWalk::new(window.until(500).unwrap()).timestamp(),
+            200
+        );
+        assert!(window.until(99).is_err());
+        let mut walk = walk_to(window, 150);
+        assert!(walk.advance(51).is_err());
+        assert!(walk.advance(usize::MAX).is_err());
+        walk.advance(50).unwrap();
+        assert_eq!(walk.timestamp(), 100);
+        walk.restart();
+        assert_eq!(walk.timestamp(), 200);
+
+        let window = CreationWindow::new(100, 200, Walk::Forward).unwrap();
+        let mut walk = TimestampWalk::new(window.until(150).unwrap());
+        assert_eq!(walk.timestamp(), 100);
+        assert!(walk.advance(51).is_err());
+        walk.advance(50).unwrap();
+        assert_eq!(walk.timestamp(), 150);
+        assert!(CreationWindow::new(201, 200, Walk::Forward).is_err());
+
+        // The ends of the timestamp range
+        let window = CreationWindow::default();
+        assert_eq!(window.size(), 1 << 32);
+        let mut walk = walk_to(window, 1);
+        walk.advance(1).unwrap();
+        assert_eq!(walk.timestamp(), 0);
+        assert!(matches!(
+            walk.advance(1),
+            Err(PGPError::CreationWindowExhausted)
+        ));
+        let mut walk = TimestampWalk::new(window);
+        walk.advance(u32::MAX as usize).unwrap();
+        assert_eq!(walk.timestamp(), 0);
+        let forward = CreationWindow::new(0, u32::MAX, Walk::Forward).unwrap();
+        let mut walk = walk_to(forward, u32::MAX - 1);
+        walk.advance(1).unwrap();
+        assert_eq!(walk.timestamp(), u32::MAX);
+        assert!(matches!(
+            walk.advance(1),
+            Err(PGPError::CreationWindowExhausted)
+        ));
+        // Past 2038, where signed timestamps overflow
+        let mut walk = walk_to(forward, i32::MAX as u32);
+        walk.advance(1).unwrap();
+        assert_eq!(walk.timestamp(), 1 << 31);
+        let single = CreationWindow::new(u32::MAX, u32::MAX, Walk::Random).unwrap();
+        let mut walk = TimestampWalk::new(single);
+        assert_eq!(walk.timestamp(), u32::MAX);
+        assert!(walk.advance(1).is_err());
+
+        assert_eq!(Walk::from_str("Forward").unwrap(), Walk::Forward);
+        assert_eq!(Walk::from_str("random").unwrap(), Walk::Random);
+        assert!(Walk::from_str("sideways").is_err());
+    }
+
+    #[test]
+    fn permutations() {
+        for size in [1, 2, 3, 4, 5, 17, 1000, 4096, 4097] {
+            for seed in 0..4 {
+                let permutation = Permutation::new(size, seed);
+                let values: HashSet<u32> = (0..size as u32)
+                    .map(|value| permutation.apply(value))
+                    .collect();
+                assert_eq!(values.len() as u64, size, "{} {}", size, seed);
+                assert!(values.iter().all(|value| u64::from(*value) < size));
+            }
+        }
+        // Different seeds give different orders
+        let first: Vec<u32> = (0..100)
+            .map(|value| Permutation::new(1000, 1).apply(value))
+            .collect();
+        let second: Vec<u32> = (0..100)
+            .map(|value| Permutation::new(1000, 2).apply(value))
+            .collect();
+        assert_ne!(first, second);
+        // The whole timestamp range
+        let permutation = Permutation::new(1 << 32, 7);
+        assert_eq!(permutation.half_bits, 16);
+        assert_ne!(permutation.apply(u32::MAX), permutation.apply(0));
+    }
+
+    #[test]
+    fn random_walk() {
+        let window = CreationWindow::new(1_000_000, 1_000_999, Walk::Random).unwrap();
+        let mut walk = TimestampWalk::new(window);
+        let mut seen = HashSet::new();
+        loop {
+            let timestamp = walk.timestamp();
+            assert!((1_000_000..=1_000_999).contains(&timestamp));
+            assert!(seen.insert(timestamp), "{} repeated", timestamp);
+            if walk.advance(1).is_err() {
+                break;
+            }
+        }
+        assert_eq!(seen.len(), 1000);
+    }
+
+    #[test]
+    fn slices() {
+        for walk in Walk::ALL {
+            let window = CreationWindow::new(1000, 1099, walk).unwrap();
+            let mut timestamps = Vec::new();
+            let mut sizes = Vec::new();
+            let first = TimestampWalk::new(window);
+            for index in 0..3 {
+                let mut slice = first.clone();
+                slice.slice(index, 3).unwrap();
+                let start = slice.timestamp();
+                let mut size = 0;
+                loop {
+                    timestamps.push(slice.timestamp());
+                    size += 1;
+                    if slice.advance(1).is_err() {
+                        break;
+                    }
+                }
+                sizes.push(size);
+                slice.restart();
+                assert_eq!(slice.timestamp(), start);
+            }
+            assert_eq!(sizes, [33, 33, 34], "{}", walk);
+            timestamps.sort_unstable();
+            assert_eq!(timestamps, (1000..=1099).collect::<Vec<u32>>(), "{}", walk);
+        }
+
+        let mut walk = TimestampWalk::new(CreationWindow::new(10, 11, Walk::Forward).unwrap());
+        assert!(walk.clone().slice(0, 3).is_err());
+        assert!(walk.clone().slice(3, 3).is_err());
+        assert!(walk.clone().slice(0, 0).is_err());
+        walk.slice(2, 3).unwrap();
+        assert_eq!(walk.timestamp(), 11);
+        assert!(walk.advance(1).is_err());
+
+        // The whole timestamp range, split unevenly
+        let mut walk = TimestampWalk::new(CreationWindow::default());
+        walk.slice(6, 7).unwrap();
+        assert_eq!(u64::from(walk.position), (1u64 << 32) * 6 / 7);
+        walk.advance(walk.left() as usize).unwrap();
+        assert_eq!(walk.timestamp(), 0);
+    }
+
+    #[test]
+    fn fingerprints() {
+        let batch = FingerprintBatch::new(KeyVersion::V4, &[0u8; 16], 4);
+        let mut out = [Digest::default(); 64];
+        let mut single = [Digest::default(); 1];
+        let backward = CreationWindow::new(1000, 2000, Walk::Backward).unwrap();
+        assert_eq!(walk_to(backward, 1010).fingerprints(&batch, &mut out), 11);
+        batch.digests(&[1000], &mut single);
+        assert_eq!(out[10], single[0]);
+
+        let forward = CreationWindow::new(1000, 2000, Walk::Forward).unwrap();
+        assert_eq!(walk_to(forward, 1990).fingerprints(&batch, &mut out), 11);
+        batch.digests(&[2000], &mut single);
+        assert_eq!(out[10], single[0]);
+        assert_eq!(walk_to(forward, 1000).fingerprints(&batch, &mut out), 64);
+        batch.digests(&[1063], &mut single);
+        assert_eq!(out[63], single[0]);
+
+        // Random walks fingerprint the creation times they advance to
+        let random = CreationWindow::new(1000, 1099, Walk::Random).unwrap();
+        let mut walk = TimestampWalk::new(random);
+        walk.advance(30).unwrap();
+        let mut digests = [Digest::default(); 100];
+        assert_eq!(walk.fingerprints(&batch, &mut digests), 70);
+        for digest in digests.iter().take(70) {
+            batch.digests(&[walk.timestamp()], &mut single);
+            assert_eq!(digest, &single[0]);
+            let _ = walk.advance(1);
+        }
+
+        // The ends of the timestamp range
+        let walk = walk_to(CreationWindow::default(), 0);
+        assert_eq!(walk.fingerprints(&batch, &mut out), 1);
+        let forward = CreationWindow::new(0, u32::MAX, Walk::Forward).unwrap();
+        assert_eq!(walk_to(forward, u32::MAX).fingerprints(&batch, &mut out), 1);
+        batch.digests(&[u32::MAX], &mut single);
+        assert_eq!(out[0], single[0]);
+        assert_eq!(
+            walk_to(forward, u32::MAX - 1).fingerprints(&batch, &mut out),
+            2
+        );
+        assert_eq!(TimestampWalk::new(forward).fingerprints(&batch, &mut []), 0);
+    }
+
+    #[test]
+    fn unix_timestamps() {
+        assert_eq!(unix_timestamp(UNIX_EPOCH).unwrap(), 0);
+        let y2038 = UNIX_EPOCH + Duration::from_secs(1 << 31);
+        assert_eq!(unix_timestamp(y2038).unwrap(), 1 << 31);
+        let last = UNIX_EPOCH + Duration::from_secs(u32::MAX as u64);
+        assert_eq!(unix_timestamp(last).unwrap(), u32::MAX);
+        assert!(matches!(
+            unix_timestamp(last + Duration::from_secs(1)),
+            Err(PGPError::TimestampOutOfRange)
+        ));
+        assert!(matches!(
+            unix_timestamp(UNIX_EPOCH - Duration::from_secs(1)),
+            Err(PGPError::TimestampOutOfRange)
+        ));
+        assert_eq!(parse_timestamp("2038-01-19T03:14:08Z").unwrap(), 1 << 31);
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(parse_timestamp("1700000000").unwrap(), 1700000000);
+        assert_eq!(parse_timestamp("1970-01-01").unwrap(), 0);
+        assert_eq!(parse_timestamp("2000-03-01").unwrap(), 951868800);
+        assert_eq!(parse_timestamp("2024-02-29T12:30").unwrap(), 1709209800);
+        assert_eq!(parse_timestamp("2024-02-29 12:30:15Z").unwrap(), 1709209815);
+        assert_eq!(parse_timestamp("2106-02-07T06:28:15").unwrap(), u32::MAX);
+        for invalid in [
+            "",
+            "2023-02-29",
+            "2024-13-01",
+            "2024-01-01T24:00",
+            "1969-12-31",
+            "2106-02-07T06:28:16",
+            "4294967296",
+            "yesterday",
+        ] {
+            assert!(parse_timestamp(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
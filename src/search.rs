@@ -0,0 +1,598 @@
+//! Search driver
+//!
+//! The loop every worker thread runs: test the current key, shuffle its creation time, and
+//! generate a fresh key once a match was found or the reshuffle limit was reached. Backends that
+//! can derive a new key from the current one (`Backend::renew`) do so instead of generating one
+//! when the limit is reached. It is generic over the `Matcher`, so any acceptance logic can be
+//! plugged in.
+//!
+//! Candidates are fingerprinted in batches through `Backend::fingerprints`, the key is only
+//! moved once a batch has been tested.
+//!
+//! With `ReshufflePolicy::Adaptive`, the search measures how long a new key and a candidate take
+//! and shuffles each key just long enough for new keys to cost a small share of the time. Cheap
+//! keys are then replaced often and end up less backdated, expensive ones use the whole window.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::matcher::{Candidate, Matcher};
+use crate::pgp_backends::{Backend, Digest, PGPError};
+
+/// Default number of shuffles before generating a new key
+pub const DEFAULT_RESHUFFLE_LIMIT: usize = 60000000; // One month ago at worst
+
+/// Default share of the time an adaptive search spends on new keys
+pub const DEFAULT_KEYGEN_OVERHEAD: f64 = 0.01;
+
+/// Number of candidates fingerprinted at once
+const BATCH_SIZE: usize = 64;
+
+/// Fewest shuffles per key an adaptive search picks
+const MIN_ADAPTIVE_LIMIT: usize = BATCH_SIZE * 64;
+
+/// Weight of the newest measurement in the running averages of the costs
+const COST_WEIGHT: f64 = 0.2;
+
+/// How many shuffles a key gets before a new one is generated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReshufflePolicy {
+    /// Always the same number of shuffles
+    Fixed(usize),
+    /// Just enough shuffles that new keys take at most `overhead` of the time, but at most `max`
+    Adaptive { max: usize, overhead: f64 },
+}
+
+/// Running averages of what a search measured, in seconds
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Costs {
+    /// Time to generate (or derive) a new key
+    pub keygen: Option<f64>,
+    /// Time to shuffle, fingerprint and test one candidate
+    pub candidate: Option<f64>,
+}
+
+/// Result of a call to `Search::run`
+#[derive(Debug)]
+pub enum Step<B> {
+    /// A key was accepted, the next `run` continues with a new key
+    Found {
+        key: B,
+        digest: Digest,
+        tested: usize,
+    },
+    /// The reshuffle limit was reached (or shuffling failed) and a new key was generated or derived
+    Regenerated { tested: usize },
+    /// The budget was used up
+    Exhausted { tested: usize },
+}
+
+/// Search state of one thread
+#[derive(Debug)]
+pub struct Search<B, M, G> {
+    matcher: M,
+    generate: G,
+    /// `None` after a match, until the next `run` generates a new key
+    key: Option<B>,
+    policy: ReshufflePolicy,
+    reshuffle_limit: usize,
+    remaining: usize,
+    costs: Costs,
+    /// Time spent on new keys during the current `run`
+    keygen_time: Duration,
+    digests: [Digest; BATCH_SIZE],
+}
+
+/// The key behind a candidate, `offset` shuffles ahead of the current key of the search
+#[derive(Debug)]
+pub struct KeyAt<'a, B> {
+    key: &'a B,
+    offset: usize,
+}
+
+impl<B> Step<B> {
+    /// Number of candidates tested during the step
+    pub fn tested(&self) -> usize {
+        match self {
+            Step::Found { tested, .. }
+            | Step::Regenerated { tested }
+            | Step::Exhausted { tested } => *tested,
+        }
+    }
+}
+
+impl ReshufflePolicy {
+    /// Adaptive policy with the default overhead and at most `max` shuffles
+    pub fn adaptive(max: usize) -> Self {
+        ReshufflePolicy::Adaptive {
+            max,
+            overhead: DEFAULT_KEYGEN_OVERHEAD,
+        }
+    }
+
+    /// The number of shuffles per key for the measured `costs`
+    ///
+    /// An adaptive policy uses `max` until both costs are known.
+    pub fn limit(&self, costs: &Costs) -> usize {
+        match *self {
+            ReshufflePolicy::Fixed(limit) => limit,
+            ReshufflePolicy::Adaptive { max, overhead } => match (costs.keygen, costs.candidate) {
+                (Some(keygen), Some(candidate)) if candidate > 0.0 && overhead > 0.0 => {
+                    // A key costs `keygen + limit * candidate`, the first part is the overhead
+                    let limit = keygen * (1.0 - overhead) / (overhead * candidate);
+                    (limit.min(max as f64) as usize).max(MIN_ADAPTIVE_LIMIT.min(max))
+                }
+                _ => max,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ReshufflePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReshufflePolicy::Fixed(limit) => write!(f, "fixed, {} shuffles per key", limit),
+            ReshufflePolicy::Adaptive { max, overhead } => write!(
+                f,
+                "adaptive, new keys take at most {}% of the time, at most {} shuffles per key",
+                overhead * 100.0,
+                max
+            ),
+        }
+    }
+}
+
+impl Costs {
+    /// Fold a new measurement into a running average
+    fn update(average: &mut Option<f64>, value: f64) {
+        *average = Some(match *average {
+            Some(average) => average + COST_WEIGHT * (value - average),
+            None => value,
+        });
+    }
+}
+
+impl<'a, B: Backend> KeyAt<'a, B> {
+    /// Get the current key of the search
+    pub fn base(&self) -> &'a B {
+        self.key
+    }
+
+    /// Number of shuffles between the current key and the candidate
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Clone the current key and move it to the candidate
+    pub fn to_key(&self) -> Result<B, PGPError>
+    where
+        B: Clone,
+    {
+        let mut key = self.key.clone();
+        key.advance(self.offset)?;
+        Ok(key)
+    }
+}
+
+impl<B, M, G> Search<B, M, G>
+where
+    B: Backend,
+    M: Matcher,
+    G: FnMut() -> Result<B, PGPError>,
+{
+    /// Start a search, `generate` is called for every new key
+    pub fn new(matcher: M, mut generate: G) -> Result<Self, PGPError> {
+        let start = Instant::now();
+        let key = generate()?;
+        let mut costs = Costs::default();
+        Costs::update(&mut costs.keygen, start.elapsed().as_secs_f64());
+        Ok(Self {
+            key: Some(key),
+            matcher,
+            generate,
+            policy: ReshufflePolicy::Fixed(DEFAULT_RESHUFFLE_LIMIT),
+            reshuffle_limit: DEFAULT_RESHUFFLE_LIMIT,
+            remaining: DEFAULT_RESHUFFLE_LIMIT,
+            costs,
+            keygen_time: Duration::ZERO,
+            digests: [Digest::default(); BATCH_SIZE],
+        })
+    }
+
+    /// Set the number of shuffles before a new key is generated
+    pub fn with_reshuffle_limit(self, reshuffle_limit: usize) -> Self {
+        self.with_reshuffle_policy(ReshufflePolicy::Fixed(reshuffle_limit))
+    }
+
+    /// Set how the number of shuffles before a new key is generated is chosen
+    pub fn with_reshuffle_policy(mut self, policy: ReshufflePolicy) -> Self {
+        self.policy = policy;
+        self.reshuffle_limit = policy.limit(&self.costs);
+        self.remaining = self.reshuffle_limit;
+        self
+    }
+
+    /// Get the current number of shuffles before a new key is generated
+    pub fn reshuffle_limit(&self) -> usize {
+        self.reshuffle_limit
+    }
+
+    /// Get the costs measured so far
+    pub fn costs(&self) -> &Costs {
+        &self.costs
+    }
+
+    /// Get the matcher
+    pub fn matcher(&self) -> &M {
+        &self.matcher
+    }
+
+    /// Get the current key, `None` after a match until the next `run`
+    pub fn key(&self) -> Option<&B> {
+        self.key.as_ref()
+    }
+
+    /// Account for the time a new key took, and start its shuffles
+    fn started_key(&mut self, start: Instant) {
+        let elapsed = start.elapsed();
+        self.keygen_time += elapsed;
+        Costs::update(&mut self.costs.keygen, elapsed.as_secs_f64());
+        self.reshuffle_limit = self.policy.limit(&self.costs);
+        self.remaining = self.reshuffle_limit;
+    }
+
+    /// Replace the current key with a freshly generated one
+    fn regenerate(&mut self) -> Result<(), PGPError> {
+        let start = Instant::now();
+        self.key = Some((self.generate)()?);
+        self.started_key(start);
+        Ok(())
+    }
+
+    /// Move on to a new key, derived from the current one if the backend can
+    fn renew(&mut self) -> Result<(), PGPError> {
+        let start = Instant::now();
+        if self
+            .key
+            .as_mut()
+            .is_some_and(|key| key.renew().unwrap_or(false))
+        {
+            self.started_key(start);
+            return Ok(());
+        }
+        self.regenerate()
+    }
+
+    /// Test up to `budget` candidates, stopping early on a match or a new key
+    ///
+    /// `inspect` sees every candidate together with the verdict of the matcher and the key it
+    /// belongs to. The key after a match is only generated here, so that a failing generator
+    /// can't lose the matched key.
+    pub fn run<F: FnMut(&KeyAt<'_, B>, &Candidate<'_>, bool)>(
+        &mut self,
+        budget: usize,
+        inspect: F,
+    ) -> Result<Step<B>, PGPError> {
+        let start = Instant::now();
+        self.keygen_time = Duration::ZERO;
+        if self.key.is_none() {
+            self.regenerate()?;
+        }
+        let step = self.step(budget, inspect)?;
+        let tested = step.tested();
+        if tested > 0 {
+            let elapsed = start.elapsed().saturating_sub(self.keygen_time);
+            Costs::update(
+                &mut self.costs.candidate,
+                elapsed.as_secs_f64() / tested as f64,
+            );
+            if let ReshufflePolicy::Adaptive { .. } = self.policy {
+                // Keep the shuffles already done to the current key
+                let limit = self.policy.limit(&self.costs);
+                let done = self.reshuffle_limit - self.remaining.min(self.reshuffle_limit);
+                self.remaining = limit.saturating_sub(done);
+                self.reshuffle_limit = limit;
+            }
+        }
+        Ok(step)
+    }
+
+    /// The body of `run`
+    fn step<F: FnMut(&KeyAt<'_, B>, &Candidate<'_>, bool)>(
+        &mut self,
+        budget: usize,
+        mut inspect: F,
+    ) -> Result<Step<B>, PGPError> {
+        let mut tested = 0;
+        while tested < budget {
+            let wanted = (budget - tested)
+                .min(BATCH_SIZE)
+                .min(self.remaining.saturating_add(1));
+            let key = self
+                .key
+                .as_mut()
+                .expect("a key is generated at the start of every run");
+            let filled = key.fingerprints(&mut self.digests[..wanted]);
+            let mut found = None;
+            for (offset, digest) in self.digests[..filled].iter().enumerate() {
+                let candidate = Candidate::new(digest);
+                let matched = self.matcher.accepts(&candidate);
+                let key = KeyAt { key: &*key, offset };
+                inspect(&key, &candidate, matched);
+                if matched {
+                    found = Some(offset);
+                    break;
+                }
+            }
+            if let Some(offset) = found {
+                key.advance(offset)?;
+                return Ok(Step::Found {
+                    key: self.key.take().expect("the matched key"),
+                    digest: self.digests[offset],
+                    tested: tested + offset + 1,
+                });
+            }
+            tested += filled;
+            if filled == 0 || filled > self.remaining || key.advance(filled).is_err() {
+                self.renew()?;
+                return Ok(Step::Regenerated { tested });
+            }
+            self.remaining -= filled;
+        }
+        Ok(Step::Exhausted { tested })
+    }
+}
+
+#[cfg(test)]
+mod search_test {
+    use super::{Costs, ReshufflePolicy, Search, Step, MIN_ADAPTIVE_LIMIT};
+    use crate::matcher::{from_fn, Candidate, Matcher, NibbleMask};
+    use crate::pgp_backends::{ArmoredKey, Backend, Digest, PGPError, UniversalError, UserID};
+
+    /// Backend whose digest is its key number followed by its timestamp
+    #[derive(Debug, Clone)]
+    struct MockBackend {
+        number: u8,
+        timestamp: u32,
+        /// Creation time of renewed keys, `None` if the key can't be renewed
+        renewable: Option<u32>,
+    }
+
+    impl Backend for MockBackend {
+        fn fingerprint_digest(&self) -> Digest {
+            let mut digest = [0u8; 20];
+            digest[0] = self.number;
+            digest[16..].copy_from_slice(&self.timestamp.to_be_bytes());
+            Digest::from(digest)
+        }
+
+        fn fingerprints(&mut self, out: &mut [Digest]) -> usize {
+            let count = out.len().min(self.timestamp as usize + 1);
+            let start = self.timestamp;
+            for (offset, digest) in out[..count].iter_mut().enumerate() {
+                self.timestamp = start - offset as u32;
+                *digest = self.fingerprint_digest();
+            }
+            self.timestamp = start;
+            count
+        }
+
+        fn shuffle(&mut self) -> Result<(), PGPError> {
+            self.timestamp = self
+                .timestamp
+                .checked_sub(1)
+                .ok_or(PGPError::CreationWindowExhausted)?;
+            Ok(())
+        }
+
+        fn renew(&mut self) -> Result<bool, PGPError> {
+            match self.renewable {
+                Some(timestamp) => {
+                    self.number += 100;
+                    self.timestamp = timestamp;
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+
+        fn get_armored_results(self, _uid: &UserID) -> Result<ArmoredKey, UniversalError> {
+            unimplemented!()
+        }
+    }
+
+    /// Generator handing out numbered keys
+    fn generator(timestamp: u32) -> impl FnMut() -> Result<MockBackend, PGPError> {
+        let mut number = 0;
+        move || {
+            number += 1;
+            Ok(MockBackend {
+                number,
+                timestamp,
+                renewable: None,
+            })
+        }
+    }
+
+    #[test]
+    fn found() {
+        let mut mask = NibbleMask::new();
+        for (position, nibble) in [0, 0, 0, 0, 0, 0, 0, 5].iter().enumerate() {
+            mask.set(32 + position, *nibble);
+        }
+        let mut search = Search::new(mask, generator(10)).unwrap();
+        let mut inspected = Vec::new();
+        let step = search
+            .run(100, |key, _, matched| {
+                inspected.push((key.to_key().unwrap().timestamp, matched))
+            })
+            .unwrap();
+        match step {
+            Step::Found {
+                key,
+                digest,
+                tested,
+            } => {
+                assert_eq!(key.number, 1);
+                assert_eq!(key.timestamp, 5);
+                assert_eq!(digest[19], 5);
+                assert_eq!(tested, 6);
+            }
+            other => panic!("unexpected step {:?}", other),
+        }
+        assert_eq!(inspected.last(), Some(&(5, true)));
+        assert!(inspected[..5].iter().all(|(_, matched)| !matched));
+        assert!(search.key().is_none());
+        search.run(1, |_, _, _| {}).unwrap();
+        assert_eq!(search.key().unwrap().number, 2);
+    }
+
+    #[test]
+    fn failing_generator() {
+        let always = from_fn(|_: &Candidate<'_>| true);
+        let mut next = generator(10);
+        let mut calls = 0;
+        // Fails right after the first key, then recovers
+        let generate = || {
+            calls += 1;
+            match calls {
+                2 => Err(PGPError::CreationWindowExhausted),
+                _ => next(),
+            }
+        };
+        let mut search = Search::new(&always, generate).unwrap();
+        match search.run(10, |_, _, _| {}).unwrap() {
+            Step::Found { key, .. } => assert_eq!(key.number, 1),
+            other => panic!("unexpected step {:?}", other),
+        }
+        assert!(search.run(10, |_, _, _| {}).is_err());
+        assert!(search.key().is_none());
+        match search.run(10, |_, _, _| {}).unwrap() {
+            Step::Found { key, .. } => assert_eq!(key.number, 2),
+            other => panic!("unexpected step {:?}", other),
+        }
+    }
+
+    #[test]
+    fn batches() {
+        let mut mask = NibbleMask::new();
+        for (position, nibble) in [0, 0, 0, 0, 0, 0, 0, 0].iter().enumerate() {
+            mask.set(32 + position, *nibble);
+        }
+        let mut search = Search::new(mask, generator(1000)).unwrap();
+        let step = search.run(200, |_, _, _| {}).unwrap();
+        assert!(matches!(step, Step::Exhausted { tested: 200 }));
+        assert_eq!(search.key().unwrap().timestamp, 800);
+
+        // The key of a match is moved to the matching timestamp
+        let step = search.run(10000, |_, _, _| {}).unwrap();
+        match step {
+            Step::Found { key, tested, .. } => {
+                assert_eq!(key.timestamp, 0);
+                assert_eq!(tested, 801);
+            }
+            other => panic!("unexpected step {:?}", other),
+        }
+    }
+
+    #[test]
+    fn regenerate_and_exhaust() {
+        let never = from_fn(|_: &Candidate<'_>| false);
+        let mut search = Search::new(&never, generator(100))
+            .unwrap()
+            .with_reshuffle_limit(3);
+        let step = search.run(10, |_, _, _| {}).unwrap();
+        assert!(matches!(step, Step::Regenerated { tested: 4 }));
+        assert_eq!(search.key().unwrap().number, 2);
+        let step = search.run(2, |_, _, _| {}).unwrap();
+        assert!(matches!(step, Step::Exhausted { tested: 2 }));
+        assert_eq!(step.tested(), 2);
+
+        // Failing shuffles also lead to a new key
+        let mut search = Search::new((&never).not().not(), generator(1)).unwrap();
+        let step = search.run(10, |_, _, _| {}).unwrap();
+        assert!(matches!(step, Step::Regenerated { tested: 2 }));
+        let mut search = Search::new(&never, generator(100)).unwrap();
+        let step = search.run(1000, |_, _, _| {}).unwrap();
+        assert!(matches!(step, Step::Regenerated { tested: 101 }));
+    }
+
+    #[test]
+    fn renew() {
+        let never = from_fn(|_: &Candidate<'_>| false);
+        let mut number = 0;
+        let generate = || {
+            number += 1;
+            Ok(MockBackend {
+                number,
+                timestamp: 100,
+                renewable: Some(100),
+            })
+        };
+        let mut search = Search::new(&never, generate)
+            .unwrap()
+            .with_reshuffle_limit(3);
+        let step = search.run(10, |_, _, _| {}).unwrap();
+        assert!(matches!(step, Step::Regenerated { tested: 4 }));
+        assert_eq!(search.key().unwrap().number, 101);
+        assert_eq!(search.key().unwrap().timestamp, 100);
+        let step = search.run(10, |_, _, _| {}).unwrap();
+        assert!(matches!(step, Step::Regenerated { tested: 4 }));
+        assert_eq!(search.key().unwrap().number, 201);
+    }
+
+    #[test]
+    fn policy() {
+        let costs = Costs {
+            keygen: Some(1e-3),
+            candidate: Some(1e-7),
+        };
+        assert_eq!(ReshufflePolicy::Fixed(42).limit(&costs), 42);
+        let adaptive = ReshufflePolicy::adaptive(100_000_000);
+        // 990000 candidates take 99 times as long as the key
+        let limit = adaptive.limit(&costs);
+        assert!((989_999..=990_000).contains(&limit));
+        assert_eq!(adaptive.limit(&Costs::default()), 100_000_000);
+        // Expensive keys use the whole window, cheap ones are still shuffled a bit
+        let expensive = Costs {
+            keygen: Some(2.0),
+            ..costs
+        };
+        assert_eq!(adaptive.limit(&expensive), 100_000_000);
+        let cheap = Costs {
+            keygen: Some(1e-9),
+            ..costs
+        };
+        assert_eq!(adaptive.limit(&cheap), MIN_ADAPTIVE_LIMIT);
+        assert_eq!(ReshufflePolicy::adaptive(10).limit(&cheap), 10);
+    }
+
+    #[test]
+    fn adaptive() {
+        let never = from_fn(|_: &Candidate<'_>| false);
+        let mut slow = generator(u32::MAX);
+        let generate = || {
+            std::thread::sleep(std::time::Duration::from_millis(1));
+            slow()
+        };
+        let mut search = Search::new(&never, generate)
+            .unwrap()
+            .with_reshuffle_policy(ReshufflePolicy::Adaptive {
+                max: 100_000_000,
+                overhead: 0.5,
+            });
+        assert_eq!(search.reshuffle_limit(), 100_000_000);
+        assert!(search.costs().keygen.unwrap() >= 1e-3);
+        search.run(10000, |_, _, _| {}).unwrap();
+        assert!(search.costs().candidate.is_some());
+        let limit = search.reshuffle_limit();
+        assert!((MIN_ADAPTIVE_LIMIT..100_000_000).contains(&limit));
+        // The key is replaced once the adapted limit is reached
+        loop {
+            if let Step::Regenerated { .. } = search.run(usize::MAX, |_, _, _| {}).unwrap() {
+                break;
+            }
+        }
+        assert_eq!(search.key().unwrap().number, 2);
+    }
+}
@@ -0,0 +1,327 @@
+//! # Statistics
+//!
+//! Every worker thread owns a slot of counters that only it writes to, so counting never
+//! contends with other threads. The summary thread samples the slots periodically and derives
+//! rolling rates from the samples.
+
+use std::collections::VecDeque;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Windows of the rolling rates and their names
+pub const RATE_WINDOWS: [(&str, Duration); 3] = [
+    ("1s", Duration::from_secs(1)),
+    ("10s", Duration::from_secs(10)),
+    ("60s", Duration::from_secs(60)),
+];
+
+/// Counters of one worker thread
+///
+/// Aligned to 128 bytes so that two threads never write to the same cache line (or pair of
+/// adjacent lines, which some CPUs prefetch together).
+#[derive(Debug, Default)]
+#[repr(align(128))]
+pub struct ThreadCounters {
+    hashes: AtomicU64,
+    keys: AtomicU64,
+}
+
+/// Counters of all worker threads
+#[derive(Debug)]
+pub struct Statistics {
+    threads: Vec<ThreadCounters>,
+}
+
+/// Hashes counted by every thread at some point in time
+#[derive(Debug, Clone)]
+struct Sample {
+    time: Instant,
+    hashes: Vec<u64>,
+}
+
+/// Rolling rates computed from periodic samples of `Statistics`
+#[derive(Debug)]
+pub struct Rates {
+    start: Instant,
+    samples: VecDeque<Sample>,
+}
+
+impl ThreadCounters {
+    /// Count tested fingerprints, must only be called by the owning thread
+    pub fn count_hashes(&self, count: usize) {
+        // There is a single writer, a read-modify-write isn't needed
+        let hashes = self.hashes.load(Ordering::Relaxed);
+        self.hashes.store(hashes + count as u64, Ordering::Relaxed);
+    }
+
+    /// Count a new key, generated or derived, must only be called by the owning thread
+    pub fn count_key(&self) {
+        let keys = self.keys.load(Ordering::Relaxed);
+        self.keys.store(keys + 1, Ordering::Relaxed);
+    }
+
+    /// Get the number of tested fingerprints
+    pub fn get_hashes(&self) -> u64 {
+        self.hashes.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of new keys
+    pub fn get_keys(&self) -> u64 {
+        self.keys.load(Ordering::Relaxed)
+    }
+}
+
+impl Statistics {
+    /// Create counters for `threads` worker threads
+    pub fn new(threads: usize) -> Self {
+        Self {
+            threads: (0..threads).map(|_| ThreadCounters::default()).collect(),
+        }
+    }
+
+    /// Get the counters of a worker thread
+    pub fn thread(&self, index: usize) -> &ThreadCounters {
+        &self.threads[index]
+    }
+
+    /// Get the number of fingerprints tested by all threads
+    pub fn get_hashes(&self) -> u64 {
+        self.threads.iter().map(ThreadCounters::get_hashes).sum()
+    }
+
+    /// Get the number of new keys of all threads
+    pub fn get_keys(&self) -> u64 {
+        self.threads.iter().map(ThreadCounters::get_keys).sum()
+    }
+
+    /// Get the number of shuffles, every tested fingerprint but the first of each key
+    pub fn get_shuffles(&self) -> u64 {
+        self.threads
+            .iter()
+            .map(|thread| thread.get_hashes().saturating_sub(thread.get_keys()))
+            .sum()
+    }
+
+    /// Render the counters and `rates` as a JSON object
+    pub fn to_json(&self, rates: &Rates) -> String {
+        let mut json = String::new();
+        write!(
+            json,
+            "{{\"elapsed\": {:.3}, \"hashes\": {}, \"shuffles\": {}, \"keys\": {}, \
+             \"rates\": {{\"average\": {}",
+            rates.elapsed().as_secs_f64(),
+            self.get_hashes(),
+            self.get_shuffles(),
+            self.get_keys(),
+            json_number(rates.average()),
+        )
+        .unwrap();
+        for (name, window) in RATE_WINDOWS {
+            write!(json, ", \"{}\": {}", name, json_number(rates.rate(window))).unwrap();
+        }
+        json.push_str("}, \"threads\": [");
+        let thread_rates = rates.thread_rates(RATE_WINDOWS[1].1);
+        for (index, thread) in self.threads.iter().enumerate() {
+            if index > 0 {
+                json.push_str(", ");
+            }
+            write!(
+                json,
+                "{{\"hashes\": {}, \"keys\": {}, \"rate\": {}}}",
+                thread.get_hashes(),
+                thread.get_keys(),
+                json_number(thread_rates.get(index).copied().flatten()),
+            )
+            .unwrap();
+        }
+        json.push_str("]}");
+        json
+    }
+}
+
+impl Rates {
+    /// Start measuring now
+    pub fn new() -> Self {
+        Self::starting_at(Instant::now())
+    }
+
+    /// Start measuring at `start`
+    fn starting_at(start: Instant) -> Self {
+        Self {
+            start,
+            samples: VecDeque::new(),
+        }
+    }
+
+    /// Sample the counters of `statistics`
+    pub fn sample(&mut self, statistics: &Statistics) {
+        let hashes = statistics.threads.iter().map(|thread| thread.get_hashes());
+        self.record(Instant::now(), hashes.collect());
+    }
+
+    /// Record the hashes counted by every thread at `time`
+    fn record(&mut self, time: Instant, hashes: Vec<u64>) {
+        self.samples.push_back(Sample { time, hashes });
+        // Keep one sample older than the longest window as its baseline
+        let longest = RATE_WINDOWS[RATE_WINDOWS.len() - 1].1;
+        while self.samples.len() > 2 && time.duration_since(self.samples[1].time) >= longest {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Time since the start
+    pub fn elapsed(&self) -> Duration {
+        match self.samples.back() {
+            Some(latest) => latest.time.duration_since(self.start),
+            None => Duration::ZERO,
+        }
+    }
+
+    /// Average rate since the start in hash/s, `None` until some time has elapsed
+    pub fn average(&self) -> Option<f64> {
+        let latest = self.samples.back()?;
+        let elapsed = self.elapsed().as_secs_f64();
+        (elapsed > 0.0).then(|| latest.hashes.iter().sum::<u64>() as f64 / elapsed)
+    }
+
+    /// The latest sample and the newest one at least `window` older
+    ///
+    /// Falls back to the oldest sample while less than `window` was recorded.
+    fn span(&self, window: Duration) -> Option<(&Sample, &Sample)> {
+        let latest = self.samples.back()?;
+        let baseline = self
+            .samples
+            .iter()
+            .rev()
+            .find(|sample| latest.time.duration_since(sample.time) >= window)
+            .or_else(|| self.samples.front())?;
+        (latest.time > baseline.time).then_some((baseline, latest))
+    }
+
+    /// Rate over the last `window` in hash/s, `None` until two samples were recorded
+    pub fn rate(&self, window: Duration) -> Option<f64> {
+        let (baseline, latest) = self.span(window)?;
+        let hashes = latest.hashes.iter().sum::<u64>() - baseline.hashes.iter().sum::<u64>();
+        Some(hashes as f64 / latest.time.duration_since(baseline.time).as_secs_f64())
+    }
+
+    /// Rate of every thread over the last `window` in hash/s
+    pub fn thread_rates(&self, window: Duration) -> Vec<Option<f64>> {
+        match self.span(window) {
+            Some((baseline, latest)) => {
+                let seconds = latest.time.duration_since(baseline.time).as_secs_f64();
+                latest
+                    .hashes
+                    .iter()
+                    .zip(baseline.hashes.iter())
+                    .map(|(now, before)| Some((now - before) as f64 / seconds))
+                    .collect()
+            }
+            None => self
+                .samples
+                .back()
+                .map(|latest| vec![None; latest.hashes.len()])
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// Render a rate as a JSON number, or `null` if it isn't known
+fn json_number(value: Option<f64>) -> String {
+    match value {
+        Some(value) if value.is_finite() => format!("{:.2}", value),
+        _ => String::from("null"),
+    }
+}
+
+/// Render a JSON string
+pub fn json_string(value: &str) -> String {
+    let mut json = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+#[cfg(test)]
+mod stats_test {
+    use super::{json_string, Rates, Statistics};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn counters() {
+        let statistics = Statistics::new(2);
+        statistics.thread(0).count_key();
+        statistics.thread(0).count_hashes(100);
+        statistics.thread(1).count_key();
+        statistics.thread(1).count_key();
+        statistics.thread(1).count_hashes(50);
+        assert_eq!(statistics.get_hashes(), 150);
+        assert_eq!(statistics.get_keys(), 3);
+        assert_eq!(statistics.get_shuffles(), 147);
+    }
+
+    #[test]
+    fn rates() {
+        let start = Instant::now();
+        let mut rates = Rates::starting_at(start);
+        assert_eq!(rates.average(), None);
+        assert_eq!(rates.rate(Duration::from_secs(1)), None);
+
+        // A single sample at the start doesn't divide by zero
+        rates.record(start, vec![0, 0]);
+        assert_eq!(rates.average(), None);
+        assert_eq!(rates.rate(Duration::from_secs(1)), None);
+        assert_eq!(rates.thread_rates(Duration::from_secs(1)), vec![None, None]);
+
+        // Thread 0 does 100 hash/s, thread 1 does 300 hash/s for 30s, then stalls
+        for tick in 1..=60u64 {
+            let time = start + Duration::from_millis(500 * tick);
+            rates.record(time, vec![50 * tick, 150 * tick]);
+        }
+        for tick in 61..=80u64 {
+            let time = start + Duration::from_millis(500 * tick);
+            rates.record(time, vec![50 * tick, 9000]);
+        }
+        assert_eq!(rates.elapsed(), Duration::from_secs(40));
+        assert_eq!(rates.rate(Duration::from_secs(1)), Some(100.0));
+        assert_eq!(rates.rate(Duration::from_secs(10)), Some(100.0));
+        // Less than 60s recorded, the window covers everything
+        assert_eq!(rates.rate(Duration::from_secs(60)), Some(325.0));
+        assert_eq!(rates.average(), Some(325.0));
+        assert_eq!(
+            rates.thread_rates(Duration::from_secs(10)),
+            vec![Some(100.0), Some(0.0)]
+        );
+
+        // Old samples are dropped, the longest window stays covered
+        for tick in 81..=200u64 {
+            let time = start + Duration::from_millis(500 * tick);
+            rates.record(time, vec![50 * tick, 9000]);
+        }
+        assert!(rates.samples.len() <= 122);
+        assert_eq!(rates.rate(Duration::from_secs(60)), Some(100.0));
+        assert_eq!(rates.average(), Some((10000.0 + 9000.0) / 100.0));
+    }
+
+    #[test]
+    fn json() {
+        let statistics = Statistics::new(1);
+        statistics.thread(0).count_hashes(10);
+        let rates = Rates::new();
+        assert_eq!(
+            statistics.to_json(&rates),
+            "{\"elapsed\": 0.000, \"hashes\": 10, \"shuffles\": 10, \"keys\": 0, \
+             \"rates\": {\"average\": null, \"1s\": null, \"10s\": null, \"60s\": null}, \
+             \"threads\": [{\"hashes\": 10, \"keys\": 0, \"rate\": null}]}"
+        );
+        assert_eq!(json_string("a\"b\\c\n"), "\"a\\\"b\\\\c\\u000a\"");
+    }
+}
@@ -0,0 +1,264 @@
+//! # CPU topology
+//!
+//! Finds out which logical CPUs share a physical core or a NUMA node, so that workers can be
+//! spread over distinct cores and pinned to them. The topology is read from sysfs on Linux,
+//! elsewhere every CPU counts as a core of its own on a single node and pinning isn't available.
+
+use anyhow::{anyhow, Error};
+
+use std::collections::HashSet;
+#[cfg(target_os = "linux")]
+use std::fs;
+
+/// One logical CPU
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cpu {
+    /// Index used by the operating system
+    id: usize,
+    /// Socket of the CPU
+    package: i64,
+    /// Physical core within the socket
+    core: i64,
+    /// NUMA node of the CPU
+    node: usize,
+}
+
+/// Logical CPUs the process may run on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topology {
+    cpus: Vec<Cpu>,
+}
+
+impl Topology {
+    /// Read the topology of the CPUs the process is allowed to run on
+    pub fn detect() -> Self {
+        #[cfg(target_os = "linux")]
+        match Self::from_sysfs() {
+            Ok(topology) if !topology.cpus.is_empty() => return topology,
+            Ok(_) => log::info!("No usable CPU found in sysfs"),
+            Err(error) => log::info!("Failed to read the CPU topology: {}", error),
+        }
+        let count = std::thread::available_parallelism().map_or(1, |count| count.get());
+        Self {
+            cpus: (0..count)
+                .map(|id| Cpu {
+                    id,
+                    package: 0,
+                    core: id as i64,
+                    node: 0,
+                })
+                .collect(),
+        }
+    }
+
+    /// Read the topology from sysfs, limited to the affinity mask of the process
+    #[cfg(target_os = "linux")]
+    fn from_sysfs() -> Result<Self, Error> {
+        const CPU_ROOT: &str = "/sys/devices/system/cpu";
+        const NODE_ROOT: &str = "/sys/devices/system/node";
+        let read = |path: String| -> Result<String, Error> {
+            Ok(fs::read_to_string(&path)
+                .map_err(|error| anyhow!("{}: {}", path, error))?
+                .trim()
+                .to_string())
+        };
+        let allowed = allowed_cpus()?;
+        let mut nodes = Vec::new();
+        if let Ok(entries) = fs::read_dir(NODE_ROOT) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().to_string();
+                if let Some(node) = name.strip_prefix("node").and_then(|id| id.parse().ok()) {
+                    let cpus = parse_cpu_list(&read(format!("{}/{}/cpulist", NODE_ROOT, name))?)?;
+                    nodes.push((node, cpus));
+                }
+            }
+        }
+        let mut cpus = Vec::new();
+        for id in parse_cpu_list(&read(format!("{}/online", CPU_ROOT))?)? {
+            if !allowed.contains(&id) {
+                continue;
+            }
+            let topology = format!("{}/cpu{}/topology", CPU_ROOT, id);
+            cpus.push(Cpu {
+                id,
+                package: read(format!("{}/physical_package_id", topology))?.parse()?,
+                core: read(format!("{}/core_id", topology))?.parse()?,
+                node: nodes
+                    .iter()
+                    .find(|(_, cpus)| cpus.contains(&id))
+                    .map_or(0, |(node, _)| *node),
+            });
+        }
+        Ok(Self { cpus })
+    }
+
+    /// Operating system indices of the logical CPUs
+    pub fn ids(&self) -> Vec<usize> {
+        self.cpus.iter().map(|cpu| cpu.id).collect()
+    }
+
+    /// Keep only the first logical CPU of every physical core
+    pub fn physical_cores(self) -> Self {
+        let mut seen = HashSet::new();
+        Self {
+            cpus: self
+                .cpus
+                .into_iter()
+                .filter(|cpu| seen.insert((cpu.package, cpu.core)))
+                .collect(),
+        }
+    }
+
+    /// Keep only the logical CPUs of a NUMA node
+    pub fn numa_node(self, node: usize) -> Result<Self, Error> {
+        let cpus: Vec<Cpu> = self
+            .cpus
+            .into_iter()
+            .filter(|cpu| cpu.node == node)
+            .collect();
+        if cpus.is_empty() {
+            return Err(anyhow!("No usable CPU on NUMA node {}", node));
+        }
+        Ok(Self { cpus })
+    }
+
+    /// Order the CPUs so that consecutive workers land on distinct physical cores
+    ///
+    /// The first logical CPU of every core comes first, then the second one and so on.
+    pub fn spread(self) -> Self {
+        let mut rank: Vec<(usize, Cpu)> = Vec::with_capacity(self.cpus.len());
+        for cpu in self.cpus {
+            let siblings = rank
+                .iter()
+                .filter(|(_, other)| (other.package, other.core) == (cpu.package, cpu.core))
+                .count();
+            rank.push((siblings, cpu));
+        }
+        rank.sort_by_key(|(siblings, cpu)| (*siblings, cpu.id));
+        Self {
+            cpus: rank.into_iter().map(|(_, cpu)| cpu).collect(),
+        }
+    }
+}
+
+/// Parse a CPU list like `0-3,8,10-11`
+pub fn parse_cpu_list(list: &str) -> Result<Vec<usize>, Error> {
+    let mut cpus = Vec::new();
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        match range.split_once('-') {
+            Some((first, last)) => {
+                let (first, last): (usize, usize) = (first.parse()?, last.parse()?);
+                if first > last {
+                    return Err(anyhow!("Invalid CPU range \"{}\"", range));
+                }
+                cpus.extend(first..=last);
+            }
+            None => cpus.push(range.parse()?),
+        }
+    }
+    Ok(cpus)
+}
+
+/// Logical CPUs in the affinity mask of the process
+#[cfg(target_os = "linux")]
+fn allowed_cpus() -> Result<HashSet<usize>, Error> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+        Ok((0..libc::CPU_SETSIZE as usize)
+            .filter(|cpu| libc::CPU_ISSET(*cpu, &set))
+            .collect())
+    }
+}
+
+/// Restrict the calling thread to the logical CPUs `cpus`
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(cpus: &[usize]) -> Result<(), Error> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in cpus {
+            libc::CPU_SET(*cpu, &mut set);
+        }
+        if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+            return Err(std::io::Error::last_os_error().into());
+        }
+    }
+    Ok(())
+}
+
+/// Restrict the calling thread to the logical CPUs `cpus`
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_cpus: &[usize]) -> Result<(), Error> {
+    Err(anyhow!("Pinning threads is only supported on Linux"))
+}
+
+#[cfg(test)]
+mod topology_test {
+    use super::{parse_cpu_list, Cpu, Topology};
+
+    /// Two sockets with two cores of two hyperthreads each, numbered like Linux does
+    fn dual_socket() -> Topology {
+        let cpu = |id, package, core| Cpu {
+            id,
+            package,
+            core,
+            node: package as usize,
+        };
+        Topology {
+            cpus: vec![
+                cpu(0, 0, 0),
+                cpu(1, 0, 1),
+                cpu(2, 1, 0),
+                cpu(3, 1, 1),
+                cpu(4, 0, 0),
+                cpu(5, 0, 1),
+                cpu(6, 1, 0),
+                cpu(7, 1, 1),
+            ],
+        }
+    }
+
+    #[test]
+    fn cpu_list() {
+        assert_eq!(
+            parse_cpu_list("0-3,8,10-11\n").unwrap(),
+            [0, 1, 2, 3, 8, 10, 11]
+        );
+        assert_eq!(parse_cpu_list("5").unwrap(), [5]);
+        assert!(parse_cpu_list("").unwrap().is_empty());
+        assert!(parse_cpu_list("3-1").is_err());
+        assert!(parse_cpu_list("a").is_err());
+    }
+
+    #[test]
+    fn selection() {
+        assert_eq!(dual_socket().physical_cores().ids(), [0, 1, 2, 3]);
+        assert_eq!(dual_socket().numa_node(1).unwrap().ids(), [2, 3, 6, 7]);
+        assert_eq!(
+            dual_socket().numa_node(1).unwrap().physical_cores().ids(),
+            [2, 3]
+        );
+        assert!(dual_socket().numa_node(2).is_err());
+    }
+
+    #[test]
+    fn spread() {
+        let mut topology = dual_socket();
+        topology
+            .cpus
+            .sort_by_key(|cpu| (cpu.package, cpu.core, cpu.id));
+        assert_eq!(topology.ids(), [0, 4, 1, 5, 2, 6, 3, 7]);
+        assert_eq!(topology.spread().ids(), [0, 1, 2, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn detect() {
+        let topology = Topology::detect();
+        let ids = topology.ids();
+        assert!(!ids.is_empty());
+        super::pin_current_thread(&ids).unwrap();
+    }
+}